@@ -1,5 +1,36 @@
+use prost_types::field_descriptor_proto::Label;
+use prost_types::DescriptorProto;
 use std::io::Result;
 use std::{env, path::Path};
+
+/// serde for the messages, used by the json api
+/// well known types have no serde impl, their fields go through `crate::msg_serde`
+fn serde_attributes(config: &mut prost_build::Config, parent: &str, msg: &DescriptorProto) {
+    let path = format!("{}.{}", parent, msg.name());
+    for field in &msg.field {
+        let module = match field.type_name() {
+            ".google.protobuf.Timestamp" => "timestamp",
+            ".google.protobuf.Struct" => "structs",
+            _ => continue,
+        };
+        let wrap = match (field.label(), field.oneof_index) {
+            (Label::Repeated, _) => "vec",
+            (_, None) => "opt",
+            (_, Some(_)) => panic!("{}.{}: well known type in oneof", path, field.name()),
+        };
+        config.field_attribute(
+            format!("{}.{}", path, field.name()),
+            format!(
+                "#[serde(with = \"crate::msg_serde::{}::{}\")]",
+                module, wrap
+            ),
+        );
+    }
+    for nested in &msg.nested_type {
+        serde_attributes(config, &path, nested);
+    }
+}
+
 fn main() -> Result<()> {
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set");
     let proto_inc = Path::new(&manifest_dir).join("proto");
@@ -11,6 +42,26 @@ fn main() -> Result<()> {
         println!("cargo:rerun-if-changed={}", p.display());
     }
     println!("cargo:rerun-if-changed=build.rs");
-    prost_build::compile_protos(&protos, &[proto_inc])?;
+
+    let mut config = prost_build::Config::new();
+    let fds = config.load_fds(&protos, &[proto_inc])?;
+    config
+        .type_attribute(".qcm", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".qcm", "#[serde(rename_all = \"camelCase\")]")
+        .message_attribute(".qcm", "#[serde(default)]");
+    for file in fds.file.iter().filter(|f| f.package().starts_with("qcm")) {
+        let package = format!(".{}", file.package());
+        // hello lists the values of top level enums
+        for e in &file.enum_type {
+            config.enum_attribute(
+                format!("{}.{}", package, e.name()),
+                "#[derive(strum_macros::EnumIter)]",
+            );
+        }
+        for msg in &file.message_type {
+            serde_attributes(&mut config, &package, msg);
+        }
+    }
+    config.compile_fds(fds)?;
     Ok(())
 }
@@ -7,6 +7,15 @@ mod m20220101_000005_create_library_table;
 mod m20250824_000001_create_table;
 mod m20251129_145233_create_remote_mix;
 mod m20251214_145233_create_fts_table;
+mod m20261018_000001_add_item_fingerprint;
+mod m20261018_000002_add_song_source;
+mod m20261018_000003_add_song_replay_gain;
+mod m20261018_000004_create_song_loudness;
+mod m20261018_000005_create_outbox;
+mod m20261018_000006_add_provider_sync_schedule;
+mod m20261018_000007_add_library_sync_cursor;
+mod m20261018_000008_add_library_enabled;
+mod m20261018_000009_create_api_key;
 
 pub struct Migrator;
 pub use cache::CacheDBMigrator;
@@ -44,6 +53,15 @@ impl MigratorTrait for Migrator {
             Box::new(m20250824_000001_create_table::Migration),
             Box::new(m20251129_145233_create_remote_mix::Migration),
             Box::new(m20251214_145233_create_fts_table::Migration),
+            Box::new(m20261018_000001_add_item_fingerprint::Migration),
+            Box::new(m20261018_000002_add_song_source::Migration),
+            Box::new(m20261018_000003_add_song_replay_gain::Migration),
+            Box::new(m20261018_000004_create_song_loudness::Migration),
+            Box::new(m20261018_000005_create_outbox::Migration),
+            Box::new(m20261018_000006_add_provider_sync_schedule::Migration),
+            Box::new(m20261018_000007_add_library_sync_cursor::Migration),
+            Box::new(m20261018_000008_add_library_enabled::Migration),
+            Box::new(m20261018_000009_create_api_key::Migration),
         ]
     }
 
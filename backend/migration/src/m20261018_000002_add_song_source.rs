@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::model::song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // sqlite only allows one column per alter
+        for col in [
+            ColumnDef::new(song::Column::SourceId).string().to_owned(),
+            ColumnDef::new(song::Column::StartOffset)
+                .big_integer()
+                .to_owned(),
+            ColumnDef::new(song::Column::EndOffset)
+                .big_integer()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(song::Entity)
+                        .add_column(col)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::model::song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for col in [
+            song::Column::TrackGain,
+            song::Column::TrackPeak,
+            song::Column::AlbumGain,
+            song::Column::AlbumPeak,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(song::Entity)
+                        .add_column(ColumnDef::new(col).double().to_owned())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
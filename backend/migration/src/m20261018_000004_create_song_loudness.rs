@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::model::{song, song_loudness};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(song_loudness::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(song_loudness::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(song_loudness::Column::Loudness)
+                            .double()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(song_loudness::Column::TruePeak)
+                            .double()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(song_loudness::Column::SourceKey)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(song_loudness::Column::UpdateAt)
+                            .big_integer()
+                            .default(Timestamp::now_expr())
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song_loudness-song")
+                            .from(song_loudness::Entity, song_loudness::Column::Id)
+                            .to(song::Entity, song::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
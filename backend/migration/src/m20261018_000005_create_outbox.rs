@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::model::{outbox, provider};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(outbox::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(outbox::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(outbox::Column::ProviderId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(outbox::Column::Kind).string().not_null())
+                    .col(ColumnDef::new(outbox::Column::Payload).json().not_null())
+                    .col(
+                        ColumnDef::new(outbox::Column::Attempts)
+                            .integer()
+                            .default(0)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(outbox::Column::NextAt)
+                            .big_integer()
+                            .default(Timestamp::now_expr())
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(outbox::Column::Error).string())
+                    .col(
+                        ColumnDef::new(outbox::Column::CreateAt)
+                            .big_integer()
+                            .default(Timestamp::now_expr())
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-outbox-provider")
+                            .from(outbox::Entity, outbox::Column::ProviderId)
+                            .to(provider::Entity, provider::Column::ProviderId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-outbox-next_at")
+                    .table(outbox::Entity)
+                    .col(outbox::Column::NextAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
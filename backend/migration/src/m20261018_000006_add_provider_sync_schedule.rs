@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::model::provider;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for col in [
+            ColumnDef::new(provider::Column::SyncInterval)
+                .big_integer()
+                .not_null()
+                .default(0)
+                .to_owned(),
+            ColumnDef::new(provider::Column::SyncIdleOnly)
+                .boolean()
+                .not_null()
+                .default(false)
+                .to_owned(),
+            ColumnDef::new(provider::Column::SyncQuietStart)
+                .integer()
+                .to_owned(),
+            ColumnDef::new(provider::Column::SyncQuietEnd)
+                .integer()
+                .to_owned(),
+            ColumnDef::new(provider::Column::LastSyncAt)
+                .big_integer()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(provider::Entity)
+                        .add_column(col)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::model::api_key;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(api_key::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(api_key::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(api_key::Column::Name).string().not_null())
+                    .col(ColumnDef::new(api_key::Column::KeyHash).string().not_null())
+                    .col(ColumnDef::new(api_key::Column::LastUsedAt).big_integer())
+                    .col(
+                        ColumnDef::new(api_key::Column::CreateAt)
+                            .big_integer()
+                            .default(Timestamp::now_expr())
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
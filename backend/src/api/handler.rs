@@ -1,16 +1,22 @@
 use super::process_http::process_http_get;
+use super::process_qcm::error_message;
 use super::process_ws::process_ws;
+use super::subsonic::process_subsonic;
 pub use super::process_ws::WsMessage;
 use crate::api::process_http::process_http_post;
+use crate::auth;
+use crate::bus::EventBus;
+use crate::listener::Peer;
 use crate::convert::*;
 use crate::global as bglobal;
 use crate::http::body_type::ResponseBody;
-use crate::msg::{self, QcmMessage, Rsp};
+use crate::msg::{self, QcmMessage};
+use crate::outbox::Outbox;
 use crate::reverse::ReverseEvent;
 use crate::task::TaskManagerOper;
 use crate::{
     error::ProcessError,
-    event::{self, EventSink, ServiceContext, BackendEvent},
+    event::{self, BackendEvent, EventSink, ServiceContext},
 };
 use futures_util::{SinkExt, StreamExt};
 use http_body_util::BodyExt;
@@ -20,9 +26,9 @@ use hyper_tungstenite::HyperWebsocket;
 use prost::{self, Message};
 use qcm_core::provider::Context;
 use qcm_core::Result;
-use scopeguard::guard;
 use sea_orm::DatabaseConnection;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc as async_mpsc;
 
 use super::process_event::{process_backend_event, process_event, ProcessContext};
@@ -47,33 +53,140 @@ impl EventSink for WsEventSink {
     }
 }
 
-pub async fn handle_request(
-    mut request: Request<Incoming>,
+/// Header or query parameter with the session id, for http requests of a websocket client
+pub const SESSION_KEY: &str = "x-qcm-session";
+
+/// The server context, with its event queues, outbox and schedule
+pub fn start_context(
     db: DatabaseConnection,
     cache_db: DatabaseConnection,
     oper: TaskManagerOper,
     reverse_ev: tokio::sync::mpsc::Sender<ReverseEvent>,
+) -> Arc<ServiceContext> {
+    let (ev_sender, mut ev_receiver) = async_mpsc::channel::<event::Event>(1024);
+    let (bk_ev_sender, mut bk_ev_receiver) = async_mpsc::channel::<event::BackendEvent>(1024);
+
+    let provider_context = Arc::new(Context::new(db, cache_db, ev_sender));
+    let ctx = Arc::new(ServiceContext {
+        outbox: Outbox::start(provider_context.clone(), bk_ev_sender.clone()),
+        provider_context,
+        backend_ev: bk_ev_sender,
+        oper,
+        reverse_ev,
+        bus: EventBus::new(),
+        session: None,
+    });
+
+    // event queue
+    tokio::spawn({
+        let ctx = ctx.clone();
+        async move {
+            while let Some(ev) = ev_receiver.recv().await {
+                match process_event(ev, ctx.clone()).await {
+                    Ok(true) => break,
+                    Err(err) => log::error!("{}", err),
+                    _ => (),
+                }
+            }
+            log::info!("Event channel recv end");
+        }
+    });
+
+    // backend event queue
+    tokio::spawn({
+        let ctx = ctx.clone();
+        let sink: Arc<dyn EventSink> = Arc::new(ctx.bus.clone());
+        async move {
+            let mut process_ctx = ProcessContext::new();
+            while let Some(ev) = bk_ev_receiver.recv().await {
+                match process_backend_event(ev, ctx.clone(), sink.clone(), &mut process_ctx).await {
+                    Ok(true) => break,
+                    Err(err) => log::error!("{}", err),
+                    _ => (),
+                }
+            }
+            log::info!("Backend event channel recv end");
+        }
+    });
+
+    crate::schedule::start(Arc::downgrade(&ctx));
+    ctx
+}
+
+/// Context of the session in the request, the server's otherwise.
+/// An ended session falls back too, its media urls keep working.
+fn request_context<B>(request: &Request<B>, server: &Arc<ServiceContext>) -> Arc<ServiceContext> {
+    let from_header = request
+        .headers()
+        .get(SESSION_KEY)
+        .and_then(|v| v.to_str().ok());
+    let from_query = || {
+        form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+            .find(|(k, _)| k == "session")
+            .map(|(_, v)| v.into_owned())
+    };
+    from_header
+        .map(str::to_string)
+        .or_else(from_query)
+        .and_then(|id| id.parse().ok())
+        .and_then(bglobal::session)
+        .map(|s| s.ctx.clone())
+        .unwrap_or_else(|| server.clone())
+}
+
+pub async fn handle_request(
+    mut request: Request<Incoming>,
+    peer: Peer,
+    server: Arc<ServiceContext>,
 ) -> Result<Response<ResponseBody>> {
+    // subsonic has its own login
+    if !request.uri().path().starts_with("/rest/")
+        && !auth::authorize(&server.provider_context.db, peer, &request).await?
+    {
+        log::warn!(target: "http", "unauthorized: {} {}", peer, request.uri().path());
+        let rsp = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(ResponseBody::Empty)
+            .unwrap();
+        return Ok(rsp);
+    }
+
     // if ws
     if hyper_tungstenite::is_upgrade_request(&request) {
-        let (response, websocket) = hyper_tungstenite::upgrade(&mut request, None)?;
+        let (mut response, websocket) = hyper_tungstenite::upgrade(&mut request, None)?;
+
+        let (ws_sender, ws_receiver) = async_mpsc::channel::<WsMessage>(256);
+        let sink: Arc<dyn EventSink> = Arc::new(WsEventSink {
+            tx: ws_sender.clone(),
+        });
+        let session = bglobal::reg_session(&server, sink);
+        response
+            .headers_mut()
+            .insert(SESSION_KEY, session.id.into());
 
         // spawn to handle ws connect
         tokio::spawn(async move {
-            if let Err(e) = handle_ws(websocket, db, cache_db, oper, reverse_ev).await {
+            let id = session.id;
+            if let Err(e) = handle_ws(websocket, session, ws_sender, ws_receiver).await {
                 log::error!("Error in websocket connection: {e}");
             }
+            if bglobal::unreg_session(id) == 0 && !bglobal::keep_alive() {
+                // the frontend is gone, close self
+                bglobal::shutdown();
+            }
         });
 
         // return upgrade rsp
         Ok(response.map(|b| ResponseBody::Boxed(b.map_err(|e| e.into()).boxed())))
     } else {
-        // TODO: support multiple backend
-        let ctx = bglobal::context(1).unwrap();
+        let ctx = request_context(&request, &server);
         log::debug!(target: "http", "{}", request.uri());
 
         use hyper::Method;
         let res = match *request.method() {
+            _ if request.uri().path().starts_with("/rest/") => {
+                process_subsonic(&ctx, request).await
+            }
             Method::GET => process_http_get(&ctx, request).await,
             Method::POST => process_http_post(&ctx, request).await,
             _ => {
@@ -108,35 +221,13 @@ pub async fn handle_request(
 
 async fn handle_ws(
     ws: HyperWebsocket,
-    db: DatabaseConnection,
-    cache_db: DatabaseConnection,
-    oper: TaskManagerOper,
-    reverse_ev: tokio::sync::mpsc::Sender<ReverseEvent>,
+    session: Arc<bglobal::Session>,
+    ws_sender: async_mpsc::Sender<WsMessage>,
+    mut ws_receiver: async_mpsc::Receiver<WsMessage>,
 ) -> Result<()> {
     let (mut ws_writer, ws_reader) = ws.await?.split();
-
-    let (ws_sender, mut ws_receiver) = async_mpsc::channel::<WsMessage>(256);
-    let (ev_sender, mut ev_receiver) = async_mpsc::channel::<event::Event>(1024);
-    let (bk_ev_sender, mut bk_ev_receiver) = async_mpsc::channel::<event::BackendEvent>(1024);
-
-    let sink: Arc<dyn EventSink> = Arc::new(WsEventSink { tx: ws_sender.clone() });
-
-    let ctx = Arc::new(ServiceContext {
-        provider_context: Arc::new(Context {
-            db,
-            cache_db,
-            ev_sender: ev_sender,
-        }),
-        backend_ev: bk_ev_sender,
-        oper,
-        reverse_ev,
-    });
-
-    // TODO: reg context by real port
-    bglobal::reg_context(1, ctx.clone());
-    let _guard = guard(1, |p| {
-        bglobal::unreg_context(p);
-    });
+    let ctx = session.ctx.clone();
+    let sink = session.sink.clone();
 
     // ws sender queue
     tokio::spawn({
@@ -150,39 +241,53 @@ async fn handle_ws(
         }
     });
 
-    // event queue
+    // server pushes
     tokio::spawn({
-        let ctx = ctx.clone();
+        let mut rx = ctx.bus.subscribe();
+        let sink = sink.clone();
+        let id = session.id;
         async move {
-            while let Some(ev) = ev_receiver.recv().await {
-                match process_event(ev, ctx.clone()).await {
-                    Ok(true) => break,
-                    Err(err) => log::error!("{}", err),
-                    _ => (),
+            loop {
+                let msg = match rx.recv().await {
+                    Ok(msg) => msg,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("session {} missed {} pushes", id, n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if sink.send_message(msg).await.is_err() {
+                    break;
                 }
             }
-            log::info!("Event channel recv end");
         }
     });
 
-    // backend event queue
+    // task status push
     tokio::spawn({
-        let ctx = ctx.clone();
+        let mut rx = ctx.oper.subscribe();
         let sink = sink.clone();
         async move {
-            let mut process_ctx = ProcessContext::new();
-            while let Some(ev) = bk_ev_receiver.recv().await {
-                match process_backend_event(ev, ctx.clone(), sink.clone(), &mut process_ctx).await {
-                    Ok(true) => break,
-                    Err(err) => log::error!("{}", err),
-                    _ => (),
+            loop {
+                let task = match rx.recv().await {
+                    Ok(task) => task,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let msg: QcmMessage = msg::TaskStatusMsg {
+                    task: Some(task.qcm_into()),
+                }
+                .qcm_into();
+                if sink.send_message(msg).await.is_err() {
+                    break;
                 }
             }
-            log::info!("Backend event channel recv end");
         }
     });
 
-    let _ = ctx.backend_ev.try_send(BackendEvent::Frist);
+    let _ = ctx.backend_ev.try_send(BackendEvent::Frist {
+        session: session.id,
+    });
 
     // receive from ws
     let mut reader = ws_reader;
@@ -198,18 +303,7 @@ async fn handle_ws(
         });
     }
 
-    log::info!("WebSocket connection closed");
-
-    // end event process
-    ctx.provider_context
-        .ev_sender
-        .send(event::Event::End)
-        .await
-        .unwrap();
-    ctx.backend_ev.send(event::BackendEvent::End).await.unwrap();
-
-    // Only support one client, close self
-    bglobal::shutdown();
+    log::info!("WebSocket connection closed: session {}", session.id);
     return Ok(());
 }
 
@@ -218,7 +312,6 @@ pub async fn handle_ws_message(
     ctx: Arc<ServiceContext>,
     ws_sender: async_mpsc::Sender<WsMessage>,
 ) -> Result<()> {
-    use msg::qcm_message::Payload;
     let mut id: Option<i32> = None;
 
     match process_ws(&ctx, &ws_sender, &msg, &mut id).await {
@@ -231,12 +324,7 @@ pub async fn handle_ws_message(
         Err(ProcessError::None) => {}
         Err(err) => {
             if let Some(id) = id {
-                let rsp: Rsp = err.qcm_into();
-                let msg = QcmMessage {
-                    id,
-                    r#type: msg::MessageType::Rsp.into(),
-                    payload: Some(Payload::Rsp(rsp)),
-                };
+                let msg = error_message(id, err);
                 log::warn!("send {}", msg.r#type);
                 let mut buf = Vec::new();
                 msg.encode(&mut buf)?;
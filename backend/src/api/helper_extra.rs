@@ -40,6 +40,34 @@ pub fn extra_insert_dynamic(extra: &mut prost_types::Struct, dy: &sqlm::dynamic:
     );
 }
 
+pub fn extra_insert_replay_gain(extra: &mut prost_types::Struct, song: &sqlm::song::Model) {
+    if song.track_gain.is_none() && song.album_gain.is_none() {
+        return;
+    }
+    let j = serde_json::json!({
+        "track_gain": song.track_gain,
+        "track_peak": song.track_peak,
+        "album_gain": song.album_gain,
+        "album_peak": song.album_peak,
+    });
+    extra.fields.insert(
+        "replay_gain".to_string(),
+        serde_json::to_string(&j).unwrap().into(),
+    );
+}
+
+pub fn extra_insert_loudness(extra: &mut prost_types::Struct, l: &sqlm::song_loudness::Model) {
+    let j = serde_json::json!({
+        "loudness": l.loudness,
+        "true_peak": l.true_peak,
+        "gain": crate::loudness::REFERENCE_LUFS - l.loudness,
+    });
+    extra.fields.insert(
+        "loudness".to_string(),
+        serde_json::to_string(&j).unwrap().into(),
+    );
+}
+
 pub async fn to_rsp_songs(
     db: &DatabaseConnection,
     songs: Vec<sqlm::song::Model>,
@@ -50,31 +78,45 @@ pub async fn to_rsp_songs(
         .await?;
 
     let dynamics = songs.load_one(sqlm::dynamic::Entity, db).await?;
+    let loudness = songs.load_one(sqlm::song_loudness::Entity, db).await?;
 
     let mut items = Vec::new();
     let mut extras = Vec::new();
     if let Some(album) = album {
-        let zip_iter = songs.into_iter().zip(artists).zip(dynamics);
-        for ((song, artists), dy) in zip_iter {
-            items.push(song.qcm_into());
+        let zip_iter = songs.into_iter().zip(artists).zip(dynamics).zip(loudness);
+        for (((song, artists), dy), loudness) in zip_iter {
             let mut extra = prost_types::Struct::default();
+            extra_insert_replay_gain(&mut extra, &song);
+            items.push(song.qcm_into());
             extra_insert_artists(&mut extra, &artists);
             if let Some(dy) = dy {
                 extra_insert_dynamic(&mut extra, &dy);
             }
+            if let Some(loudness) = loudness {
+                extra_insert_loudness(&mut extra, &loudness);
+            }
             extra_insert_album(&mut extra, &album);
             extras.push(extra);
         }
     } else {
         let albums = songs.load_one(sqlm::album::Entity, db).await?;
-        let zip_iter = songs.into_iter().zip(artists).zip(dynamics).zip(albums);
-        for (((song, artists), dy), album) in zip_iter {
-            items.push(song.qcm_into());
+        let zip_iter = songs
+            .into_iter()
+            .zip(artists)
+            .zip(dynamics)
+            .zip(albums)
+            .zip(loudness);
+        for ((((song, artists), dy), album), loudness) in zip_iter {
             let mut extra = prost_types::Struct::default();
+            extra_insert_replay_gain(&mut extra, &song);
+            items.push(song.qcm_into());
             extra_insert_artists(&mut extra, &artists);
             if let Some(dy) = dy {
                 extra_insert_dynamic(&mut extra, &dy);
             }
+            if let Some(loudness) = loudness {
+                extra_insert_loudness(&mut extra, &loudness);
+            }
             if let Some(album) = album {
                 extra_insert_album(&mut extra, &album);
             }
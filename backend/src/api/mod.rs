@@ -1,8 +1,10 @@
 mod process_qcm;
 mod process_event;
 mod process_http;
+mod process_json;
 mod process_ws;
 mod helper_extra;
 mod helper_sort;
 pub mod handler;
 pub mod pagination;
+pub mod subsonic;
@@ -1,22 +1,45 @@
 use crate::event::{EventSink, ServiceContext, BackendEvent, Event};
 use crate::msg::model::AuthInfo;
 use qcm_core::event::SyncCommit;
-use qcm_core::event::{Event as CoreEvent, SyncState};
+use qcm_core::event::{Event as CoreEvent, SyncPhase, SyncState};
 use qcm_core::model as sqlm;
-use qcm_core::provider::Provider;
+use qcm_core::provider::{Provider, SyncResult, SyncSince};
 use qcm_core::{self};
 use qcm_core::{global, Result};
-use sea_orm::EntityTrait;
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use qcm_core::db::values::Timestamp;
+use once_cell::sync::Lazy;
+use sea_orm::{
+    prelude::Expr, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect,
+    TransactionTrait,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
 use tokio::sync::oneshot;
 
 use crate::convert::*;
+use crate::outbox::MAX_ATTEMPTS;
+use crate::task::TaskKind;
 use crate::msg::{
     self, model::ProviderStatus, ProviderMetaStatusMsg, ProviderStatusMsg,
     QcmMessage,
 };
 
+/// a broken provider could warn for every item
+const MAX_SYNC_WARNINGS: usize = 100;
+
+/// Task id of the running sync of each provider.
+/// The watcher, the scheduler and clients can all ask for one at the same time
+static SYNCING: Lazy<Mutex<HashMap<i64, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Frees the provider in `SYNCING` when the sync task ends or is dropped
+struct SyncGuard(i64);
+
+impl Drop for SyncGuard {
+    fn drop(&mut self) {
+        SYNCING.lock().unwrap().remove(&self.0);
+    }
+}
+
 pub struct ProcessContext {
     sync_status: BTreeMap<i64, msg::model::ProviderSyncStatus>,
 }
@@ -31,52 +54,8 @@ impl ProcessContext {
 
 pub async fn process_event(ev: Event, ctx: Arc<ServiceContext>) -> Result<bool> {
     match ev {
-        Event::ProviderSync { id, oneshot } => {
-            if let Some(p) = global::provider(id) {
-                let name = p.name();
-                ctx.oper.spawn({
-                    let ctx = ctx.provider_context.clone();
-                    log::info!("spawn sync: {}", name);
-                    |tid| async move {
-                        log::info!("start sync: {}", name);
-                        if let Some(tx) = oneshot {
-                            let _ = tx.send(tid);
-                        }
-                        let id = p.id().unwrap();
-                        let _ = ctx
-                            .ev_sender
-                            .send(CoreEvent::SyncCommit {
-                                id,
-                                commit: SyncCommit::SetState(SyncState::Syncing),
-                            })
-                            .await;
-
-                        match p.sync(&ctx).await {
-                            Err(err) => {
-                                log::error!("{:?}", err);
-                                let _ = ctx
-                                    .ev_sender
-                                    .send(CoreEvent::SyncCommit {
-                                        id,
-                                        commit: SyncCommit::SetState(err.into()),
-                                    })
-                                    .await;
-                            }
-                            Ok(_) => {
-                                let _ = ctx
-                                    .ev_sender
-                                    .send(CoreEvent::SyncCommit {
-                                        id,
-                                        commit: SyncCommit::SetState(SyncState::Finished),
-                                    })
-                                    .await;
-                            }
-                        }
-                        log::info!("sync end: {}", name);
-                    }
-                });
-            }
-        }
+        Event::ProviderSync { id, oneshot } => spawn_sync(&ctx, id, false, oneshot),
+        Event::ProviderFullSync { id, oneshot } => spawn_sync(&ctx, id, true, oneshot),
         Event::SyncCommit { id, commit } => {
             let _ = ctx
                 .backend_ev
@@ -88,6 +67,128 @@ pub async fn process_event(ev: Event, ctx: Arc<ServiceContext>) -> Result<bool>
     return Ok(false);
 }
 
+/// `full` ignores the saved cursors.
+/// A sync asked for while one of the provider is running joins that one
+fn spawn_sync(
+    ctx: &Arc<ServiceContext>,
+    id: i64,
+    full: bool,
+    oneshot: Option<oneshot::Sender<i64>>,
+) {
+    if let Some(p) = global::provider(id) {
+        let name = p.name();
+        // held till the task id is in, so the guard of a fast task can't run before
+        let mut syncing = SYNCING.lock().unwrap();
+        if let Some(tid) = syncing.get(&id) {
+            log::info!("sync already running: {}", name);
+            if let Some(tx) = oneshot {
+                let _ = tx.send(*tid);
+            }
+            return;
+        }
+        let tid = ctx.oper.spawn(TaskKind::Sync, Some(id), {
+            let ctx = ctx.provider_context.clone();
+            log::info!("spawn sync: {}", name);
+            move |tid, cancel| {
+                let guard = SyncGuard(id);
+                cancel.scope(async move {
+                    let _guard = guard;
+                    log::info!("start sync: {}", name);
+                    if let Some(tx) = oneshot {
+                        let _ = tx.send(tid);
+                    }
+                    let id = p.id().unwrap();
+                    let _ = ctx
+                        .ev_sender
+                        .send(CoreEvent::SyncCommit {
+                            id,
+                            commit: SyncCommit::SetState(SyncState::Syncing),
+                        })
+                        .await;
+
+                    let since = match full {
+                        true => SyncSince::default(),
+                        false => load_sync_since(&ctx.db, id).await.unwrap_or_else(|e| {
+                            log::error!("{}", e);
+                            SyncSince::default()
+                        }),
+                    };
+                    match p.sync_since(&ctx, &since).await {
+                        Err(err) => {
+                            log::error!("{:?}", err);
+                            let _ = ctx
+                                .ev_sender
+                                .send(CoreEvent::SyncCommit {
+                                    id,
+                                    commit: SyncCommit::SetState(err.into()),
+                                })
+                                .await;
+                        }
+                        Ok(res) => {
+                            if let Err(e) = save_sync_result(&ctx.db, id, res).await {
+                                log::error!("{}", e);
+                            }
+                            let _ = ctx
+                                .ev_sender
+                                .send(CoreEvent::SyncCommit {
+                                    id,
+                                    commit: SyncCommit::SetState(SyncState::Finished),
+                                })
+                                .await;
+                        }
+                    }
+                    log::info!("sync end: {}", name);
+                })
+            }
+        });
+        syncing.insert(id, tid);
+    }
+}
+
+async fn load_sync_since(db: &DatabaseConnection, provider_id: i64) -> Result<SyncSince> {
+    let last_sync_at = sqlm::provider::Entity::find_by_id(provider_id)
+        .one(db)
+        .await?
+        .and_then(|p| p.last_sync_at);
+    let cursors = sqlm::library::Entity::find()
+        .select_only()
+        .column(sqlm::library::Column::NativeId)
+        .column(sqlm::library::Column::SyncCursor)
+        .filter(sqlm::library::Column::ProviderId.eq(provider_id))
+        .filter(sqlm::library::Column::SyncCursor.is_not_null())
+        .into_tuple::<(String, String)>()
+        .all(db)
+        .await?
+        .into_iter()
+        .collect();
+    Ok(SyncSince {
+        last_sync_at,
+        cursors,
+    })
+}
+
+async fn save_sync_result(db: &DatabaseConnection, provider_id: i64, res: SyncResult) -> Result<()> {
+    let txn = db.begin().await?;
+    sqlm::provider::Entity::update_many()
+        .col_expr(
+            sqlm::provider::Column::LastSyncAt,
+            Expr::val(Timestamp::now()).into(),
+        )
+        .filter(sqlm::provider::Column::ProviderId.eq(provider_id))
+        .exec(&txn)
+        .await?;
+    for (native_id, cursor) in res.cursors {
+        sqlm::library::Entity::update_many()
+            .col_expr(sqlm::library::Column::SyncCursor, Expr::val(cursor).into())
+            .filter(sqlm::library::Column::ProviderId.eq(provider_id))
+            .filter(sqlm::library::Column::NativeId.eq(native_id))
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
 pub async fn process_backend_event(
     ev: BackendEvent,
     ctx: Arc<ServiceContext>,
@@ -96,9 +197,13 @@ pub async fn process_backend_event(
 ) -> Result<bool> {
     let ev_sender = &ctx.provider_context.ev_sender;
     match ev {
-        BackendEvent::Frist => {
-            send_provider_meta_status(ctx.as_ref(), sink.as_ref()).await?;
-            let _ = send_provider_status(ctx.as_ref(), sink.as_ref(), &pctx.sync_status).await?;
+        BackendEvent::Frist { session } => {
+            let Some(session) = crate::global::session(session) else {
+                return Ok(false);
+            };
+            let sink = session.sink.as_ref();
+            send_provider_meta_status(ctx.as_ref(), sink).await?;
+            let _ = send_provider_status(ctx.as_ref(), sink, &pctx.sync_status).await?;
             // for p in providers {
             //     if let Some(id) = p.id() {
             //         ev_sender.send(Event::ProviderSync { id: id }).await?;
@@ -109,7 +214,7 @@ pub async fn process_backend_event(
             send_provider_status(ctx.as_ref(), sink.as_ref(), &pctx.sync_status).await?;
             let (tx, rx) = oneshot::channel::<i64>();
             ev_sender
-                .send(Event::ProviderSync {
+                .send(Event::ProviderFullSync {
                     id,
                     oneshot: Some(tx),
                 })
@@ -146,11 +251,19 @@ pub async fn process_backend_event(
                         let mut p = msg::model::ProviderSyncStatus::default();
                         merge_sync_status(&mut p, commit);
                         p.id = id;
-                        pctx.sync_status.insert(id, p);
+                        pctx.sync_status.insert(id, p.clone());
                         p
                     }
                 }
             };
+            if status.state == SyncState::Finished as i32 {
+                ctx.bus.all_changed();
+            }
+            if status.state == SyncState::Syncing as i32 {
+                let current = (status.album + status.artist + status.song) as i64;
+                let total = (status.album_total + status.artist_total + status.song_total) as i64;
+                ctx.oper.progress(TaskKind::Sync, id, current, total);
+            }
             let msg: QcmMessage = msg::ProviderSyncStatusMsg {
                 status: Some(status),
                 statuses: Vec::new(),
@@ -165,11 +278,30 @@ pub async fn process_backend_event(
 
 fn merge_sync_status(v: &mut msg::model::ProviderSyncStatus, m: SyncCommit) {
     match m {
-        SyncCommit::SetState(state) => {
+        // a new sync starts from scratch, the result of the last one is kept till then
+        SyncCommit::SetState(SyncState::Syncing) => {
             let id = v.id;
             *v = msg::model::ProviderSyncStatus::default();
             v.id = id;
+            v.state = SyncState::Syncing as i32;
+        }
+        SyncCommit::SetState(state) => {
             v.state = state as i32;
+            v.phase = SyncPhase::Unspecified as i32;
+        }
+        SyncCommit::SetPhase(phase) => {
+            v.phase = phase as i32;
+        }
+        SyncCommit::SetTotal(kind, n) => match kind {
+            SyncPhase::Albums => v.album_total = n,
+            SyncPhase::Artists => v.artist_total = n,
+            SyncPhase::Songs => v.song_total = n,
+            _ => {}
+        },
+        SyncCommit::Warn(w) => {
+            if v.warnings.len() < MAX_SYNC_WARNINGS {
+                v.warnings.push(w);
+            }
         }
         SyncCommit::AddAlbum(n) => {
             v.album += n;
@@ -216,6 +348,21 @@ async fn send_provider_status(
         let mut msg = ProviderStatusMsg::default();
 
         let libraries = sqlm::library::Entity::find().all(db).await?;
+        let outbox_lens: BTreeMap<i64, i64> = sqlm::outbox::Entity::find()
+            .select_only()
+            .column(sqlm::outbox::Column::ProviderId)
+            .column_as(sqlm::outbox::Column::Id.count(), "len")
+            .filter(sqlm::outbox::Column::Attempts.lt(MAX_ATTEMPTS))
+            .group_by(sqlm::outbox::Column::ProviderId)
+            .into_tuple::<(i64, i64)>()
+            .all(db)
+            .await?
+            .into_iter()
+            .collect();
+        let outbox_failed = sqlm::outbox::Entity::find()
+            .filter(sqlm::outbox::Column::Attempts.gte(MAX_ATTEMPTS))
+            .all(db)
+            .await?;
 
         msg.statuses = providers
             .iter()
@@ -224,6 +371,8 @@ async fn send_provider_status(
                 status.id = p.id().unwrap_or(-1);
                 status.name = p.name();
                 status.type_name = p.type_name().to_string();
+                status.capabilities = global::provider_meta(&status.type_name)
+                    .map(|m| m.capabilities().clone().qcm_into());
                 status.auth_info = Some(AuthInfo {
                     server_url: p.base_url(),
                     method: p.auth_method().qcm_into(),
@@ -234,6 +383,12 @@ async fn send_provider_status(
                         status.libraries.push(lib.clone().qcm_into());
                     }
                 }
+                status.outbox_len = outbox_lens.get(&status.id).copied().unwrap_or(0) as i32;
+                status.outbox_failed = outbox_failed
+                    .iter()
+                    .filter(|e| e.provider_id == status.id)
+                    .map(|e| e.clone().qcm_into())
+                    .collect();
                 if let Some(s) = sync_status.get(&status.id) {
                     status.sync_status = Some(s.clone());
                 }
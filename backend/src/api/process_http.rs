@@ -1,3 +1,4 @@
+use super::process_json::process_http_post_json;
 use super::process_qcm::process_qcm;
 use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::{Bytes, Incoming};
@@ -18,15 +19,19 @@ use std::sync::Arc;
 use crate::error::ProcessError;
 use crate::event::ServiceContext;
 use crate::http::body_type::ResponseBody;
-use crate::reverse::handler::{media_get_audio, media_get_image};
+use crate::reverse::handler::{media_get_audio, media_get_image, AudioSlice};
 
-const SECURE_MAX_SIZE: usize = 64 * 1024;
+pub(super) const SECURE_MAX_SIZE: usize = 64 * 1024;
 const HEADER_ICY: HeaderName = HeaderName::from_static("icy-metadata");
 
 pub async fn process_http_post(
     ctx: &Arc<ServiceContext>,
     req: Request<Incoming>,
 ) -> Result<Response<ResponseBody>, ProcessError> {
+    if let Some(message_type) = req.uri().path().strip_prefix("/api/") {
+        let message_type = message_type.trim_end_matches('/').to_string();
+        return process_http_post_json(ctx, &message_type, req).await;
+    }
     let mut in_id: Option<i32> = None;
     let data = Limited::new(req.into_body(), SECURE_MAX_SIZE)
         .collect()
@@ -56,7 +61,7 @@ fn filter_image_type(image_type: ImageType) -> Condition {
         .add(Expr::col((sqlm::image::Entity, sqlm::image::Column::Id)).is_null())
 }
 
-async fn process_http_get_image(
+pub(super) async fn process_http_get_image(
     ctx: &Arc<ServiceContext>,
     item_type: ItemType,
     image_type: ImageType,
@@ -202,6 +207,54 @@ async fn process_http_get_image(
     }
 }
 
+pub(super) async fn process_http_get_audio(
+    ctx: &Arc<ServiceContext>,
+    id: i64,
+    req_headers: &http::HeaderMap,
+) -> Result<Response<ResponseBody>, ProcessError> {
+    let db = &ctx.provider_context.db;
+    let (native_id, provider_id, source_id, start, end): (
+        String,
+        i64,
+        Option<String>,
+        Option<i64>,
+        Option<i64>,
+    ) = sqlm::song::Entity::find_by_id(id)
+        .inner_join(sqlm::item::Entity)
+        .select_only()
+        .column(sqlm::item::Column::NativeId)
+        .column(sqlm::item::Column::ProviderId)
+        .column(sqlm::song::Column::SourceId)
+        .column(sqlm::song::Column::StartOffset)
+        .column(sqlm::song::Column::EndOffset)
+        .into_tuple()
+        .one(db)
+        .await?
+        .ok_or(ProcessError::NoSuchSong(id.to_string()))?;
+    let slice = source_id.as_deref().map(|source_id| AudioSlice {
+        source_id,
+        start: start.unwrap_or(0),
+        end,
+    });
+
+    let mut headers = http::HeaderMap::new();
+
+    use reqwest::header;
+    req_headers
+        .iter()
+        .filter(|(k, _)| match **k {
+            header::ACCEPT => true,
+            header::RANGE => true,
+            header::CONNECTION => true,
+            _ => *k == &HEADER_ICY,
+        })
+        .for_each(|(k, v)| {
+            headers.insert(k, v.clone());
+        });
+
+    media_get_audio(ctx, id, provider_id, &native_id, slice, headers).await
+}
+
 pub async fn process_http_get(
     ctx: &Arc<ServiceContext>,
     req: Request<Incoming>,
@@ -213,7 +266,6 @@ pub async fn process_http_get(
             .parse()
             .map_err(|_| ProcessError::WrongId(id.to_string()));
     };
-    let db = &ctx.provider_context.db;
 
     match path_segments.as_slice() {
         ["image", item_type_str, id, image_type] => {
@@ -228,36 +280,7 @@ pub async fn process_http_get(
             match ItemType::from_str(&item_type)
                 .map_err(|_| ProcessError::NoSuchItemType(item_type.to_string()))?
             {
-                ItemType::Song => {
-                    let id = parse_id(id)?;
-                    let (native_id, provider_id): (String, i64) =
-                        sqlm::song::Entity::find_by_id(id)
-                            .inner_join(sqlm::item::Entity)
-                            .select_only()
-                            .column(sqlm::item::Column::NativeId)
-                            .column(sqlm::item::Column::ProviderId)
-                            .into_tuple()
-                            .one(db)
-                            .await?
-                            .ok_or(ProcessError::NoSuchSong(id.to_string()))?;
-
-                    let mut headers = http::HeaderMap::new();
-
-                    use reqwest::header;
-                    req.headers()
-                        .iter()
-                        .filter(|(k, _)| match **k {
-                            header::ACCEPT => true,
-                            header::RANGE => true,
-                            header::CONNECTION => true,
-                            _ => *k == &HEADER_ICY,
-                        })
-                        .for_each(|(k, v)| {
-                            headers.insert(k, v.clone());
-                        });
-
-                    media_get_audio(ctx, provider_id, &native_id, headers).await
-                }
+                ItemType::Song => process_http_get_audio(ctx, parse_id(id)?, req.headers()).await,
                 _ => Err(ProcessError::UnsupportedItemType(item_type.to_string())),
             }
         }
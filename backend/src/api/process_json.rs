@@ -0,0 +1,178 @@
+//! `POST /api/{message_type}`, the QcmMessage api with json bodies
+//!
+//! The body is the payload of the request type, the response is the payload of whatever
+//! `process_qcm` returns, errors are a `Rsp` with a matching http status
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Request, Response, StatusCode};
+use prost::Message;
+use qcm_core::anyhow;
+use serde_json::Value as Json;
+use std::sync::Arc;
+
+use super::process_http::SECURE_MAX_SIZE;
+use super::process_qcm::process_qcm;
+use crate::convert::QcmInto;
+use crate::error::ProcessError;
+use crate::event::ServiceContext;
+use crate::http::body_type::ResponseBody;
+use crate::msg::{self, qcm_message::Payload, MessageType, QcmMessage};
+
+/// `getAlbumsReq`, `GetAlbumsReq` and `get_albums_req` all work
+fn parse_message_type(name: &str) -> Option<MessageType> {
+    let mut upper = String::with_capacity(name.len() + 8);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 && !name.contains('_') {
+            upper.push('_');
+        }
+        upper.push(c.to_ascii_uppercase());
+    }
+    MessageType::from_str_name(&upper).filter(|t| *t != MessageType::Unknown)
+}
+
+/// payload key of the type, `GET_ALBUMS_REQ` -> `getAlbumsReq`
+fn payload_key(t: MessageType) -> String {
+    let mut key = String::new();
+    for (i, part) in t.as_str_name().split('_').enumerate() {
+        let part = part.to_ascii_lowercase();
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            match i {
+                0 => key.push(first),
+                _ => key.push(first.to_ascii_uppercase()),
+            }
+            key.extend(chars);
+        }
+    }
+    key
+}
+
+fn status_of(e: &ProcessError) -> StatusCode {
+    match e {
+        ProcessError::Decode(_)
+        | ProcessError::Json(_)
+        | ProcessError::UnexpectedPayload(_)
+        | ProcessError::MissingFields(_)
+        | ProcessError::WrongId(_)
+        | ProcessError::NoSuchItemType(_)
+        | ProcessError::NoSuchImageType(_)
+        | ProcessError::NoSuchSearchType(_)
+        | ProcessError::NoSuchProviderType(_)
+        | ProcessError::UnsupportedItemType(_) => StatusCode::BAD_REQUEST,
+        ProcessError::NotFound
+        | ProcessError::NoSuchLibrary(_)
+        | ProcessError::NoSuchProvider(_)
+        | ProcessError::NoSuchAlbum(_)
+        | ProcessError::NoSuchSong(_)
+        | ProcessError::NoSuchArtist(_)
+        | ProcessError::NoSuchMix(_) => StatusCode::NOT_FOUND,
+        ProcessError::UnsupportedMessageType(_)
+        | ProcessError::UnknownMessageType(_)
+        | ProcessError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+        ProcessError::ProviderRejected(_) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn json_response(status: StatusCode, value: &Json) -> Response<ResponseBody> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(ResponseBody::Boxed(
+            Full::new(Bytes::from(body)).map_err(|e| e.into()).boxed(),
+        ))
+        .unwrap()
+}
+
+fn to_payload(t: MessageType, body: Json) -> Result<Payload, ProcessError> {
+    let mut wrapped = serde_json::Map::new();
+    wrapped.insert(payload_key(t), body);
+    Ok(serde_json::from_value(Json::Object(wrapped))?)
+}
+
+async fn process(
+    ctx: &Arc<ServiceContext>,
+    mtype: MessageType,
+    req: Request<Incoming>,
+) -> Result<Json, ProcessError> {
+    let data = Limited::new(req.into_body(), SECURE_MAX_SIZE)
+        .collect()
+        .await
+        .map_err(|e| ProcessError::Internal(anyhow!("{}", e)))?
+        .to_bytes();
+    let body: Json = match data.iter().all(u8::is_ascii_whitespace) {
+        true => Json::Object(Default::default()),
+        false => serde_json::from_slice(&data)?,
+    };
+
+    let message = QcmMessage {
+        r#type: mtype.into(),
+        id: 0,
+        payload: Some(to_payload(mtype, body)?),
+    };
+    let mut in_id = None;
+    let rsp = process_qcm(ctx, &message.encode_to_vec(), &mut in_id).await?;
+
+    Ok(match rsp.payload.map(serde_json::to_value).transpose()? {
+        Some(Json::Object(o)) => o.into_iter().next().map(|(_, v)| v).unwrap_or_default(),
+        _ => Json::Object(Default::default()),
+    })
+}
+
+pub async fn process_http_post_json(
+    ctx: &Arc<ServiceContext>,
+    message_type: &str,
+    req: Request<Incoming>,
+) -> Result<Response<ResponseBody>, ProcessError> {
+    let Some(mtype) = parse_message_type(message_type) else {
+        return Err(ProcessError::NotFound);
+    };
+    Ok(match process(ctx, mtype, req).await {
+        Ok(value) => json_response(StatusCode::OK, &value),
+        Err(e) => {
+            log::warn!(target: "http", "{}: {}", mtype.as_str_name(), e);
+            let status = status_of(&e);
+            let rsp: msg::Rsp = e.qcm_into();
+            json_response(status, &serde_json::to_value(rsp)?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_type_names() {
+        for name in [
+            "getAlbumsReq",
+            "GetAlbumsReq",
+            "get_albums_req",
+            "GET_ALBUMS_REQ",
+        ] {
+            assert_eq!(parse_message_type(name), Some(MessageType::GetAlbumsReq));
+        }
+        assert_eq!(parse_message_type("unknown"), None);
+        assert_eq!(parse_message_type("nope"), None);
+        assert_eq!(payload_key(MessageType::GetAlbumsReq), "getAlbumsReq");
+    }
+
+    #[test]
+    fn test_payload() {
+        let body = serde_json::json!({ "libraryId": [1, 2], "page": 3 });
+        let Payload::GetAlbumsReq(req) = to_payload(MessageType::GetAlbumsReq, body).unwrap()
+        else {
+            panic!("Expected GetAlbumsReq");
+        };
+        assert_eq!(req.library_id, vec![1, 2]);
+        assert_eq!(req.page, 3);
+        assert!(to_payload(
+            MessageType::GetAlbumsReq,
+            serde_json::json!({ "page": "x" })
+        )
+        .is_err());
+    }
+}
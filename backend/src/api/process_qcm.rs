@@ -1,10 +1,12 @@
 use prost::{self, Message};
 use qcm_core::db::values::Timestamp;
-use qcm_core::model::type_enum::MixType;
-use qcm_core::provider::AuthResult;
+use qcm_core::db::sync::{allocate_items, sync_remote_mixes};
+use qcm_core::model::type_enum::{ItemType, MixType};
+use qcm_core::provider::{AuthResult, PlaybackAction};
 use qcm_core::{event::Event as CoreEvent, global, Result};
 use sea_orm::TransactionTrait;
 use std::sync::Arc;
+use strum::IntoEnumIterator;
 use tokio::sync::oneshot;
 
 use qcm_core::model::{self as sqlm};
@@ -22,24 +24,117 @@ use crate::convert::QcmInto;
 use crate::db::filter::SelectQcmMsgFilters;
 use crate::error::ProcessError;
 use crate::event::{ServiceContext, BackendEvent};
+use crate::outbox::OutboxOp;
 use crate::msg::{
     self, AuthProviderRsp, GetAlbumArtistsRsp, GetAlbumsRsp, GetArtistsRsp, GetProviderMetasRsp,
     GetSongsRsp, GetStorageInfoRsp, MessageType, QcmMessage, QrAuthUrlRsp, Rsp, SyncRsp, TestRsp,
 };
 use sea_orm::DatabaseConnection;
 
+/// Bumped when a message change breaks older clients
+pub const PROTOCOL_VERSION: i32 = 1;
+
+/// Every value of a generated top level enum
+fn enum_values<E: IntoEnumIterator + Into<i32>>() -> impl Iterator<Item = i32> {
+    E::iter().map(Into::into)
+}
+
+fn hello() -> msg::HelloRsp {
+    use msg::filter::FilterType;
+    use msg::model::{AlbumSort, ArtistSort, MixSort, SongSort};
+    msg::HelloRsp {
+        version: qcm_core::global::APP_VERSION.to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        message_types: enum_values::<MessageType>()
+            .filter(|v| {
+                MessageType::try_from(*v).is_ok_and(|t| t.as_str_name().ends_with("_REQ"))
+            })
+            .collect(),
+        filter_types: enum_values::<FilterType>()
+            .filter(|v| *v != FilterType::Unspecified as i32)
+            .collect(),
+        album_sorts: enum_values::<AlbumSort>().collect(),
+        artist_sorts: enum_values::<ArtistSort>().collect(),
+        song_sorts: enum_values::<SongSort>().collect(),
+        mix_sorts: enum_values::<MixSort>().collect(),
+        plugins: global::with_plugins(|plugins| {
+            plugins.values().map(|p| p.as_ref().qcm_into()).collect()
+        }),
+    }
+}
+
 pub async fn process_qcm(
     ctx: &Arc<ServiceContext>,
     data: &[u8],
     in_id: &mut Option<i32>,
 ) -> Result<QcmMessage, ProcessError> {
-    use msg::qcm_message::Payload;
     let message = QcmMessage::decode(data)?;
+    *in_id = Some(message.id);
+    process_message(ctx, &message).await
+}
+
+/// The `Rsp` a failed request `id` gets
+pub fn error_message(id: i32, err: ProcessError) -> QcmMessage {
+    let rsp: Rsp = err.qcm_into();
+    let mut msg: QcmMessage = rsp.qcm_into();
+    msg.id = id;
+    msg
+}
+
+/// Each sub request gets its own response, errors included, so one failure doesn't fail
+/// the batch
+async fn process_batch(
+    ctx: &Arc<ServiceContext>,
+    messages: &[QcmMessage],
+) -> Result<QcmMessage, ProcessError> {
+    let results = futures::future::join_all(messages.iter().map(|m| async move {
+        let res = match m.r#type() {
+            MessageType::BatchReq => Err(ProcessError::UnsupportedMessageType(m.r#type)),
+            _ => Box::pin(process_message(ctx, m)).await,
+        };
+        match res {
+            Ok(mut rsp) => {
+                rsp.id = m.id;
+                rsp
+            }
+            Err(err) => error_message(m.id, err),
+        }
+    }))
+    .await;
+    Ok(msg::BatchRsp { messages: results }.qcm_into())
+}
+
+pub async fn process_message(
+    ctx: &Arc<ServiceContext>,
+    message: &QcmMessage,
+) -> Result<QcmMessage, ProcessError> {
+    use msg::qcm_message::Payload;
     let mtype = message.r#type();
     let payload = &message.payload;
-    *in_id = Some(message.id);
 
     match mtype {
+        MessageType::HelloReq => {
+            if let Some(Payload::HelloReq(_)) = payload {
+                return Ok(hello().qcm_into());
+            }
+        }
+        MessageType::BatchReq => {
+            if let Some(Payload::BatchReq(req)) = payload {
+                return process_batch(ctx, &req.messages).await;
+            }
+        }
+        MessageType::CancelReq => {
+            if let Some(Payload::CancelReq(req)) = payload {
+                let session = ctx
+                    .session
+                    .and_then(crate::global::session)
+                    .ok_or(ProcessError::NotFound)?;
+                if !session.cancel(req.id) {
+                    return Err(ProcessError::NotFound);
+                }
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
         MessageType::GetProviderMetasReq => {
             let response = GetProviderMetasRsp {
                 metas: qcm_core::global::with_provider_metas(|metas| {
@@ -62,10 +157,37 @@ pub async fn process_qcm(
                     .backend_ev
                     .send(BackendEvent::DeleteProvider { id })
                     .await;
+                ctx.bus.all_changed();
                 let rsp = Rsp::default();
                 return Ok(rsp.qcm_into());
             }
         }
+        MessageType::UpdateLibraryReq => {
+            if let Some(Payload::UpdateLibraryReq(req)) = payload {
+                use sea_orm::Set;
+                let db = &ctx.provider_context.db;
+                let library = sqlm::library::Entity::find_by_id(req.library_id)
+                    .one(db)
+                    .await?
+                    .ok_or(ProcessError::NoSuchLibrary(req.library_id.to_string()))?;
+                if let Some(enabled) = req.enabled {
+                    let m = sqlm::library::ActiveModel {
+                        library_id: Set(library.library_id),
+                        enabled: Set(enabled),
+                        ..Default::default()
+                    };
+                    sqlm::library::Entity::update(m).exec(db).await?;
+                }
+                let _ = ctx
+                    .backend_ev
+                    .send(BackendEvent::UpdateProvider {
+                        id: library.provider_id,
+                    })
+                    .await;
+                ctx.bus.all_changed();
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
         MessageType::AuthProviderReq => {
             if let Some(Payload::AuthProviderReq(req)) = payload {
                 let provider = global::get_tmp_provider(&req.tmp_provider)
@@ -248,10 +370,14 @@ pub async fn process_qcm(
                     req.sort.try_into().unwrap_or(msg::model::AlbumSort::Title);
                 let sort_asc = req.sort_asc.qcm_into();
 
+                let library_ids = match req.library_id.is_empty() {
+                    true => crate::db::enabled_library_ids(&ctx.provider_context.db).await?,
+                    false => req.library_id.clone(),
+                };
                 let query = sqlm::album::Entity::find()
                     .inner_join(sqlm::item::Entity)
                     .left_join(sqlm::dynamic::Entity)
-                    .filter(sqlm::item::Column::LibraryId.is_in(req.library_id.clone()))
+                    .filter(sqlm::item::Column::LibraryId.is_in(library_ids))
                     .filter(sqlm::dynamic::Column::IsExternal.eq(false))
                     .qcm_filters(&req.filters, &req.filter_logics)
                     .order_by(album_sort_col(sort), sort_asc);
@@ -470,6 +596,72 @@ pub async fn process_qcm(
             if let Some(Payload::CreateMixReq(req)) = payload {
                 let db = &ctx.provider_context.db;
 
+                if let Some(provider_id) = req.provider_id {
+                    let provider = global::provider(provider_id)
+                        .ok_or(ProcessError::NoSuchProvider(provider_id.to_string()))?;
+                    let native_id = provider
+                        .create_mix(&ctx.provider_context, &req.name)
+                        .await
+                        .map_err(ProcessError::rejected)?;
+                    drop(provider);
+
+                    let db = db.begin().await?;
+                    let now = Timestamp::now();
+                    let item = sqlm::item::ActiveModel {
+                        native_id: sea_orm::Set(native_id.clone()),
+                        library_id: sea_orm::Set(None),
+                        provider_id: sea_orm::Set(provider_id),
+                        r#type: sea_orm::Set(ItemType::Mix),
+                        update_at: sea_orm::Set(now),
+                        last_sync_at: sea_orm::Set(now),
+                        ..Default::default()
+                    };
+                    let remote_id = allocate_items(&db, [item])
+                        .await?
+                        .first()
+                        .copied()
+                        .ok_or(ProcessError::NoSuchMix(native_id))?;
+                    let remote_mix = sqlm::remote_mix::Model {
+                        id: remote_id,
+                        name: req.name.clone(),
+                        description: None,
+                        track_count: 0,
+                        mix_type: "playlist".to_string(),
+                    };
+                    sync_remote_mixes(&db, vec![remote_mix], &[]).await?;
+
+                    let new_mix = sqlm::mix::ActiveModel {
+                        name: sea_orm::Set(req.name.clone()),
+                        track_count: sea_orm::Set(0),
+                        description: sea_orm::Set(String::new()),
+                        mix_type: sea_orm::Set(MixType::Link),
+                        remote_id: sea_orm::Set(Some(remote_id)),
+                        ..Default::default()
+                    };
+                    sqlm::mix::Entity::insert(new_mix)
+                        .on_conflict(
+                            sea_query::OnConflict::column(sqlm::mix::Column::RemoteId)
+                                .update_column(sqlm::mix::Column::MixType)
+                                .to_owned(),
+                        )
+                        .exec(db)
+                        .await?;
+                    let id: i64 = sqlm::mix::Entity::find()
+                        .select_only()
+                        .column(sqlm::mix::Column::Id)
+                        .filter(sqlm::mix::Column::RemoteId.eq(remote_id))
+                        .into_tuple()
+                        .one(db)
+                        .await?
+                        .ok_or(ProcessError::NoSuchMix(remote_id.to_string()))?;
+                    db.commit().await?;
+
+                    ctx.bus
+                        .items_changed(msg::model::ItemType::Mix, vec![id], false);
+                    let rsp = msg::CreateMixRsp { id };
+                    return Ok(rsp.qcm_into());
+                }
+
                 let new_mix = sqlm::mix::ActiveModel {
                     name: sea_orm::Set(req.name.clone()),
                     track_count: sea_orm::Set(0),
@@ -480,6 +672,11 @@ pub async fn process_qcm(
 
                 let res = sqlm::mix::Entity::insert(new_mix).exec(db).await?;
 
+                ctx.bus.items_changed(
+                    msg::model::ItemType::Mix,
+                    vec![res.last_insert_id],
+                    false,
+                );
                 let rsp = msg::CreateMixRsp {
                     id: res.last_insert_id,
                 };
@@ -489,6 +686,35 @@ pub async fn process_qcm(
         MessageType::DeleteMixReq => {
             if let Some(Payload::DeleteMixReq(req)) = payload {
                 let db = &ctx.provider_context.db;
+                if req.remote {
+                    // check every id before deleting anything upstream
+                    let mut items = Vec::new();
+                    let mut unlinked = Vec::new();
+                    for id in &req.ids {
+                        match qcm_core::db::sync::remote_mix_item(db, *id).await? {
+                            Some(item) => items.push(item),
+                            None => unlinked.push(id.to_string()),
+                        }
+                    }
+                    if !unlinked.is_empty() {
+                        return Err(ProcessError::ProviderRejected(format!(
+                            "mixes not linked to a remote playlist: {}",
+                            unlinked.join(", ")
+                        )));
+                    }
+                    for item in items {
+                        let op = OutboxOp::DeleteMix {
+                            native_id: item.native_id.clone(),
+                        };
+                        ctx.outbox
+                            .deliver(&ctx.provider_context, item.provider_id, op)
+                            .await
+                            .map_err(ProcessError::rejected)?;
+                        // cascades to remote_mix and mix
+                        sqlm::item::Entity::delete_by_id(item.id).exec(db).await?;
+                    }
+                }
+
                 sqlm::mix::Entity::delete_many()
                     .filter(sqlm::mix::Column::Id.is_in(req.ids.clone()))
                     .filter(sqlm::mix::Column::MixType.eq(MixType::Normal))
@@ -501,6 +727,8 @@ pub async fn process_qcm(
                     .filter(sqlm::mix::Column::MixType.eq(MixType::Link))
                     .exec(db)
                     .await?;
+                ctx.bus
+                    .items_changed(msg::model::ItemType::Mix, req.ids.clone(), true);
                 return Ok(Rsp::default().qcm_into());
             }
         }
@@ -512,24 +740,81 @@ pub async fn process_qcm(
                     .filter(sqlm::mix::Column::Id.is_in(req.ids.clone()))
                     .exec(db)
                     .await?;
+                ctx.bus
+                    .items_changed(msg::model::ItemType::Mix, req.ids.clone(), false);
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
+        MessageType::UpdateMixReq => {
+            if let Some(Payload::UpdateMixReq(req)) = payload {
+                let db = &ctx.provider_context.db;
+
+                if let Some(item) = qcm_core::db::sync::remote_mix_item(db, req.id).await? {
+                    let op = OutboxOp::RenameMix {
+                        native_id: item.native_id.clone(),
+                        name: req.name.clone(),
+                    };
+                    ctx.outbox
+                        .deliver(&ctx.provider_context, item.provider_id, op)
+                        .await
+                        .map_err(ProcessError::rejected)?;
+
+                    sqlm::remote_mix::Entity::update_many()
+                        .col_expr(sqlm::remote_mix::Column::Name, Expr::val(req.name.clone()).into())
+                        .filter(sqlm::remote_mix::Column::Id.eq(item.id))
+                        .exec(db)
+                        .await?;
+                }
+
+                sqlm::mix::Entity::update_many()
+                    .col_expr(sqlm::mix::Column::Name, Expr::val(req.name.clone()).into())
+                    .col_expr(sqlm::mix::Column::UpdateAt, Expr::val(Timestamp::now()).into())
+                    .filter(sqlm::mix::Column::Id.eq(req.id))
+                    .exec(db)
+                    .await?;
+                ctx.bus
+                    .items_changed(msg::model::ItemType::Mix, vec![req.id], false);
                 return Ok(Rsp::default().qcm_into());
             }
         }
         MessageType::MixManipulateReq => {
             if let Some(Payload::MixManipulateReq(req)) = payload {
+                let mut song_ids = match req.oper() {
+                    msg::model::MixManipulateOper::AddSongs
+                    | msg::model::MixManipulateOper::RemoveSongs => req.song_ids.clone(),
+                    msg::model::MixManipulateOper::AddAlbums => {
+                        sqlm::song::Entity::find()
+                            .select_only()
+                            .column(sqlm::song::Column::Id)
+                            .filter(sqlm::song::Column::AlbumId.is_in(req.album_ids.clone()))
+                            .into_tuple()
+                            .all(&ctx.provider_context.db)
+                            .await?
+                    }
+                    _ => {
+                        return Err(ProcessError::NotImplemented);
+                    }
+                };
+                if let Some(item) =
+                    qcm_core::db::sync::remote_mix_item(&ctx.provider_context.db, req.id).await?
+                {
+                    song_ids = push_mix_songs(ctx, &item, req.oper(), song_ids).await?;
+                }
+
                 let db = ctx.provider_context.db.begin().await?;
 
                 let mut rsp = msg::MixManipulateRsp::default();
                 match req.oper() {
-                    msg::model::MixManipulateOper::AddSongs => {
-                        let count = sqlm::mix::append_songs(&db, req.id, &req.song_ids).await?;
+                    msg::model::MixManipulateOper::AddSongs
+                    | msg::model::MixManipulateOper::AddAlbums => {
+                        let count = sqlm::mix::append_songs(&db, req.id, &song_ids).await?;
 
                         db.commit().await?;
                         rsp.count = count as i64;
                     }
                     msg::model::MixManipulateOper::RemoveSongs => {
                         let res = sqlm::rel_mix_song::Entity::delete_many()
-                            .filter(sqlm::rel_mix_song::Column::SongId.is_in(req.song_ids.clone()))
+                            .filter(sqlm::rel_mix_song::Column::SongId.is_in(song_ids))
                             .filter(sqlm::rel_mix_song::Column::MixId.eq(req.id))
                             .exec(&db)
                             .await?;
@@ -540,30 +825,19 @@ pub async fn process_qcm(
                                 sqlm::mix::Column::TrackCount,
                                 Expr::col(sqlm::mix::Column::TrackCount).sub(count).into(),
                             )
+                            .filter(sqlm::mix::Column::Id.eq(req.id))
                             .exec(&db)
                             .await?;
 
                         db.commit().await?;
                         rsp.count = count as i64;
                     }
-                    msg::model::MixManipulateOper::AddAlbums => {
-                        let ids: Vec<i64> = sqlm::song::Entity::find()
-                            .select_only()
-                            .column(sqlm::song::Column::Id)
-                            .filter(sqlm::song::Column::AlbumId.is_in(req.album_ids.clone()))
-                            .into_tuple()
-                            .all(&db)
-                            .await?;
-
-                        let count = sqlm::mix::append_songs(&db, req.id, &ids).await?;
-
-                        db.commit().await?;
-                        rsp.count = count as i64;
-                    }
                     _ => {
                         return Err(ProcessError::NotImplemented);
                     }
                 }
+                ctx.bus
+                    .items_changed(msg::model::ItemType::Mix, vec![req.id], false);
                 return Ok(rsp.qcm_into());
             }
         }
@@ -744,7 +1018,9 @@ pub async fn process_qcm(
                 let provider = global::provider(provider_id)
                     .ok_or(ProcessError::NoSuchProvider(provider_id.to_string()))?;
 
-                let subtitle = provider.subtitle(&native_id).await?;
+                let subtitle = provider
+                    .song_subtitle(&ctx.provider_context, &native_id)
+                    .await?;
                 let rsp = msg::GetSubtitleRsp {
                     subtitle: Some(subtitle.qcm_into()),
                 };
@@ -763,8 +1039,11 @@ pub async fn process_qcm(
                 let library_ids = if search_query.is_empty() {
                     String::new()
                 } else {
-                    req.library_id
-                        .iter()
+                    let ids = match req.library_id.is_empty() {
+                        true => crate::db::enabled_library_ids(db).await?,
+                        false => req.library_id.clone(),
+                    };
+                    ids.iter()
                         .map(|id| id.to_string())
                         .collect::<Vec<_>>()
                         .join(",")
@@ -869,6 +1148,96 @@ pub async fn process_qcm(
                 return Ok(rsp.qcm_into());
             }
         }
+        MessageType::RemoteSearchReq => {
+            if let Some(Payload::RemoteSearchReq(req)) = payload {
+                use sqlm::type_enum::ItemType;
+                let db = &ctx.provider_context.db;
+                let page_params = PageParams::new(req.page, req.page_size);
+
+                let mut types = Vec::new();
+                for search_type in &req.types {
+                    let search_type = msg::SearchType::try_from(*search_type)
+                        .map_err(|_| ProcessError::NoSuchSearchType(search_type.to_string()))?;
+                    types.push(match search_type {
+                        msg::SearchType::Album => ItemType::Album,
+                        msg::SearchType::Artist => ItemType::Artist,
+                        msg::SearchType::Song => ItemType::Song,
+                    });
+                }
+
+                let provider = global::provider(req.provider_id)
+                    .ok_or(ProcessError::NoSuchProvider(req.provider_id.to_string()))?;
+                let result = provider
+                    .search(
+                        &ctx.provider_context,
+                        &req.query,
+                        &types,
+                        req.page,
+                        req.page_size,
+                    )
+                    .await?;
+                drop(provider);
+
+                // models are already upserted by the provider, read back by id
+                let mut albums_rsp = None;
+                let mut artists_rsp = None;
+                let mut songs_rsp = None;
+                if types.contains(&ItemType::Album) {
+                    let ids: Vec<i64> = result.albums.iter().map(|a| a.id).collect();
+                    let mut albums = sqlm::album::Entity::find()
+                        .filter(sqlm::album::Column::Id.is_in(ids.clone()))
+                        .all(db)
+                        .await?;
+                    albums.sort_by_key(|a| ids.iter().position(|id| *id == a.id));
+                    let (items, extras) = to_rsp_albums(db, albums).await?;
+                    let total = result.album_total.max(0) as u64;
+                    albums_rsp = Some(GetAlbumsRsp {
+                        items,
+                        extras,
+                        total: total as i32,
+                        has_more: page_params.has_more(total),
+                    });
+                }
+                if types.contains(&ItemType::Artist) {
+                    let ids: Vec<i64> = result.artists.iter().map(|a| a.id).collect();
+                    let mut artists = sqlm::artist::Entity::find()
+                        .filter(sqlm::artist::Column::Id.is_in(ids.clone()))
+                        .all(db)
+                        .await?;
+                    artists.sort_by_key(|a| ids.iter().position(|id| *id == a.id));
+                    let total = result.artist_total.max(0) as u64;
+                    artists_rsp = Some(GetArtistsRsp {
+                        items: artists.into_iter().map(|a| a.qcm_into()).collect(),
+                        extras: Vec::new(),
+                        total: total as i32,
+                        has_more: page_params.has_more(total),
+                    });
+                }
+                if types.contains(&ItemType::Song) {
+                    let ids: Vec<i64> = result.songs.iter().map(|s| s.id).collect();
+                    let mut songs = sqlm::song::Entity::find()
+                        .filter(sqlm::song::Column::Id.is_in(ids.clone()))
+                        .all(db)
+                        .await?;
+                    songs.sort_by_key(|s| ids.iter().position(|id| *id == s.id));
+                    let (items, extras) = to_rsp_songs(db, songs, None).await?;
+                    let total = result.song_total.max(0) as u64;
+                    songs_rsp = Some(GetSongsRsp {
+                        items,
+                        extras,
+                        total: total as i32,
+                        has_more: page_params.has_more(total),
+                    });
+                }
+
+                let rsp = msg::SearchRsp {
+                    albums: albums_rsp,
+                    artists: artists_rsp,
+                    songs: songs_rsp,
+                };
+                return Ok(rsp.qcm_into());
+            }
+        }
         MessageType::SetFavoriteReq => {
             if let Some(Payload::SetFavoriteReq(req)) = payload {
                 let db = &ctx.provider_context.db;
@@ -889,12 +1258,16 @@ pub async fn process_qcm(
                         .ok_or(ProcessError::NotFound)?
                 };
 
-                let provider = global::provider(provider_id)
-                    .ok_or(ProcessError::NoSuchProvider(provider_id.to_string()))?;
-
-                provider
-                    .favorite(&ctx.provider_context, &native_id, item_type, req.value)
-                    .await?;
+                // queued when offline, the local state is set either way
+                let op = OutboxOp::Favorite {
+                    native_id,
+                    item_type,
+                    value: req.value,
+                };
+                ctx.outbox
+                    .deliver(&ctx.provider_context, provider_id, op)
+                    .await
+                    .map_err(ProcessError::rejected)?;
 
                 use sea_orm::Set;
 
@@ -920,6 +1293,7 @@ pub async fn process_qcm(
                     }
                     _ => {}
                 }
+                ctx.bus.items_changed(req.item_type(), vec![req.id], false);
                 return Ok(Rsp::default().qcm_into());
             }
         }
@@ -992,6 +1366,30 @@ pub async fn process_qcm(
                         }
                         _ => {}
                     }
+
+                    let action = match action {
+                        msg::model::PlaylogAction::Play => Some(PlaybackAction::Play),
+                        msg::model::PlaylogAction::Pause => Some(PlaybackAction::Pause),
+                        msg::model::PlaylogAction::Stop => Some(PlaybackAction::Stop),
+                        msg::model::PlaylogAction::Next => Some(PlaybackAction::Next),
+                        msg::model::PlaylogAction::Prev => Some(PlaybackAction::Prev),
+                        msg::model::PlaylogAction::Unspecified => None,
+                    };
+                    let scrobble = global::provider(*provider_id)
+                        .and_then(|p| global::provider_meta(&p.type_name()))
+                        .map(|m| m.capabilities().scrobble)
+                        .unwrap_or(false);
+                    if let (Some(action), true) = (action, scrobble) {
+                        let op = OutboxOp::Playback {
+                            native_id: native_id.clone(),
+                            action,
+                            position: req.position as i64,
+                            timestamp: req.timestamp,
+                        };
+                        ctx.outbox
+                            .push(&ctx.provider_context, *provider_id, op)
+                            .await?;
+                    }
                 }
 
                 let album_id: Option<i64> = sqlm::song::Entity::find_by_id(req.song_id)
@@ -1029,18 +1427,18 @@ pub async fn process_qcm(
                     )
                     .exec(&ctx.provider_context.db)
                     .await?;
+                return Ok(Rsp::default().qcm_into());
             }
         }
         MessageType::SyncReq => {
             if let Some(Payload::SyncReq(req)) = payload {
                 let (tx, rx) = oneshot::channel::<i64>();
-                ctx.provider_context
-                    .ev_sender
-                    .send(CoreEvent::ProviderSync {
-                        id: req.provider_id,
-                        oneshot: Some(tx),
-                    })
-                    .await?;
+                let (id, oneshot) = (req.provider_id, Some(tx));
+                let ev = match req.full {
+                    true => CoreEvent::ProviderFullSync { id, oneshot },
+                    false => CoreEvent::ProviderSync { id, oneshot },
+                };
+                ctx.provider_context.ev_sender.send(ev).await?;
                 let task_id = rx.await;
                 let rsp = SyncRsp {
                     handle: task_id.unwrap_or(-1),
@@ -1048,6 +1446,106 @@ pub async fn process_qcm(
                 return Ok(rsp.qcm_into());
             }
         }
+        MessageType::GetSyncScheduleReq => {
+            if let Some(Payload::GetSyncScheduleReq(req)) = payload {
+                let provider = sqlm::provider::Entity::find_by_id(req.provider_id)
+                    .one(&ctx.provider_context.db)
+                    .await?
+                    .ok_or(ProcessError::NoSuchProvider(req.provider_id.to_string()))?;
+                let rsp = msg::GetSyncScheduleRsp {
+                    schedule: Some(provider.qcm_into()),
+                };
+                return Ok(rsp.qcm_into());
+            }
+        }
+        MessageType::UpdateSyncScheduleReq => {
+            if let Some(Payload::UpdateSyncScheduleReq(req)) = payload {
+                use sea_orm::Set;
+                let schedule = req
+                    .schedule
+                    .as_ref()
+                    .ok_or(ProcessError::MissingFields("schedule".to_string()))?;
+                let minute = |m: Option<i32>| m.map(|m| m.rem_euclid(24 * 60));
+
+                let m = sqlm::provider::ActiveModel {
+                    provider_id: Set(req.provider_id),
+                    sync_interval: Set(schedule.interval.clamp(0, crate::schedule::MAX_INTERVAL)),
+                    sync_idle_only: Set(schedule.idle_only),
+                    sync_quiet_start: Set(minute(schedule.quiet_start)),
+                    sync_quiet_end: Set(minute(schedule.quiet_end)),
+                    ..Default::default()
+                };
+                let res = sqlm::provider::Entity::update(m)
+                    .exec(&ctx.provider_context.db)
+                    .await;
+                match res {
+                    Err(sea_orm::DbErr::RecordNotUpdated) => {
+                        return Err(ProcessError::NoSuchProvider(req.provider_id.to_string()));
+                    }
+                    res => {
+                        res?;
+                    }
+                }
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
+        MessageType::GetTasksReq => {
+            if let Some(Payload::GetTasksReq(_)) = payload {
+                let rsp = msg::GetTasksRsp {
+                    tasks: ctx
+                        .oper
+                        .tasks()
+                        .await
+                        .into_iter()
+                        .map(|t| t.qcm_into())
+                        .collect(),
+                };
+                return Ok(rsp.qcm_into());
+            }
+        }
+        MessageType::CancelTaskReq => {
+            if let Some(Payload::CancelTaskReq(req)) = payload {
+                if !ctx.oper.cancel(req.id).await {
+                    return Err(ProcessError::NotFound);
+                }
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
+        MessageType::GetApiKeysReq => {
+            if let Some(Payload::GetApiKeysReq(_)) = payload {
+                let keys = sqlm::api_key::Entity::find()
+                    .order_by_asc(sqlm::api_key::Column::Id)
+                    .all(&ctx.provider_context.db)
+                    .await?;
+                let rsp = msg::GetApiKeysRsp {
+                    keys: keys.into_iter().map(|k| k.qcm_into()).collect(),
+                };
+                return Ok(rsp.qcm_into());
+            }
+        }
+        MessageType::CreateApiKeyReq => {
+            if let Some(Payload::CreateApiKeyReq(req)) = payload {
+                let (key, token) =
+                    crate::auth::create(&ctx.provider_context.db, &req.name).await?;
+                let rsp = msg::CreateApiKeyRsp {
+                    token,
+                    key: Some(key.qcm_into()),
+                };
+                return Ok(rsp.qcm_into());
+            }
+        }
+        MessageType::DeleteApiKeyReq => {
+            if let Some(Payload::DeleteApiKeyReq(req)) = payload {
+                let res = sqlm::api_key::Entity::delete_by_id(req.id)
+                    .exec(&ctx.provider_context.db)
+                    .await?;
+                crate::auth::forget(req.id);
+                if res.rows_affected == 0 {
+                    return Err(ProcessError::NotFound);
+                }
+                return Ok(Rsp::default().qcm_into());
+            }
+        }
         MessageType::SyncItemReq => {
             if let Some(Payload::SyncItemReq(req)) = payload {
                 let db = &ctx.provider_context.db;
@@ -1217,3 +1715,71 @@ pub async fn process_qcm(
     }
     return Err(ProcessError::UnexpectedPayload(mtype.into()));
 }
+
+/// Push a song change of a linked mix upstream
+///
+/// Songs of other providers can't be in it, the change is rejected if there are any
+async fn push_mix_songs(
+    ctx: &ServiceContext,
+    item: &sqlm::item::Model,
+    oper: msg::model::MixManipulateOper,
+    song_ids: Vec<i64>,
+) -> Result<Vec<i64>, ProcessError> {
+    let songs: Vec<(i64, String)> = sqlm::item::Entity::find()
+        .select_only()
+        .column(sqlm::item::Column::Id)
+        .column(sqlm::item::Column::NativeId)
+        .filter(sqlm::item::Column::Id.is_in(song_ids.clone()))
+        .filter(sqlm::item::Column::ProviderId.eq(item.provider_id))
+        .into_tuple()
+        .all(&ctx.provider_context.db)
+        .await?;
+    // keep the requested order
+    let songs: Vec<(i64, String)> = song_ids
+        .iter()
+        .filter_map(|id| songs.iter().find(|(s, _)| s == id).cloned())
+        .collect();
+    if songs.len() != song_ids.len() {
+        return Err(ProcessError::ProviderRejected(
+            "songs of other providers can't be in a linked mix".to_string(),
+        ));
+    }
+    if songs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let native_id = item.native_id.clone();
+    let song_native_ids: Vec<String> = songs.iter().map(|(_, n)| n.clone()).collect();
+
+    let op = match oper {
+        msg::model::MixManipulateOper::RemoveSongs => OutboxOp::MixRemoveSongs {
+            native_id,
+            song_native_ids,
+        },
+        _ => OutboxOp::MixAddSongs {
+            native_id,
+            song_native_ids,
+        },
+    };
+    ctx.outbox
+        .deliver(&ctx.provider_context, item.provider_id, op)
+        .await
+        .map_err(ProcessError::rejected)?;
+    Ok(songs.into_iter().map(|(id, _)| id).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hello() {
+        let hello = hello();
+        assert!(hello.message_types.contains(&(MessageType::HelloReq as i32)));
+        assert!(hello.message_types.contains(&(MessageType::GetHomeBlockItemsReq as i32)));
+        assert!(!hello.message_types.contains(&(MessageType::HelloRsp as i32)));
+        assert!(!hello.filter_types.contains(&0));
+        assert!(hello
+            .album_sorts
+            .contains(&(msg::model::AlbumSort::Random as i32)));
+    }
+}
@@ -1,4 +1,5 @@
-use super::process_qcm::process_qcm;
+use super::process_qcm::process_message;
+use prost::Message;
 pub use hyper_tungstenite::tungstenite::Message as WsMessage;
 use qcm_core::Result;
 use std::sync::Arc;
@@ -18,9 +19,25 @@ pub async fn process_ws(
 ) -> Result<QcmMessage, ProcessError> {
     match msg {
         WsMessage::Binary(data) => {
-            let mut qcm_msg = process_qcm(ctx, data, in_id).await;
-            if let (Some(id), Ok(qcm_msg)) = (in_id, &mut qcm_msg) {
-                qcm_msg.id = *id;
+            let message = QcmMessage::decode(&data[..])?;
+            let id = message.id;
+            *in_id = Some(id);
+
+            // a cancelled request is answered with `Cancelled`, clients wait on every id
+            let session = ctx.session.and_then(crate::global::session);
+            let cancel = session.as_ref().map(|s| s.track(id));
+            let mut qcm_msg = match cancel {
+                Some(cancel) => tokio::select! {
+                    res = process_message(ctx, &message) => res,
+                    Ok(()) = cancel => Err(ProcessError::Cancelled),
+                },
+                None => process_message(ctx, &message).await,
+            };
+            if let Some(session) = session {
+                session.untrack(id);
+            }
+            if let Ok(qcm_msg) = &mut qcm_msg {
+                qcm_msg.id = id;
             };
             return qcm_msg;
         }
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::model as sqlm;
+use sea_orm::sea_query::{Expr, SimpleExpr};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, Order, QueryFilter, QueryOrder,
+    QuerySelect, Select,
+};
+use serde_json::{json, Value as Json};
+
+use super::response::SubsonicError;
+use super::{model, Params};
+use crate::event::ServiceContext;
+
+const MAX_LIST_SIZE: u64 = 500;
+
+/// `musicFolderId` or every enabled library
+async fn library_ids(db: &DatabaseConnection, params: &Params) -> Result<Vec<i64>, SubsonicError> {
+    match params.get("musicFolderId") {
+        Some(_) => Ok(vec![params.id("musicFolderId")?]),
+        None => Ok(crate::db::enabled_library_ids(db).await?),
+    }
+}
+
+fn page(
+    params: &Params,
+    size: &str,
+    offset: &str,
+    default: u64,
+) -> Result<(u64, u64), SubsonicError> {
+    Ok((
+        params.parse_or(size, default)?.min(MAX_LIST_SIZE),
+        params.parse_or(offset, 0)?,
+    ))
+}
+
+/// `name:` match in the fts table of `table`
+fn fts_match(table: &str, query: &str) -> SimpleExpr {
+    Expr::cust_with_values(
+        format!(
+            "{table}.id IN (SELECT rowid FROM {table}_fts WHERE {table}_fts MATCH ('name:' || qcm_query(?)))"
+        ),
+        [query],
+    )
+}
+
+fn index_name(artist: &sqlm::artist::Model) -> String {
+    artist
+        .sort_name
+        .as_deref()
+        .unwrap_or(&artist.name)
+        .chars()
+        .next()
+        .filter(|c| c.is_alphabetic())
+        .map(|c| c.to_uppercase().to_string())
+        .unwrap_or("#".to_string())
+}
+
+async fn album_counts(
+    db: &DatabaseConnection,
+    artist_ids: Option<&[i64]>,
+) -> Result<HashMap<i64, usize>, SubsonicError> {
+    let mut query = sqlm::rel_album_artist::Entity::find()
+        .select_only()
+        .column(sqlm::rel_album_artist::Column::ArtistId)
+        .column_as(sqlm::rel_album_artist::Column::AlbumId.count(), "count")
+        .group_by(sqlm::rel_album_artist::Column::ArtistId);
+    if let Some(ids) = artist_ids {
+        query = query.filter(sqlm::rel_album_artist::Column::ArtistId.is_in(ids.to_vec()));
+    }
+    let counts: Vec<(i64, i64)> = query.into_tuple().all(db).await?;
+    Ok(counts.into_iter().map(|(id, n)| (id, n as usize)).collect())
+}
+
+fn artists_in(library_ids: Vec<i64>) -> Select<sqlm::artist::Entity> {
+    sqlm::artist::Entity::find()
+        .inner_join(sqlm::item::Entity)
+        .filter(sqlm::item::Column::LibraryId.is_in(library_ids))
+}
+
+fn albums_in(library_ids: Vec<i64>) -> Select<sqlm::album::Entity> {
+    sqlm::album::Entity::find()
+        .inner_join(sqlm::item::Entity)
+        .left_join(sqlm::dynamic::Entity)
+        .filter(sqlm::item::Column::LibraryId.is_in(library_ids))
+        .filter(sqlm::dynamic::Column::IsExternal.eq(false))
+}
+
+fn songs_in(library_ids: Vec<i64>) -> Select<sqlm::song::Entity> {
+    sqlm::song::Entity::find()
+        .inner_join(sqlm::item::Entity)
+        .filter(sqlm::item::Column::LibraryId.is_in(library_ids))
+}
+
+async fn to_artists(
+    db: &DatabaseConnection,
+    artists: Vec<sqlm::artist::Model>,
+) -> Result<Vec<Json>, SubsonicError> {
+    let ids: Vec<i64> = artists.iter().map(|a| a.id).collect();
+    let counts = album_counts(db, Some(&ids)).await?;
+    let dynamics = model::dynamics(db, ids).await?;
+    Ok(artists
+        .iter()
+        .map(|a| {
+            let count = counts.get(&a.id).copied().unwrap_or(0);
+            model::artist(a, count, dynamics.get(&a.id))
+        })
+        .collect())
+}
+
+pub async fn get_music_folders(ctx: &Arc<ServiceContext>) -> Result<Json, SubsonicError> {
+    let libraries = sqlm::library::Entity::find()
+        .filter(sqlm::library::Column::Enabled.eq(true))
+        .all(&ctx.provider_context.db)
+        .await?;
+    let folders: Vec<_> = libraries
+        .iter()
+        .map(|l| json!({ "id": l.library_id, "name": l.name }))
+        .collect();
+    Ok(json!({ "musicFolders": { "musicFolder": folders } }))
+}
+
+/// Album artists, grouped by the first letter
+pub async fn get_artists(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let artists = artists_in(library_ids(db, params).await?)
+        .order_by_asc(sqlm::artist::Column::Name)
+        .all(db)
+        .await?;
+    let counts = album_counts(db, None).await?;
+    let artists: Vec<_> = artists
+        .into_iter()
+        .filter(|a| counts.contains_key(&a.id))
+        .collect();
+    let dynamics = model::dynamics(db, artists.iter().map(|a| a.id).collect()).await?;
+
+    let mut index: BTreeMap<String, Vec<Json>> = BTreeMap::new();
+    for a in &artists {
+        let count = counts.get(&a.id).copied().unwrap_or(0);
+        index
+            .entry(index_name(a))
+            .or_default()
+            .push(model::artist(a, count, dynamics.get(&a.id)));
+    }
+    let index: Vec<_> = index
+        .into_iter()
+        .map(|(name, artist)| json!({ "name": name, "artist": artist }))
+        .collect();
+    Ok(json!({ "artists": { "ignoredArticles": "", "index": index } }))
+}
+
+pub async fn get_artist(ctx: &Arc<ServiceContext>, params: &Params) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let id = params.id("id")?;
+    let artist = sqlm::artist::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(SubsonicError::NotFound)?;
+    let albums = artist
+        .find_related(sqlm::album::Entity)
+        .order_by_asc(sqlm::album::Column::PublishTime)
+        .all(db)
+        .await?;
+    let dynamics = model::dynamics(db, vec![id]).await?;
+
+    let mut artist = model::artist(&artist, albums.len(), dynamics.get(&id));
+    artist["album"] = Json::Array(model::albums(db, albums).await?);
+    Ok(json!({ "artist": artist }))
+}
+
+pub async fn get_album(ctx: &Arc<ServiceContext>, params: &Params) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let id = params.id("id")?;
+    let album = sqlm::album::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(SubsonicError::NotFound)?;
+    let songs = sqlm::song::Entity::find()
+        .filter(sqlm::song::Column::AlbumId.eq(id))
+        .order_by_asc(sqlm::song::Column::DiscNumber)
+        .order_by_asc(sqlm::song::Column::TrackNumber)
+        .all(db)
+        .await?;
+
+    let mut album = model::albums(db, vec![album])
+        .await?
+        .pop()
+        .ok_or(SubsonicError::NotFound)?;
+    album["song"] = Json::Array(model::songs(db, songs).await?);
+    Ok(json!({ "album": album }))
+}
+
+fn year_start(year: i32) -> Timestamp {
+    chrono::NaiveDate::from_ymd_opt(year, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|d| Timestamp::from(d.and_utc()))
+        .unwrap_or_default()
+}
+
+pub async fn get_album_list2(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let (size, offset) = page(params, "size", "offset", 10)?;
+    let query = albums_in(library_ids(db, params).await?);
+
+    let query = match params.required("type")? {
+        "random" => query.order_by(Expr::cust("RANDOM()"), Order::Asc),
+        "newest" => query.order_by_desc(sqlm::album::Column::AddedAt),
+        "highest" | "frequent" => query
+            .filter(sqlm::dynamic::Column::PlayCount.gt(0))
+            .order_by_desc(sqlm::dynamic::Column::PlayCount),
+        "recent" => query
+            .filter(sqlm::dynamic::Column::LastPlayedAt.is_not_null())
+            .order_by_desc(sqlm::dynamic::Column::LastPlayedAt),
+        "starred" => query
+            .filter(sqlm::dynamic::Column::FavoriteAt.is_not_null())
+            .order_by_desc(sqlm::dynamic::Column::FavoriteAt),
+        "alphabeticalByName" => query.order_by_asc(sqlm::album::Column::Name),
+        "alphabeticalByArtist" => query.order_by(
+            Expr::cust(
+                "(SELECT MIN(artist.name) FROM rel_album_artist \
+                 INNER JOIN artist ON artist.id = rel_album_artist.artist_id \
+                 WHERE rel_album_artist.album_id = album.id)",
+            ),
+            Order::Asc,
+        ),
+        "byYear" => {
+            let from: i32 = params.required("fromYear")?.parse().unwrap_or(0);
+            let to: i32 = params.required("toYear")?.parse().unwrap_or(0);
+            let (low, high, order) = match from <= to {
+                true => (from, to, Order::Asc),
+                false => (to, from, Order::Desc),
+            };
+            query
+                .filter(sqlm::album::Column::PublishTime.gte(year_start(low)))
+                .filter(sqlm::album::Column::PublishTime.lt(year_start(high + 1)))
+                .order_by(sqlm::album::Column::PublishTime, order)
+        }
+        // albums have no genres here
+        "byGenre" => return Ok(json!({ "albumList2": { "album": [] } })),
+        t => return Err(SubsonicError::Generic(format!("Unknown list type: {}", t))),
+    };
+
+    let albums = query.limit(size).offset(offset).all(db).await?;
+    Ok(json!({ "albumList2": { "album": model::albums(db, albums).await? } }))
+}
+
+/// An empty query lists everything, some clients sync their library this way
+pub async fn search3(ctx: &Arc<ServiceContext>, params: &Params) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let library_ids = library_ids(db, params).await?;
+    let query = params.get("query").unwrap_or_default().trim_matches('"');
+    let query = query.trim_matches('*').trim();
+    let filter = |table: &str| match query.is_empty() {
+        true => Expr::value(true),
+        false => fts_match(table, query),
+    };
+
+    let (size, offset) = page(params, "artistCount", "artistOffset", 20)?;
+    let artists = artists_in(library_ids.clone())
+        .filter(filter("artist"))
+        .order_by_asc(sqlm::artist::Column::Name)
+        .limit(size)
+        .offset(offset)
+        .all(db)
+        .await?;
+
+    let (size, offset) = page(params, "albumCount", "albumOffset", 20)?;
+    let albums = albums_in(library_ids.clone())
+        .filter(filter("album"))
+        .order_by_asc(sqlm::album::Column::Name)
+        .limit(size)
+        .offset(offset)
+        .all(db)
+        .await?;
+
+    let (size, offset) = page(params, "songCount", "songOffset", 20)?;
+    let songs = songs_in(library_ids)
+        .filter(filter("song"))
+        .order_by_asc(sqlm::song::Column::Id)
+        .limit(size)
+        .offset(offset)
+        .all(db)
+        .await?;
+
+    Ok(json!({
+        "searchResult3": {
+            "artist": to_artists(db, artists).await?,
+            "album": model::albums(db, albums).await?,
+            "song": model::songs(db, songs).await?,
+        }
+    }))
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use hyper::{HeaderMap, Response};
+use qcm_core::db::values::Timestamp;
+use qcm_core::global;
+use qcm_core::model as sqlm;
+use qcm_core::model::type_enum::{ImageType, ItemType};
+use sea_orm::EntityTrait;
+use serde_json::{json, Value as Json};
+
+use super::model::{COVER_ALBUM, COVER_ARTIST, COVER_PLAYLIST};
+use super::response::SubsonicError;
+use super::{call, Params};
+use crate::api::process_http::{process_http_get_audio, process_http_get_image};
+use crate::convert::QcmInto;
+use crate::error::ProcessError;
+use crate::event::ServiceContext;
+use crate::http::body_type::ResponseBody;
+use crate::msg::{self, qcm_message::Payload};
+
+pub async fn stream(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+    headers: &HeaderMap,
+) -> Result<Response<ResponseBody>, SubsonicError> {
+    Ok(process_http_get_audio(ctx, params.id("id")?, headers).await?)
+}
+
+pub async fn get_cover_art(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Response<ResponseBody>, SubsonicError> {
+    let id = params.required("id")?;
+    let (item_type, id) = [
+        (COVER_ALBUM, ItemType::Album),
+        (COVER_ARTIST, ItemType::Artist),
+        (COVER_PLAYLIST, ItemType::Mix),
+    ]
+    .into_iter()
+    .find_map(|(prefix, t)| id.strip_prefix(prefix).map(|id| (t, id)))
+    .unwrap_or((ItemType::Song, id));
+    let id = id.parse().map_err(|_| SubsonicError::NotFound)?;
+    Ok(process_http_get_image(ctx, item_type, ImageType::Primary, id).await?)
+}
+
+pub async fn star(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+    value: bool,
+) -> Result<Json, SubsonicError> {
+    for (name, item_type) in [
+        ("id", msg::model::ItemType::Song),
+        ("albumId", msg::model::ItemType::Album),
+        ("artistId", msg::model::ItemType::Artist),
+    ] {
+        for id in params.ids(name)? {
+            let req = msg::SetFavoriteReq {
+                id,
+                item_type: item_type.into(),
+                value,
+            };
+            call(ctx, req.qcm_into()).await?;
+        }
+    }
+    Ok(Json::Null)
+}
+
+/// Now playing only marks the song as playing, clients send a submission after each listen,
+/// which is the play that is counted and reported
+pub async fn scrobble(ctx: &Arc<ServiceContext>, params: &Params) -> Result<Json, SubsonicError> {
+    let submission = params.parse_or("submission", true)?;
+    if !submission {
+        for song_id in params.ids("id")? {
+            let item = sqlm::item::Entity::find_by_id(song_id)
+                .one(&ctx.provider_context.db)
+                .await?
+                .ok_or(SubsonicError::NotFound)?;
+            global::set_playing(
+                item.provider_id,
+                global::PlayingInfo {
+                    song_id,
+                    native_id: item.native_id,
+                },
+            );
+        }
+        return Ok(Json::Null);
+    }
+    let times = params
+        .all("time")
+        .map(|t| {
+            t.parse::<i64>()
+                .map_err(|_| SubsonicError::Generic("Invalid time".into()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for (i, song_id) in params.ids("id")?.into_iter().enumerate() {
+        let req = msg::PlaylogReq {
+            song_id,
+            action: msg::model::PlaylogAction::Stop.into(),
+            timestamp: times
+                .get(i)
+                .copied()
+                .unwrap_or(Timestamp::now().as_millis()),
+            ..Default::default()
+        };
+        call(ctx, req.qcm_into()).await?;
+    }
+    Ok(Json::Null)
+}
+
+pub async fn get_lyrics_by_song_id(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let id = params.id("id")?;
+    let song = sqlm::song::Entity::find_by_id(id)
+        .one(&ctx.provider_context.db)
+        .await?
+        .ok_or(SubsonicError::NotFound)?;
+
+    let req = msg::GetSubtitleReq { song_id: id };
+    let items = match call(ctx, req.qcm_into()).await {
+        Ok(Some(Payload::GetSubtitleRsp(rsp))) => rsp.subtitle.unwrap_or_default().items,
+        Ok(_) | Err(ProcessError::NotFound) => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let lyrics = match items.is_empty() {
+        true => Vec::new(),
+        false => {
+            let lines: Vec<_> = items
+                .iter()
+                .map(|l| json!({ "start": l.start, "value": l.text }))
+                .collect();
+            vec![json!({
+                "displayTitle": song.name,
+                "lang": "und",
+                "synced": true,
+                "line": lines,
+            })]
+        }
+    };
+    Ok(json!({ "lyricsList": { "structuredLyrics": lyrics } }))
+}
@@ -0,0 +1,239 @@
+//! Subsonic / OpenSubsonic api under `/rest`, for existing clients
+//!
+//! Ids are the ids of the local tables, cover art ids carry the item type (`al-1`, `ar-1`,
+//! `pl-1`, song otherwise). Changes go through `process_qcm` like any other client.
+
+mod browse;
+mod media;
+mod model;
+mod playlist;
+mod response;
+
+use http_body_util::{BodyExt, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, Response};
+use md5::{Digest, Md5};
+use prost::Message;
+use serde_json::{json, Value as Json};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::process_http::SECURE_MAX_SIZE;
+use super::process_qcm::process_qcm;
+use crate::auth::eq_ct;
+use crate::error::ProcessError;
+use crate::event::ServiceContext;
+use crate::global;
+use crate::http::body_type::ResponseBody;
+use crate::msg::{qcm_message::Payload, QcmMessage};
+use response::{Format, SubsonicError};
+
+/// The single user subsonic clients log in as
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// Query and form parameters, names can repeat
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn parse(query: &str, form: &[u8]) -> Self {
+        Self(
+            form_urlencoded::parse(query.as_bytes())
+                .chain(form_urlencoded::parse(form))
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, SubsonicError> {
+        self.get(name)
+            .ok_or(SubsonicError::MissingParam(name.to_string()))
+    }
+
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, SubsonicError> {
+        match self.get(name) {
+            None | Some("") => Ok(default),
+            Some(v) => v
+                .parse()
+                .map_err(|_| SubsonicError::Generic(format!("Invalid parameter: {}", name))),
+        }
+    }
+
+    /// Ids are plain numbers, anything else can't exist
+    pub fn id(&self, name: &str) -> Result<i64, SubsonicError> {
+        self.required(name)?
+            .parse()
+            .map_err(|_| SubsonicError::NotFound)
+    }
+
+    pub fn ids(&self, name: &str) -> Result<Vec<i64>, SubsonicError> {
+        self.all(name)
+            .map(|id| id.parse().map_err(|_| SubsonicError::NotFound))
+            .collect()
+    }
+}
+
+fn password_matches(params: &Params, password: &str) -> Result<bool, SubsonicError> {
+    match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token), Some(salt), _) => {
+            let digest = hex::encode(Md5::digest(format!("{}{}", password, salt)));
+            Ok(eq_ct(
+                digest.as_bytes(),
+                token.to_ascii_lowercase().as_bytes(),
+            ))
+        }
+        (_, _, Some(p)) => {
+            let p = match p.strip_prefix("enc:") {
+                Some(h) => hex::decode(h).ok().and_then(|b| String::from_utf8(b).ok()),
+                None => Some(p.to_string()),
+            };
+            Ok(p.is_some_and(|p| eq_ct(p.as_bytes(), password.as_bytes())))
+        }
+        _ => Err(SubsonicError::MissingParam("t".to_string())),
+    }
+}
+
+fn authenticate(params: &Params) -> Result<(), SubsonicError> {
+    let credentials = global::subsonic_credentials().ok_or(SubsonicError::NotEnabled)?;
+    let user = params.required("u")?;
+    match user == credentials.user && password_matches(params, &credentials.password)? {
+        true => Ok(()),
+        false => Err(SubsonicError::WrongCredentials),
+    }
+}
+
+/// Run a request through `process_qcm`, same as a websocket client would
+pub(super) async fn call(
+    ctx: &Arc<ServiceContext>,
+    msg: QcmMessage,
+) -> Result<Option<Payload>, ProcessError> {
+    let mut in_id = None;
+    Ok(process_qcm(ctx, &msg.encode_to_vec(), &mut in_id)
+        .await?
+        .payload)
+}
+
+async fn dispatch(
+    ctx: &Arc<ServiceContext>,
+    endpoint: &str,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    match endpoint {
+        "ping" => Ok(Json::Null),
+        "getLicense" => Ok(json!({ "license": { "valid": true } })),
+        "getOpenSubsonicExtensions" => Ok(json!({
+            "openSubsonicExtensions": [{ "name": "songLyrics", "versions": [1] }]
+        })),
+        "getMusicFolders" => browse::get_music_folders(ctx).await,
+        "getArtists" => browse::get_artists(ctx, params).await,
+        "getArtist" => browse::get_artist(ctx, params).await,
+        "getAlbum" => browse::get_album(ctx, params).await,
+        "getAlbumList2" => browse::get_album_list2(ctx, params).await,
+        "search3" => browse::search3(ctx, params).await,
+        "getPlaylists" => playlist::get_playlists(ctx, params).await,
+        "getPlaylist" => playlist::get_playlist(ctx, params).await,
+        "createPlaylist" => playlist::create_playlist(ctx, params).await,
+        "updatePlaylist" => playlist::update_playlist(ctx, params).await,
+        "star" => media::star(ctx, params, true).await,
+        "unstar" => media::star(ctx, params, false).await,
+        "scrobble" => media::scrobble(ctx, params).await,
+        "getLyricsBySongId" => media::get_lyrics_by_song_id(ctx, params).await,
+        _ => Err(SubsonicError::Generic(format!(
+            "Unsupported endpoint: {}",
+            endpoint
+        ))),
+    }
+}
+
+pub async fn process_subsonic(
+    ctx: &Arc<ServiceContext>,
+    req: Request<Incoming>,
+) -> Result<Response<ResponseBody>, ProcessError> {
+    let endpoint = req
+        .uri()
+        .path()
+        .trim_start_matches("/rest/")
+        .trim_end_matches(".view")
+        .to_string();
+    let query = req.uri().query().unwrap_or_default().to_string();
+    let is_form = req.method() == Method::POST
+        && req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    let headers = req.headers().clone();
+    let form = match is_form {
+        true => Limited::new(req.into_body(), SECURE_MAX_SIZE)
+            .collect()
+            .await
+            .map_err(|e| ProcessError::Internal(qcm_core::anyhow!("{}", e)))?
+            .to_bytes(),
+        false => Bytes::new(),
+    };
+    let params = Params::parse(&query, &form);
+    let format = Format::new(params.get("f"));
+
+    let res = match authenticate(&params) {
+        Err(e) => Err(e),
+        Ok(()) => match endpoint.as_str() {
+            "stream" | "download" => media::stream(ctx, &params, &headers).await,
+            "getCoverArt" => media::get_cover_art(ctx, &params).await,
+            _ => dispatch(ctx, &endpoint, &params)
+                .await
+                .map(|body| response::ok(format, body)),
+        },
+    };
+    Ok(res.unwrap_or_else(|e| {
+        log::warn!(target: "subsonic", "{}: {}", endpoint, e);
+        response::error(format, &e)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_matches() {
+        // example from the subsonic api docs
+        let params = Params::parse("u=joe&t=26719a1196d2a940705a59634eb18eab&s=c19b2d", b"");
+        assert!(password_matches(&params, "sesame").unwrap());
+        assert!(!password_matches(&params, "other").unwrap());
+        let params = Params::parse("u=joe&t=26719A1196D2A940705A59634EB18EAB&s=c19b2d", b"");
+        assert!(password_matches(&params, "sesame").unwrap());
+
+        let params = Params::parse("u=joe&p=enc:736573616d65", b"");
+        assert!(password_matches(&params, "sesame").unwrap());
+        let params = Params::parse("u=joe", b"p=sesame");
+        assert!(password_matches(&params, "sesame").unwrap());
+        assert!(password_matches(&Params::parse("u=joe", b""), "sesame").is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        let params = Params::parse("id=1&id=2&size=", b"name=a%20b");
+        assert_eq!(params.ids("id").unwrap(), vec![1, 2]);
+        assert_eq!(params.parse_or("size", 10).unwrap(), 10);
+        assert_eq!(params.get("name"), Some("a b"));
+        assert!(params.required("missing").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Subsonic shapes of the local models, `Child`, `AlbumID3`, `ArtistID3` and `Playlist`
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, SecondsFormat, Utc};
+use qcm_core::db::values::Timestamp;
+use qcm_core::model as sqlm;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, LoaderTrait, QueryFilter};
+use serde_json::{json, Value as Json};
+
+pub const COVER_ALBUM: &str = "al-";
+pub const COVER_ARTIST: &str = "ar-";
+pub const COVER_PLAYLIST: &str = "pl-";
+
+pub fn iso(t: Timestamp) -> Option<String> {
+    DateTime::<Utc>::from_timestamp_millis(t.as_millis())
+        .map(|d| d.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+fn year(t: Option<Timestamp>) -> Option<i32> {
+    t.and_then(|t| DateTime::<Utc>::from_timestamp_millis(t.as_millis()))
+        .map(|d| d.year())
+}
+
+fn starred(dy: Option<&sqlm::dynamic::Model>) -> Option<String> {
+    dy.and_then(|dy| dy.favorite_at).and_then(iso)
+}
+
+fn display_artist(artists: &[sqlm::artist::Model]) -> Option<String> {
+    match artists.is_empty() {
+        true => None,
+        false => Some(
+            artists
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+    }
+}
+
+fn artist_refs(artists: &[sqlm::artist::Model]) -> Json {
+    Json::Array(
+        artists
+            .iter()
+            .map(|a| json!({ "id": a.id.to_string(), "name": a.name }))
+            .collect(),
+    )
+}
+
+pub fn artist(
+    artist: &sqlm::artist::Model,
+    album_count: usize,
+    dy: Option<&sqlm::dynamic::Model>,
+) -> Json {
+    json!({
+        "id": artist.id.to_string(),
+        "name": artist.name,
+        "sortName": artist.sort_name,
+        "coverArt": format!("{}{}", COVER_ARTIST, artist.id),
+        "albumCount": album_count,
+        "starred": starred(dy),
+    })
+}
+
+pub fn album(
+    album: &sqlm::album::Model,
+    artists: &[sqlm::artist::Model],
+    dy: Option<&sqlm::dynamic::Model>,
+) -> Json {
+    json!({
+        "id": album.id.to_string(),
+        "name": album.name,
+        "sortName": album.sort_name,
+        "artist": display_artist(artists),
+        "displayArtist": display_artist(artists),
+        "artistId": artists.first().map(|a| a.id.to_string()),
+        "artists": artist_refs(artists),
+        "coverArt": format!("{}{}", COVER_ALBUM, album.id),
+        "songCount": album.track_count,
+        "duration": album.duration / 1000,
+        "created": album.added_at.or(album.publish_time).and_then(iso),
+        "year": year(album.publish_time),
+        "starred": starred(dy),
+        "playCount": dy.map(|dy| dy.play_count),
+        "played": dy.and_then(|dy| dy.last_played_at).and_then(iso),
+    })
+}
+
+pub fn song(
+    song: &sqlm::song::Model,
+    album: Option<&sqlm::album::Model>,
+    artists: &[sqlm::artist::Model],
+    dy: Option<&sqlm::dynamic::Model>,
+) -> Json {
+    let replay_gain = match song.track_gain.is_some() || song.album_gain.is_some() {
+        true => json!({
+            "trackGain": song.track_gain,
+            "trackPeak": song.track_peak,
+            "albumGain": song.album_gain,
+            "albumPeak": song.album_peak,
+        }),
+        false => Json::Null,
+    };
+    json!({
+        "id": song.id.to_string(),
+        "parent": song.album_id.map(|id| id.to_string()),
+        "isDir": false,
+        "title": song.name,
+        "sortName": song.sort_name,
+        "album": album.map(|a| a.name.clone()),
+        "albumId": song.album_id.map(|id| id.to_string()),
+        "artist": display_artist(artists),
+        "displayArtist": display_artist(artists),
+        "artistId": artists.first().map(|a| a.id.to_string()),
+        "artists": artist_refs(artists),
+        "track": song.track_number,
+        "discNumber": song.disc_number,
+        "year": year(song.publish_time.or(album.and_then(|a| a.publish_time))),
+        "coverArt": match song.album_id {
+            Some(id) => format!("{}{}", COVER_ALBUM, id),
+            None => song.id.to_string(),
+        },
+        "duration": song.duration / 1000,
+        "type": "music",
+        "mediaType": "song",
+        "isVideo": false,
+        "created": song.added_at.and_then(iso),
+        "starred": starred(dy),
+        "playCount": dy.map(|dy| dy.play_count),
+        "played": dy.and_then(|dy| dy.last_played_at).and_then(iso),
+        "replayGain": replay_gain,
+    })
+}
+
+pub fn playlist(mix: &sqlm::mix::Model, owner: &str, duration: i64) -> Json {
+    json!({
+        "id": mix.id.to_string(),
+        "name": mix.name,
+        "comment": mix.description,
+        "owner": owner,
+        "public": false,
+        "songCount": mix.track_count,
+        "duration": duration / 1000,
+        "created": iso(mix.create_at),
+        "changed": iso(mix.update_at),
+        "coverArt": format!("{}{}", COVER_PLAYLIST, mix.id),
+    })
+}
+
+/// For items without a relation to `dynamic`, like artists
+pub async fn dynamics(
+    db: &DatabaseConnection,
+    ids: Vec<i64>,
+) -> Result<HashMap<i64, sqlm::dynamic::Model>, DbErr> {
+    Ok(sqlm::dynamic::Entity::find()
+        .filter(sqlm::dynamic::Column::Id.is_in(ids))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|dy| (dy.id, dy))
+        .collect())
+}
+
+pub async fn albums(
+    db: &DatabaseConnection,
+    albums: Vec<sqlm::album::Model>,
+) -> Result<Vec<Json>, DbErr> {
+    let artists = albums
+        .load_many_to_many(sqlm::artist::Entity, sqlm::rel_album_artist::Entity, db)
+        .await?;
+    let dynamics = albums.load_one(sqlm::dynamic::Entity, db).await?;
+    Ok(albums
+        .iter()
+        .zip(artists)
+        .zip(dynamics)
+        .map(|((a, artists), dy)| album(a, &artists, dy.as_ref()))
+        .collect())
+}
+
+pub async fn songs(
+    db: &DatabaseConnection,
+    songs: Vec<sqlm::song::Model>,
+) -> Result<Vec<Json>, DbErr> {
+    let artists = songs
+        .load_many_to_many(sqlm::artist::Entity, sqlm::rel_song_artist::Entity, db)
+        .await?;
+    let dynamics = songs.load_one(sqlm::dynamic::Entity, db).await?;
+    let albums = songs.load_one(sqlm::album::Entity, db).await?;
+    Ok(songs
+        .iter()
+        .zip(artists)
+        .zip(dynamics)
+        .zip(albums)
+        .map(|(((s, artists), dy), a)| song(s, a.as_ref(), &artists, dy.as_ref()))
+        .collect())
+}
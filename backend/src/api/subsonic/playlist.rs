@@ -0,0 +1,182 @@
+//! Playlists are the local mixes, in the order songs were added
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use qcm_core::model as sqlm;
+use qcm_core::model::type_enum::MixType;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use serde_json::{json, Value as Json};
+
+use super::response::SubsonicError;
+use super::{call, model, Params};
+use crate::convert::QcmInto;
+use crate::event::ServiceContext;
+use crate::msg::{self, model::MixManipulateOper, qcm_message::Payload};
+
+async fn find_mix(db: &DatabaseConnection, id: i64) -> Result<sqlm::mix::Model, SubsonicError> {
+    sqlm::mix::Entity::find_by_id(id)
+        .filter(sqlm::mix::Column::MixType.ne(MixType::Cache))
+        .one(db)
+        .await?
+        .ok_or(SubsonicError::NotFound)
+}
+
+async fn durations(
+    db: &DatabaseConnection,
+    mix_ids: Vec<i64>,
+) -> Result<HashMap<i64, i64>, SubsonicError> {
+    let durations: Vec<(i64, i64)> = sqlm::song::Entity::find()
+        .inner_join(sqlm::rel_mix_song::Entity)
+        .select_only()
+        .column(sqlm::rel_mix_song::Column::MixId)
+        .column_as(sqlm::song::Column::Duration.sum(), "duration")
+        .filter(sqlm::rel_mix_song::Column::MixId.is_in(mix_ids))
+        .group_by(sqlm::rel_mix_song::Column::MixId)
+        .into_tuple()
+        .all(db)
+        .await?;
+    Ok(durations.into_iter().collect())
+}
+
+async fn songs(db: &DatabaseConnection, id: i64) -> Result<Vec<sqlm::song::Model>, SubsonicError> {
+    Ok(sqlm::song::Entity::find()
+        .inner_join(sqlm::rel_mix_song::Entity)
+        .filter(sqlm::rel_mix_song::Column::MixId.eq(id))
+        .order_by_asc(sqlm::rel_mix_song::Column::OrderIdx)
+        .all(db)
+        .await?)
+}
+
+async fn manipulate(
+    ctx: &Arc<ServiceContext>,
+    id: i64,
+    oper: MixManipulateOper,
+    song_ids: Vec<i64>,
+) -> Result<(), SubsonicError> {
+    if song_ids.is_empty() {
+        return Ok(());
+    }
+    let req = msg::MixManipulateReq {
+        id,
+        oper: oper.into(),
+        song_ids,
+        album_ids: Vec::new(),
+    };
+    call(ctx, req.qcm_into()).await?;
+    Ok(())
+}
+
+fn owner(params: &Params) -> &str {
+    params.get("u").unwrap_or_default()
+}
+
+pub async fn get_playlists(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let mixes = sqlm::mix::Entity::find()
+        .filter(sqlm::mix::Column::MixType.ne(MixType::Cache))
+        .order_by_asc(sqlm::mix::Column::Name)
+        .all(db)
+        .await?;
+    let durations = durations(db, mixes.iter().map(|m| m.id).collect()).await?;
+    let playlists: Vec<_> = mixes
+        .iter()
+        .map(|m| model::playlist(m, owner(params), durations.get(&m.id).copied().unwrap_or(0)))
+        .collect();
+    Ok(json!({ "playlists": { "playlist": playlists } }))
+}
+
+pub async fn get_playlist(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    playlist(ctx, params, params.id("id")?).await
+}
+
+async fn playlist(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+    id: i64,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let mix = find_mix(db, id).await?;
+    let songs = songs(db, id).await?;
+    let duration = songs.iter().map(|s| s.duration).sum();
+
+    let mut playlist = model::playlist(&mix, owner(params), duration);
+    playlist["songCount"] = songs.len().into();
+    playlist["entry"] = Json::Array(model::songs(db, songs).await?);
+    Ok(json!({ "playlist": playlist }))
+}
+
+/// With `playlistId` the songs of that playlist are replaced
+pub async fn create_playlist(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let song_ids = params.ids("songId")?;
+    let id = match params.get("playlistId") {
+        Some(_) => {
+            let id = find_mix(db, params.id("playlistId")?).await?.id;
+            let current = songs(db, id).await?.iter().map(|s| s.id).collect();
+            manipulate(ctx, id, MixManipulateOper::RemoveSongs, current).await?;
+            id
+        }
+        None => {
+            let req = msg::CreateMixReq {
+                name: params.required("name")?.to_string(),
+                song_ids: Vec::new(),
+                provider_id: None,
+            };
+            match call(ctx, req.qcm_into()).await? {
+                Some(Payload::CreateMixRsp(rsp)) => rsp.id,
+                _ => return Err(SubsonicError::Generic("Failed to create playlist".into())),
+            }
+        }
+    };
+    manipulate(ctx, id, MixManipulateOper::AddSongs, song_ids).await?;
+    playlist(ctx, params, id).await
+}
+
+pub async fn update_playlist(
+    ctx: &Arc<ServiceContext>,
+    params: &Params,
+) -> Result<Json, SubsonicError> {
+    let db = &ctx.provider_context.db;
+    let id = find_mix(db, params.id("playlistId")?).await?.id;
+
+    if let Some(name) = params.get("name") {
+        let req = msg::UpdateMixReq {
+            id,
+            name: name.to_string(),
+        };
+        call(ctx, req.qcm_into()).await?;
+    }
+
+    let indexes = params
+        .all("songIndexToRemove")
+        .map(|i| i.parse::<usize>().map_err(|_| SubsonicError::NotFound))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !indexes.is_empty() {
+        let current = songs(db, id).await?;
+        let remove = indexes
+            .iter()
+            .filter_map(|i| current.get(*i))
+            .map(|s| s.id)
+            .collect();
+        manipulate(ctx, id, MixManipulateOper::RemoveSongs, remove).await?;
+    }
+
+    manipulate(
+        ctx,
+        id,
+        MixManipulateOper::AddSongs,
+        params.ids("songIdToAdd")?,
+    )
+    .await?;
+    Ok(Json::Null)
+}
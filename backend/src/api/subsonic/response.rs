@@ -0,0 +1,235 @@
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Response, StatusCode};
+use sea_orm::DbErr;
+use serde_json::{json, Value as Json};
+use thiserror::Error;
+
+use crate::error::ProcessError;
+use crate::http::body_type::ResponseBody;
+
+pub const API_VERSION: &str = "1.16.1";
+const XMLNS: &str = "http://subsonic.org/restapi";
+
+#[derive(Debug, Error)]
+pub enum SubsonicError {
+    #[error("{0}")]
+    Generic(String),
+    #[error("Required parameter is missing: {0}")]
+    MissingParam(String),
+    #[error("Wrong username or password")]
+    WrongCredentials,
+    #[error("Subsonic api is not enabled")]
+    NotEnabled,
+    #[error("The requested data was not found")]
+    NotFound,
+    #[error("{0}")]
+    Process(#[from] ProcessError),
+}
+
+impl From<DbErr> for SubsonicError {
+    fn from(e: DbErr) -> Self {
+        SubsonicError::Process(e.into())
+    }
+}
+
+impl SubsonicError {
+    pub fn code(&self) -> i32 {
+        match self {
+            SubsonicError::Generic(_) => 0,
+            SubsonicError::MissingParam(_) => 10,
+            SubsonicError::WrongCredentials => 40,
+            SubsonicError::NotEnabled => 50,
+            SubsonicError::NotFound => 70,
+            SubsonicError::Process(e) => match e {
+                ProcessError::NotFound
+                | ProcessError::WrongId(_)
+                | ProcessError::NoSuchLibrary(_)
+                | ProcessError::NoSuchAlbum(_)
+                | ProcessError::NoSuchSong(_)
+                | ProcessError::NoSuchArtist(_)
+                | ProcessError::NoSuchMix(_) => 70,
+                _ => 0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Xml,
+    Json,
+}
+
+impl Format {
+    pub fn new(f: Option<&str>) -> Self {
+        match f {
+            Some("json") => Format::Json,
+            _ => Format::Xml,
+        }
+    }
+}
+
+fn envelope(status: &str, body: Json) -> Json {
+    let mut rsp = json!({
+        "status": status,
+        "version": API_VERSION,
+        "type": "qcm",
+        "serverVersion": env!("CARGO_PKG_VERSION"),
+        "openSubsonic": true,
+    });
+    if let (Json::Object(rsp), Json::Object(body)) = (&mut rsp, body) {
+        rsp.extend(body);
+    }
+    rsp
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn scalar(v: &Json) -> Option<String> {
+    match v {
+        Json::Bool(b) => Some(b.to_string()),
+        Json::Number(n) => Some(n.to_string()),
+        Json::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// scalars are attributes, objects and arrays are child elements, `value` is the text
+fn write_xml(out: &mut String, name: &str, v: &Json, root: bool) {
+    match v {
+        Json::Null => {}
+        Json::Array(items) => {
+            for item in items {
+                write_xml(out, name, item, false);
+            }
+        }
+        Json::Object(o) => {
+            out.push('<');
+            out.push_str(name);
+            if root {
+                out.push_str(&format!(" xmlns=\"{}\"", XMLNS));
+            }
+            for (k, v) in o.iter().filter(|(k, _)| *k != "value") {
+                if let Some(s) = scalar(v) {
+                    out.push_str(&format!(" {}=\"{}\"", k, escape(&s)));
+                }
+            }
+            let text = o.get("value").and_then(scalar);
+            let children: Vec<_> = o
+                .iter()
+                .filter(|(_, v)| v.is_object() || v.is_array())
+                .collect();
+            if text.is_none() && children.is_empty() {
+                out.push_str("/>");
+                return;
+            }
+            out.push('>');
+            if let Some(text) = text {
+                out.push_str(&escape(&text));
+            }
+            for (k, v) in children {
+                write_xml(out, k, v, false);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        v => {
+            let text = scalar(v).unwrap_or_default();
+            out.push_str(&format!("<{name}>{}</{name}>", escape(&text)));
+        }
+    }
+}
+
+fn strip_nulls(v: &mut Json) {
+    match v {
+        Json::Object(o) => {
+            o.retain(|_, v| !v.is_null());
+            o.values_mut().for_each(strip_nulls);
+        }
+        Json::Array(a) => a.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+fn render(format: Format, mut rsp: Json) -> Response<ResponseBody> {
+    strip_nulls(&mut rsp);
+    let (content_type, body) = match format {
+        Format::Json => (
+            "application/json",
+            serde_json::to_vec(&json!({ "subsonic-response": rsp })).unwrap_or_default(),
+        ),
+        Format::Xml => {
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+            write_xml(&mut out, "subsonic-response", &rsp, true);
+            ("text/xml; charset=utf-8", out.into_bytes())
+        }
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .body(ResponseBody::Boxed(
+            Full::new(Bytes::from(body)).map_err(|e| e.into()).boxed(),
+        ))
+        .unwrap()
+}
+
+/// `body` is an object merged into the response, e.g. `{ "album": {..} }`
+pub fn ok(format: Format, body: Json) -> Response<ResponseBody> {
+    render(format, envelope("ok", body))
+}
+
+/// Errors are still http 200, as clients expect
+pub fn error(format: Format, e: &SubsonicError) -> Response<ResponseBody> {
+    let body = json!({
+        "error": {
+            "code": e.code(),
+            "message": e.to_string(),
+        }
+    });
+    render(format, envelope("failed", body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_xml() {
+        let mut rsp = envelope(
+            "ok",
+            json!({
+                "lyrics": {
+                    "line": [
+                        { "start": 0, "value": "a & b" },
+                        { "start": 1000, "value": "c" }
+                    ]
+                },
+                "musicFolders": { "musicFolder": [{ "id": 1, "name": "x", "missing": null }] }
+            }),
+        );
+        strip_nulls(&mut rsp);
+        let mut out = String::new();
+        write_xml(&mut out, "subsonic-response", &rsp, true);
+        assert!(out.starts_with("<subsonic-response xmlns=\"http://subsonic.org/restapi\""));
+        assert!(out.contains(" status=\"ok\""));
+        assert!(out.contains(
+            "<lyrics><line start=\"0\">a &amp; b</line><line start=\"1000\">c</line></lyrics>"
+        ));
+        assert!(out.contains("<musicFolders><musicFolder id=\"1\" name=\"x\"/></musicFolders>"));
+        assert!(out.ends_with("</subsonic-response>"));
+    }
+}
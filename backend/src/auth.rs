@@ -0,0 +1,276 @@
+//! Api keys and signed media urls, for clients not on this machine
+//!
+//! A token is `{id}.{secret}`, only the sha256 of the secret is stored. Requests carry it as
+//! `Authorization: Bearer <token>`, or as `access_token=<token>` in the query for websocket
+//! clients that can't set headers.
+//!
+//! Media urls can be signed instead, for players that only take an url:
+//! `?key={id}&exp={unix seconds}&sig={hex hmac_sha256(sign_key, "{path}\n{exp}")}`,
+//! with `sign_key = hmac_sha256(secret, "qcm-media-sign")`. The sign key is never stored, it is
+//! kept in memory once the token of the key is used, so signed urls of a key work after its
+//! client connected since the backend started.
+
+use hmac::{Hmac, Mac};
+use hyper::header::AUTHORIZATION;
+use hyper::Request;
+use once_cell::sync::Lazy;
+use qcm_core::db::values::Timestamp;
+use qcm_core::model::api_key;
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait, Set};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::listener::Peer;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `last_used_at` is only written this long after the stored one, media range requests come
+/// in bursts
+const LAST_USED_STEP_MS: i64 = 60 * 1000;
+
+const SIGN_LABEL: &[u8] = b"qcm-media-sign";
+
+/// Sign keys of the tokens used since start, by key id
+static SIGN_KEYS: Lazy<Mutex<HashMap<i64, Vec<u8>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+enum Credential<'a> {
+    Token { id: i64, secret: &'a str },
+    Signed { id: i64, exp: i64, sig: &'a str },
+}
+
+fn hash_secret(secret: &str) -> Vec<u8> {
+    Sha256::digest(secret.as_bytes()).to_vec()
+}
+
+/// Domain separated from the stored hash, a db copy can't sign urls
+fn sign_key(secret: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac takes any key size");
+    mac.update(SIGN_LABEL);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Remember the sign key of a key whose secret is known
+pub fn remember(id: i64, secret: &str) {
+    SIGN_KEYS.lock().unwrap().insert(id, sign_key(secret));
+}
+
+pub fn forget(id: i64) {
+    SIGN_KEYS.lock().unwrap().remove(&id);
+}
+
+/// Constant time, for secrets
+pub(crate) fn eq_ct(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn parse_token(token: &str) -> Option<Credential<'_>> {
+    let (id, secret) = token.split_once('.')?;
+    Some(Credential::Token {
+        id: id.parse().ok()?,
+        secret,
+    })
+}
+
+/// Returns the secret and its stored hash
+pub fn generate() -> (String, String) {
+    let secret = hex::encode(rand::random::<[u8; 32]>());
+    let hash = hex::encode(hash_secret(&secret));
+    (secret, hash)
+}
+
+pub fn token(id: i64, secret: &str) -> String {
+    format!("{}.{}", id, secret)
+}
+
+/// Store a new key, returns it with its token, the only time the secret is seen
+pub async fn create(db: &DatabaseConnection, name: &str) -> Result<(api_key::Model, String), DbErr> {
+    let (secret, key_hash) = generate();
+    let m = api_key::ActiveModel {
+        name: Set(name.to_string()),
+        key_hash: Set(key_hash),
+        ..Default::default()
+    };
+    let key = api_key::Entity::insert(m).exec_with_returning(db).await?;
+    remember(key.id, &secret);
+    let token = token(key.id, &secret);
+    Ok((key, token))
+}
+
+fn signature(sign_key: &[u8], path: &str, exp: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(sign_key).expect("hmac takes any key size");
+    mac.update(format!("{}\n{}", path, exp).as_bytes());
+    mac
+}
+
+fn credential<B>(req: &Request<B>) -> Option<Credential<'_>> {
+    if let Some(token) = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return parse_token(token.trim());
+    }
+
+    let query = req.uri().query().unwrap_or_default();
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    };
+    if let Some(token) = param("access_token") {
+        return parse_token(token);
+    }
+    match (param("key"), param("exp"), param("sig")) {
+        (Some(id), Some(exp), Some(sig)) => Some(Credential::Signed {
+            id: id.parse().ok()?,
+            exp: exp.parse().ok()?,
+            sig,
+        }),
+        _ => None,
+    }
+}
+
+fn verify(key: &api_key::Model, credential: &Credential, path: &str, now: i64) -> bool {
+    match credential {
+        Credential::Token { secret, .. } => {
+            let Ok(key_hash) = hex::decode(&key.key_hash) else {
+                return false;
+            };
+            let ok = eq_ct(&hash_secret(secret), &key_hash);
+            if ok {
+                remember(key.id, secret);
+            }
+            ok
+        }
+        Credential::Signed { exp, sig, .. } => {
+            let Ok(sig) = hex::decode(sig) else {
+                return false;
+            };
+            let Some(sign_key) = SIGN_KEYS.lock().unwrap().get(&key.id).cloned() else {
+                return false;
+            };
+            *exp >= now && signature(&sign_key, path, *exp).verify_slice(&sig).is_ok()
+        }
+    }
+}
+
+/// Unix socket peers, and loopback ones when trusted (see `main`), are let in, others need a
+/// valid key. Signed urls are only for media, websocket and posts need the token.
+pub async fn authorize<B>(
+    db: &DatabaseConnection,
+    peer: Peer,
+    req: &Request<B>,
+) -> Result<bool, DbErr> {
+    if peer.is_trusted(crate::global::trust_loopback()) {
+        return Ok(true);
+    }
+    let credential = match credential(req) {
+        Some(Credential::Signed { .. })
+            if req.method() != hyper::Method::GET || hyper_tungstenite::is_upgrade_request(req) =>
+        {
+            return Ok(false)
+        }
+        Some(c) => c,
+        None => return Ok(false),
+    };
+    let id = match credential {
+        Credential::Token { id, .. } | Credential::Signed { id, .. } => id,
+    };
+    let Some(key) = api_key::Entity::find_by_id(id).one(db).await? else {
+        return Ok(false);
+    };
+
+    let now = Timestamp::now();
+    if !verify(&key, &credential, req.uri().path(), now.as_millis() / 1000) {
+        return Ok(false);
+    }
+    let fresh = key
+        .last_used_at
+        .is_some_and(|t| now.as_millis() - t.as_millis() < LAST_USED_STEP_MS);
+    if fresh {
+        return Ok(true);
+    }
+    let m = api_key::ActiveModel {
+        id: Set(key.id),
+        last_used_at: Set(Some(now)),
+        ..Default::default()
+    };
+    api_key::Entity::update(m).exec(db).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(hash: &str) -> api_key::Model {
+        api_key::Model {
+            id: 3,
+            name: "test".to_string(),
+            key_hash: hash.to_string(),
+            last_used_at: None,
+            create_at: Timestamp::from_millis(0),
+        }
+    }
+
+    /// what a client does with its secret
+    fn sign(id: i64, secret: &str, path: &str, exp: i64) -> String {
+        let sig = hex::encode(signature(&sign_key(secret), path, exp).finalize().into_bytes());
+        format!("key={}&exp={}&sig={}", id, exp, sig)
+    }
+
+    fn req(uri: &str) -> Request<()> {
+        Request::get(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn test_token() {
+        let (secret, hash) = generate();
+        let key = key(&hash);
+
+        let header = Request::get("/ws")
+            .header(AUTHORIZATION, format!("Bearer {}", token(3, &secret)))
+            .body(())
+            .unwrap();
+        assert!(verify(&key, &credential(&header).unwrap(), "/ws", 0));
+
+        let query = req(&format!("/ws?access_token={}", token(3, &secret)));
+        assert!(verify(&key, &credential(&query).unwrap(), "/ws", 0));
+
+        let wrong = req(&format!("/ws?access_token={}", token(3, "0000")));
+        assert!(!verify(&key, &credential(&wrong).unwrap(), "/ws", 0));
+        assert!(credential(&req("/ws")).is_none());
+    }
+
+    #[test]
+    fn test_signed() {
+        let (secret, hash) = generate();
+        // not the id of test_token, the sign keys are shared
+        let key = api_key::Model { id: 4, ..key(&hash) };
+        let path = "/audio/song/1";
+        let signed = req(&format!("{}?{}", path, sign(4, &secret, path, 100)));
+        let credential = credential(&signed).unwrap();
+
+        forget(4);
+        // the token was not used yet
+        assert!(!verify(&key, &credential, path, 100));
+        remember(4, &secret);
+        assert!(verify(&key, &credential, path, 100));
+        assert!(!verify(&key, &credential, path, 101));
+        assert!(!verify(&key, &credential, "/audio/song/2", 100));
+
+        // the stored hash is not enough to sign
+        let key_hash = hex::decode(&hash).unwrap();
+        let sig = hex::encode(signature(&key_hash, path, 100).finalize().into_bytes());
+        let forged = Credential::Signed {
+            id: 4,
+            exp: 100,
+            sig: &sig,
+        };
+        assert!(!verify(&key, &forged, path, 100));
+    }
+}
@@ -0,0 +1,68 @@
+//! Server wide pushes, every session forwards them to its client
+
+use tokio::sync::broadcast;
+
+use crate::convert::QcmInto;
+use crate::event::EventSink;
+use crate::msg::{self, model::ItemType, QcmMessage};
+
+/// a client that lags this far behind misses pushes
+const CAPACITY: usize = 1024;
+
+/// what a sync or a provider change can touch
+const SYNCED_TYPES: [ItemType; 4] = [
+    ItemType::Album,
+    ItemType::Artist,
+    ItemType::Song,
+    ItemType::Mix,
+];
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<QcmMessage>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Self { tx }
+    }
+
+    pub fn publish(&self, msg: QcmMessage) {
+        // no session is fine
+        let _ = self.tx.send(msg);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QcmMessage> {
+        self.tx.subscribe()
+    }
+
+    pub fn items_changed(&self, item_type: ItemType, ids: Vec<i64>, removed: bool) {
+        self.publish(
+            msg::ItemsChangedMsg {
+                item_type: item_type.into(),
+                ids,
+                removed,
+            }
+            .qcm_into(),
+        );
+    }
+
+    /// Too many to list, e.g. after a sync
+    pub fn all_changed(&self) {
+        for item_type in SYNCED_TYPES {
+            self.items_changed(item_type, Vec::new(), false);
+        }
+    }
+}
+
+impl EventSink for EventBus {
+    fn send_message(
+        &self,
+        msg: QcmMessage,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = qcm_core::Result<()>> + Send + '_>>
+    {
+        self.publish(msg);
+        Box::pin(async { Ok(()) })
+    }
+}
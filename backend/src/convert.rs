@@ -187,6 +187,8 @@ impl QcmFrom<proto::Library> for core::model::library::Model {
             library_id: v.library_id,
             name: v.name,
             edit_time: v.edit_time.unwrap_or_default().qcm_into(),
+            sync_cursor: None,
+            enabled: v.enabled,
         }
     }
 }
@@ -199,6 +201,66 @@ impl QcmFrom<core::model::library::Model> for proto::Library {
             library_id: v.library_id,
             name: v.name,
             edit_time: Some(v.edit_time.qcm_into()),
+            enabled: v.enabled,
+        }
+    }
+}
+
+impl QcmFrom<sqlm::provider::Model> for proto::SyncSchedule {
+    fn qcm_from(v: sqlm::provider::Model) -> Self {
+        Self {
+            interval: v.sync_interval,
+            idle_only: v.sync_idle_only,
+            quiet_start: v.sync_quiet_start,
+            quiet_end: v.sync_quiet_end,
+            last_sync_at: v.last_sync_at.map(|t| t.qcm_into()),
+        }
+    }
+}
+
+impl QcmFrom<sqlm::api_key::Model> for proto::ApiKey {
+    fn qcm_from(v: sqlm::api_key::Model) -> Self {
+        Self {
+            id: v.id,
+            name: v.name,
+            create_at: Some(v.create_at.qcm_into()),
+            last_used_at: v.last_used_at.map(|t| t.qcm_into()),
+        }
+    }
+}
+
+impl QcmFrom<crate::task::TaskInfo> for proto::Task {
+    fn qcm_from(v: crate::task::TaskInfo) -> Self {
+        use crate::task::{TaskKind, TaskState};
+        Self {
+            id: v.id,
+            kind: match v.kind {
+                TaskKind::Sync => proto::TaskKind::Sync,
+            }
+            .into(),
+            provider_id: v.provider_id,
+            start_at: Some(v.start_at.qcm_into()),
+            current: v.current,
+            total: v.total,
+            state: match v.state {
+                TaskState::Running => proto::TaskState::Running,
+                TaskState::Cancelling => proto::TaskState::Cancelling,
+                TaskState::Finished => proto::TaskState::Finished,
+                TaskState::Cancelled => proto::TaskState::Cancelled,
+            }
+            .into(),
+        }
+    }
+}
+
+impl QcmFrom<sqlm::outbox::Model> for proto::OutboxEntry {
+    fn qcm_from(v: sqlm::outbox::Model) -> Self {
+        Self {
+            id: v.id,
+            kind: v.kind,
+            attempts: v.attempts,
+            error: v.error.unwrap_or_default(),
+            create_at: Some(v.create_at.qcm_into()),
         }
     }
 }
@@ -304,6 +366,13 @@ impl QcmFrom<proto::Song> for core::model::song::Model {
                     .collect(),
             ),
             added_at: None,
+            source_id: None,
+            start_offset: None,
+            end_offset: None,
+            track_gain: v.track_gain,
+            track_peak: v.track_peak,
+            album_gain: v.album_gain,
+            album_peak: v.album_peak,
         }
     }
 }
@@ -331,6 +400,10 @@ impl QcmFrom<core::model::song::Model> for proto::Song {
                         .collect()
                 })
                 .unwrap_or_default(),
+            track_gain: v.track_gain,
+            track_peak: v.track_peak,
+            album_gain: v.album_gain,
+            album_peak: v.album_peak,
         }
     }
 }
@@ -370,6 +443,7 @@ impl QcmFrom<core::model::mix::Model> for proto::Mix {
 
 impl QcmFrom<core::provider::ProviderMeta> for proto::ProviderMeta {
     fn qcm_from(v: core::provider::ProviderMeta) -> Self {
+        let capabilities = Some(v.capabilities().clone().qcm_into());
         Self {
             type_name: v.type_name,
             svg: v.svg.deref().clone(),
@@ -377,6 +451,36 @@ impl QcmFrom<core::provider::ProviderMeta> for proto::ProviderMeta {
             is_script: v.is_script,
             has_server_url: v.has_server_url,
             auth_types: v.auth_types,
+            capabilities,
+        }
+    }
+}
+
+impl QcmFrom<&dyn core::plugin::Plugin> for proto::PluginInfo {
+    fn qcm_from(v: &dyn core::plugin::Plugin) -> Self {
+        Self {
+            id: v.id().to_string(),
+            name: v.name().to_string(),
+            provider_types: v
+                .provider_metas()
+                .into_iter()
+                .map(|m| m.type_name)
+                .collect(),
+        }
+    }
+}
+
+impl QcmFrom<core::provider::ProviderCapabilities> for proto::ProviderCapabilities {
+    fn qcm_from(v: core::provider::ProviderCapabilities) -> Self {
+        Self {
+            favorite: v.favorite.into_iter().map(|t| t as i32).collect(),
+            qr_auth: v.qr_auth,
+            home: v.home,
+            radio_queue: v.radio_queue,
+            search: v.search,
+            playlist_write: v.playlist_write,
+            scrobble: v.scrobble,
+            lyrics: v.lyrics,
         }
     }
 }
@@ -509,7 +613,7 @@ impl QcmFrom<ProcessError> for msg::Rsp {
             code: match v {
                 ProcessError::Internal(_) => msg::ErrorCode::Internal.into(),
                 ProcessError::Encode(_) => msg::ErrorCode::Encode.into(),
-                ProcessError::Decode(_) => msg::ErrorCode::Decode.into(),
+                ProcessError::Decode(_) | ProcessError::Json(_) => msg::ErrorCode::Decode.into(),
                 ProcessError::UnsupportedMessageType(_) => {
                     msg::ErrorCode::UnsupportedMessageType.into()
                 }
@@ -532,6 +636,8 @@ impl QcmFrom<ProcessError> for msg::Rsp {
                 ProcessError::ParseSubtitle(_) => msg::ErrorCode::ParseSubtitle.into(),
                 ProcessError::NotFound => msg::ErrorCode::NotFound.into(),
                 ProcessError::NotImplemented => msg::ErrorCode::NotImplemented.into(),
+                ProcessError::ProviderRejected(_) => msg::ErrorCode::ProviderRejected.into(),
+                ProcessError::Cancelled => msg::ErrorCode::Cancelled.into(),
                 ProcessError::HyperBody(_) => msg::ErrorCode::HyperBody.into(),
                 ProcessError::Infallible(_) => {
                     panic!("Got infallible error!")
@@ -575,6 +681,8 @@ impl_from_for_qcm_msg!(GetProviderMetasRsp);
 impl_from_for_qcm_msg!(QrAuthUrlRsp);
 impl_from_for_qcm_msg!(TestRsp);
 impl_from_for_qcm_msg!(Rsp);
+impl_from_for_qcm_msg!(BatchRsp);
+impl_from_for_qcm_msg!(HelloRsp);
 
 impl_from_for_qcm_msg!(GetAlbumsRsp);
 impl_from_for_qcm_msg!(GetAlbumRsp);
@@ -594,15 +702,25 @@ impl_from_for_qcm_msg!(GetMixSongsReq);
 impl_from_for_qcm_msg!(GetMixSongsRsp);
 impl_from_for_qcm_msg!(MixManipulateReq);
 impl_from_for_qcm_msg!(MixManipulateRsp);
+impl_from_for_qcm_msg!(UpdateMixReq);
 
 impl_from_for_qcm_msg!(SearchRsp);
+impl_from_for_qcm_msg!(GetSubtitleReq);
 impl_from_for_qcm_msg!(GetSubtitleRsp);
+impl_from_for_qcm_msg!(SetFavoriteReq);
+impl_from_for_qcm_msg!(PlaylogReq);
 
 impl_from_for_qcm_msg!(GetQueueNextRsp);
 impl_from_for_qcm_msg!(GetRadioQueuesRsp);
 
 impl_from_for_qcm_msg!(GetStorageInfoRsp);
 impl_from_for_qcm_msg!(SyncRsp);
+impl_from_for_qcm_msg!(GetSyncScheduleRsp);
+impl_from_for_qcm_msg!(GetTasksRsp);
+impl_from_for_qcm_msg!(TaskStatusMsg);
+impl_from_for_qcm_msg!(ItemsChangedMsg);
+impl_from_for_qcm_msg!(GetApiKeysRsp);
+impl_from_for_qcm_msg!(CreateApiKeyRsp);
 impl_from_for_qcm_msg!(GetSongsByIdRsp);
 impl_from_for_qcm_msg!(GetSongIdsRsp);
 
@@ -24,6 +24,11 @@ pub async fn add_provider(
         cookie: Set(p.save_cookie()),
         custom: Set(p.save()),
         edit_time: Set(chrono::Utc::now()),
+        sync_interval: NotSet,
+        sync_idle_only: NotSet,
+        sync_quiet_start: NotSet,
+        sync_quiet_end: NotSet,
+        last_sync_at: NotSet,
     };
 
     let r = sqlm::provider::Entity::insert(model)
@@ -43,3 +48,127 @@ pub async fn add_provider(
         .await?;
     Ok(r.last_insert_id)
 }
+
+/// Libraries to use when a request doesn't name any
+pub async fn enabled_library_ids(db: &DatabaseConnection) -> Result<Vec<i64>, DbErr> {
+    sqlm::library::Entity::find()
+        .select_only()
+        .column(sqlm::library::Column::LibraryId)
+        .filter(sqlm::library::Column::Enabled.eq(true))
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+/// In-memory db with all migrations applied
+#[cfg(test)]
+pub async fn test_db() -> DatabaseConnection {
+    use migration::{Migrator, MigratorTrait};
+    // the fts tables use the qcm tokenizer
+    assert!(crate::fts::load_fts_plugin());
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    Migrator::up(&db, None).await.unwrap();
+    db
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qcm_core::provider::Context;
+    use qcm_plugin_local::provider::LocalProvider;
+    use std::path::Path;
+
+    /// one second of silence, 8 kHz mono 16-bit
+    fn write_wav(path: &Path) {
+        let data_len: u32 = 8000 * 2;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"RIFF");
+        buf.extend_from_slice(&(36 + data_len).to_le_bytes());
+        buf.extend_from_slice(b"WAVEfmt ");
+        buf.extend_from_slice(&16u32.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&8000u32.to_le_bytes());
+        buf.extend_from_slice(&16000u32.to_le_bytes());
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.extend_from_slice(&16u16.to_le_bytes());
+        buf.extend_from_slice(b"data");
+        buf.extend_from_slice(&data_len.to_le_bytes());
+        buf.resize(buf.len() + data_len as usize, 0);
+        std::fs::write(path, buf).unwrap();
+    }
+
+    async fn item_count(db: &DatabaseConnection, provider_id: i64) -> u64 {
+        sqlm::item::Entity::find()
+            .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+            .count(db)
+            .await
+            .unwrap()
+    }
+
+    async fn track_count(db: &DatabaseConnection, id: i64) -> i32 {
+        sqlm::mix::Entity::find_by_id(id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .track_count
+    }
+
+    #[tokio::test]
+    async fn test_append_songs_counts_one_mix() {
+        use qcm_core::model::type_enum::MixType;
+        let db = test_db().await;
+        // only the mix rows matter here, not the songs behind the relations
+        db.execute_unprepared("PRAGMA foreign_keys = OFF;")
+            .await
+            .unwrap();
+        for (id, track_count) in [(1, 0), (2, 5)] {
+            let m = sqlm::mix::ActiveModel {
+                id: Set(id),
+                name: Set(format!("mix {}", id)),
+                track_count: Set(track_count),
+                mix_type: Set(MixType::Normal),
+                description: Set(String::new()),
+                ..Default::default()
+            };
+            sqlm::mix::Entity::insert(m).exec(&db).await.unwrap();
+        }
+
+        let txn = db.begin().await.unwrap();
+        let appended = sqlm::mix::append_songs(&txn, 1, &vec![10, 11]).await.unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(appended, 2);
+        assert_eq!(track_count(&db, 1).await, 2);
+        assert_eq!(track_count(&db, 2).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_local_sync_keeps_missing_dir() {
+        let db = test_db().await;
+        let dir = std::env::temp_dir().join(format!("qcm-local-missing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_wav(&dir.join("a.wav"));
+
+        let provider = Arc::new(LocalProvider::new(Some(1), "local", "test"));
+        provider.load(&serde_json::json!({ "dirs": [dir], "watch": false }).to_string());
+        add_provider(&db, provider.clone()).await.unwrap();
+        let (ev_sender, _ev_receiver) = tokio::sync::mpsc::channel(64);
+        let ctx = Context::new(db.clone(), db.clone(), ev_sender);
+
+        provider.sync(&ctx).await.unwrap();
+        let count = item_count(&db, 1).await;
+        // song, album and artist
+        assert_eq!(count, 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        provider.sync(&ctx).await.unwrap();
+        assert_eq!(item_count(&db, 1).await, count);
+        let libraries = sqlm::library::Entity::find()
+            .filter(sqlm::library::Column::ProviderId.eq(1))
+            .count(&db)
+            .await
+            .unwrap();
+        assert_eq!(libraries, 1);
+    }
+}
@@ -15,6 +15,8 @@ pub enum ProcessError {
     Encode(#[from] prost::EncodeError),
     #[error("Decode error: {0}")]
     Decode(#[from] prost::DecodeError),
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("Unsupported message type: {0}")]
     UnsupportedMessageType(i32),
     #[error("Unknown message type: {0}")]
@@ -57,6 +59,10 @@ pub enum ProcessError {
     NotFound,
     #[error("Not Implemented")]
     NotImplemented,
+    #[error("Provider rejected: {0}")]
+    ProviderRejected(String),
+    #[error("Cancelled")]
+    Cancelled,
     #[error("Infallible")]
     Infallible(#[from] std::convert::Infallible),
     #[default]
@@ -73,6 +79,13 @@ where
     }
 }
 
+impl ProcessError {
+    /// For changes pushed upstream, the local state is left untouched
+    pub fn rejected(e: qcm_core::error::ProviderError) -> Self {
+        ProcessError::ProviderRejected(e.to_string())
+    }
+}
+
 impl From<qcm_core::error::ProviderError> for ProcessError {
     fn from(e: qcm_core::error::ProviderError) -> Self {
         use qcm_core::error::ProviderError;
@@ -1,3 +1,5 @@
+use super::bus::EventBus;
+use super::outbox::Outbox;
 use super::reverse::ReverseEvent;
 use super::task::TaskManagerOper;
 pub use qcm_core::event::Event;
@@ -18,7 +20,8 @@ pub trait EventSink: Send + Sync {
 }
 
 pub enum BackendEvent {
-    Frist,
+    /// a session connected, it gets the full status
+    Frist { session: i64 },
     NewProvider { id: i64 },
     UpdateProvider { id: i64 },
     DeleteProvider { id: i64 },
@@ -28,9 +31,29 @@ pub enum BackendEvent {
 }
 
 /// 传输无关的服务上下文，可被任意 IPC 层复用
+///
+/// One per server, created at startup. Sessions get a copy tagged with their id.
 pub struct ServiceContext {
     pub provider_context: Arc<provider::Context>,
     pub backend_ev: Sender<BackendEvent>,
     pub oper: TaskManagerOper,
     pub reverse_ev: Sender<ReverseEvent>,
+    pub outbox: Outbox,
+    pub bus: EventBus,
+    /// None for the server, e.g. http requests without a session
+    pub session: Option<i64>,
+}
+
+impl ServiceContext {
+    pub fn for_session(&self, id: i64) -> Self {
+        Self {
+            provider_context: self.provider_context.clone(),
+            backend_ev: self.backend_ev.clone(),
+            oper: self.oper.clone(),
+            reverse_ev: self.reverse_ev.clone(),
+            outbox: self.outbox.clone(),
+            bus: self.bus.clone(),
+            session: Some(id),
+        }
+    }
 }
@@ -1,39 +1,89 @@
 use once_cell::sync::Lazy;
-use sea_orm::EntityTrait;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
 
-use crate::event::ServiceContext;
+use crate::api::subsonic::Credentials;
+use crate::event::{EventSink, ServiceContext};
+
+/// A connected websocket client
+pub struct Session {
+    pub id: i64,
+    pub ctx: Arc<ServiceContext>,
+    pub sink: Arc<dyn EventSink>,
+    inflight: Mutex<HashMap<i32, oneshot::Sender<()>>>,
+}
+
+impl Session {
+    /// The receiver fires when the request `id` is cancelled
+    pub fn track(&self, id: i32) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.inflight.lock().unwrap().insert(id, tx);
+        rx
+    }
+
+    pub fn untrack(&self, id: i32) {
+        self.inflight.lock().unwrap().remove(&id);
+    }
+
+    /// False if `id` isn't running, already done or never sent
+    pub fn cancel(&self, id: i32) -> bool {
+        match self.inflight.lock().unwrap().remove(&id) {
+            Some(tx) => tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
 
 struct Global {
-    contexts: BTreeMap<i64, Arc<ServiceContext>>,
+    sessions: BTreeMap<i64, Arc<Session>>,
+    next_session: i64,
     shutdown_tx: Option<tokio::sync::watch::Sender<bool>>,
+    keep_alive: bool,
+    trust_loopback: bool,
+    subsonic: Option<Credentials>,
 }
 
 impl Global {
     fn new() -> Self {
         Self {
-            contexts: BTreeMap::new(),
+            sessions: BTreeMap::new(),
+            next_session: 1,
             shutdown_tx: None,
+            keep_alive: false,
+            trust_loopback: false,
+            subsonic: None,
         }
     }
 }
 
 static GLOBAL: Lazy<Arc<Mutex<Global>>> = Lazy::new(|| Arc::new(Mutex::new(Global::new())));
 
-pub fn context(port: i64) -> Option<Arc<ServiceContext>> {
+pub fn session(id: i64) -> Option<Arc<Session>> {
     let g = GLOBAL.lock().unwrap();
-    g.contexts.get(&port).cloned()
+    g.sessions.get(&id).cloned()
 }
 
-pub fn reg_context(port: i64, c: Arc<ServiceContext>) {
+/// Registers a session on `server`, ids are never reused
+pub fn reg_session(server: &ServiceContext, sink: Arc<dyn EventSink>) -> Arc<Session> {
     let mut g = GLOBAL.lock().unwrap();
-    g.contexts.insert(port, c);
+    let id = g.next_session;
+    g.next_session += 1;
+    let session = Arc::new(Session {
+        id,
+        ctx: Arc::new(server.for_session(id)),
+        sink,
+        inflight: Mutex::new(HashMap::new()),
+    });
+    g.sessions.insert(id, session.clone());
+    session
 }
 
-pub fn unreg_context(port: i64) {
+/// Returns the number of sessions left
+pub fn unreg_session(id: i64) -> usize {
     let mut g = GLOBAL.lock().unwrap();
-    g.contexts.remove(&port);
+    g.sessions.remove(&id);
+    g.sessions.len()
 }
 
 pub fn set_shutdown_tx(tx: tokio::sync::watch::Sender<bool>) {
@@ -47,3 +97,34 @@ pub fn shutdown() {
         let _ = tx.send(true);
     }
 }
+
+/// Keep running when the last session closes
+pub fn set_keep_alive(v: bool) {
+    let mut g = GLOBAL.lock().unwrap();
+    g.keep_alive = v;
+}
+
+pub fn keep_alive() -> bool {
+    let g = GLOBAL.lock().unwrap();
+    g.keep_alive
+}
+
+pub fn set_trust_loopback(v: bool) {
+    let mut g = GLOBAL.lock().unwrap();
+    g.trust_loopback = v;
+}
+
+pub fn trust_loopback() -> bool {
+    let g = GLOBAL.lock().unwrap();
+    g.trust_loopback
+}
+
+pub fn set_subsonic_credentials(c: Credentials) {
+    let mut g = GLOBAL.lock().unwrap();
+    g.subsonic = Some(c);
+}
+
+pub fn subsonic_credentials() -> Option<Credentials> {
+    let g = GLOBAL.lock().unwrap();
+    g.subsonic.clone()
+}
@@ -0,0 +1,155 @@
+//! Endpoints the server accepts connections on, plain or tls tcp and unix sockets
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_native_tls::{native_tls, TlsAcceptor};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// Where a connection comes from
+#[derive(Clone, Copy, Debug)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl Peer {
+    /// Unix sockets are only reachable on this machine. Loopback is trusted only when told,
+    /// a reverse proxy on the same host forwards remote requests from there
+    pub fn is_trusted(&self, loopback: bool) -> bool {
+        match self {
+            Peer::Tcp(addr) => loopback && addr.ip().is_loopback(),
+            Peer::Unix => true,
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix => write!(f, "unix"),
+        }
+    }
+}
+
+/// Accepted, before the tls handshake, which is done off the accept loop
+pub enum Connection {
+    Tcp(TcpStream, Option<TlsAcceptor>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    pub async fn establish(self) -> io::Result<Box<dyn Io>> {
+        match self {
+            Connection::Tcp(stream, None) => Ok(Box::new(stream)),
+            Connection::Tcp(stream, Some(tls)) => tls
+                .accept(stream)
+                .await
+                .map(|s| Box::new(s) as Box<dyn Io>)
+                .map_err(io::Error::other),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Ok(Box::new(stream)),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener, Option<TlsAcceptor>),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<(Connection, Peer)> {
+        match self {
+            Listener::Tcp(l, tls) => {
+                let (stream, addr) = l.accept().await?;
+                Ok((Connection::Tcp(stream, tls.clone()), Peer::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l, _) => {
+                let (stream, _) = l.accept().await?;
+                Ok((Connection::Unix(stream), Peer::Unix))
+            }
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Listener::Tcp(l, _) => l.local_addr().ok().map(|a| a.port()),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    /// Url like description for the startup line
+    pub fn endpoint(&self) -> String {
+        match self {
+            Listener::Tcp(l, tls) => {
+                let scheme = if tls.is_some() { "https" } else { "http" };
+                match l.local_addr() {
+                    Ok(addr) => format!("{}://{}", scheme, addr),
+                    Err(_) => format!("{}://", scheme),
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+}
+
+/// Certificate chain and pkcs8 key, both pem
+pub fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, anyhow::Error> {
+    let identity = native_tls::Identity::from_pkcs8(&std::fs::read(cert)?, &std::fs::read(key)?)?;
+    Ok(native_tls::TlsAcceptor::new(identity)?.into())
+}
+
+/// All addresses share the port, the first one picks it when auto
+pub async fn bind_tcp(
+    addrs: &[IpAddr],
+    port: u16,
+    tls: Option<TlsAcceptor>,
+) -> io::Result<Vec<Listener>> {
+    let mut port = port;
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        let listener = TcpListener::bind((*addr, port)).await?;
+        port = listener.local_addr()?.port();
+        listeners.push(Listener::Tcp(listener, tls.clone()));
+    }
+    Ok(listeners)
+}
+
+/// A stale socket file from a previous run is replaced, any other file is left alone
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> io::Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("not a socket: {}", path.display()),
+            ))
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => (),
+    }
+    Ok(Listener::Unix(
+        UnixListener::bind(path)?,
+        path.to_path_buf(),
+    ))
+}
@@ -0,0 +1,240 @@
+//! EBU R128 / ITU-R BS.1770 loudness meter
+
+use std::collections::VecDeque;
+
+/// replaygain 2.0 reference
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// 100ms steps, a gating block is 4 of them
+const SUB_BLOCKS: usize = 4;
+
+const OVERSAMPLE: usize = 4;
+const PHASE_TAPS: usize = 12;
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// the two K-weighting stages, designed for any sample rate
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    // high shelf
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // high pass
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// surround channels are weighted up and LFE is ignored, assuming the usual 5.1 order
+fn channel_weight(channels: usize, index: usize) -> f64 {
+    match (channels, index) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// 4x windowed sinc interpolation for true peak
+struct TruePeak {
+    coeffs: [[f64; PHASE_TAPS]; OVERSAMPLE],
+    history: Vec<[f64; PHASE_TAPS]>,
+    pos: usize,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(channels: usize) -> Self {
+        let len = OVERSAMPLE * PHASE_TAPS;
+        let center = (len - 1) as f64 / 2.0;
+        let mut coeffs = [[0.0; PHASE_TAPS]; OVERSAMPLE];
+        for i in 0..len {
+            let t = (i as f64 - center) / OVERSAMPLE as f64;
+            let sinc = match t == 0.0 {
+                true => 1.0,
+                false => (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t),
+            };
+            let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / len as f64).cos();
+            coeffs[i % OVERSAMPLE][i / OVERSAMPLE] = sinc * window;
+        }
+        Self {
+            coeffs,
+            history: vec![[0.0; PHASE_TAPS]; channels],
+            pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, channel: usize, x: f64) {
+        let history = &mut self.history[channel];
+        history[self.pos] = x;
+        self.peak = self.peak.max(x.abs());
+        for phase in &self.coeffs {
+            let y: f64 = phase
+                .iter()
+                .enumerate()
+                .map(|(k, c)| c * history[(self.pos + PHASE_TAPS - k) % PHASE_TAPS])
+                .sum();
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+
+    fn advance(&mut self) {
+        self.pos = (self.pos + 1) % PHASE_TAPS;
+    }
+}
+
+pub struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    sub_len: usize,
+    sub_pos: usize,
+    sub_energy: f64,
+    recent: VecDeque<f64>,
+    /// mean square of each gating block
+    blocks: Vec<f64>,
+    peak: TruePeak,
+}
+
+impl Meter {
+    pub fn new(rate: u32, channels: usize) -> Self {
+        Self {
+            channels,
+            weights: (0..channels).map(|i| channel_weight(channels, i)).collect(),
+            filters: vec![k_weighting(rate); channels],
+            sub_len: (rate as usize / 10).max(1),
+            sub_pos: 0,
+            sub_energy: 0.0,
+            recent: VecDeque::with_capacity(SUB_BLOCKS),
+            blocks: Vec::new(),
+            peak: TruePeak::new(channels),
+        }
+    }
+
+    pub fn add_interleaved(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, s) in frame.iter().enumerate() {
+                let x = *s as f64;
+                self.peak.push(c, x);
+                let [shelf, high_pass] = &mut self.filters[c];
+                let y = high_pass.process(shelf.process(x));
+                self.sub_energy += self.weights[c] * y * y;
+            }
+            self.peak.advance();
+
+            self.sub_pos += 1;
+            if self.sub_pos == self.sub_len {
+                if self.recent.len() == SUB_BLOCKS {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(self.sub_energy);
+                if self.recent.len() == SUB_BLOCKS {
+                    let sum: f64 = self.recent.iter().sum();
+                    self.blocks.push(sum / (SUB_BLOCKS * self.sub_len) as f64);
+                }
+                self.sub_pos = 0;
+                self.sub_energy = 0.0;
+            }
+        }
+    }
+
+    /// Gated integrated loudness in LUFS, none for silence or less than 400ms
+    pub fn loudness(&self) -> Option<f64> {
+        let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = blocks.fold((0.0, 0usize), |(s, n), b| (s + b, n + 1));
+            (n > 0).then(|| sum / n as f64)
+        };
+        let absolute = 10f64.powf((ABSOLUTE_GATE + 0.691) / 10.0);
+        let above = mean(&mut self.blocks.iter().copied().filter(|b| *b > absolute))?;
+        let relative = 10f64.powf((energy_to_lufs(above) + RELATIVE_GATE + 0.691) / 10.0);
+        let gated = mean(
+            &mut self
+                .blocks
+                .iter()
+                .copied()
+                .filter(|b| *b > absolute && *b > relative),
+        )?;
+        Some(energy_to_lufs(gated))
+    }
+
+    /// dBTP
+    pub fn true_peak(&self) -> f64 {
+        20.0 * self.peak.peak.max(f64::MIN_POSITIVE).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, channels: usize, amplitude: f32, secs: usize) -> Vec<f32> {
+        (0..rate as usize * secs)
+            .flat_map(|i| {
+                let t = i as f32 / rate as f32;
+                let s = amplitude * (2.0 * std::f32::consts::PI * 997.0 * t).sin();
+                std::iter::repeat(s).take(channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sine_loudness() {
+        // a full scale sine on one channel is -3.01 LKFS
+        let mut m = Meter::new(48000, 1);
+        m.add_interleaved(&sine(48000, 1, 1.0, 5));
+        assert!((m.loudness().unwrap() + 3.01).abs() < 0.05);
+
+        let mut m = Meter::new(44100, 2);
+        m.add_interleaved(&sine(44100, 2, 0.5, 5));
+        assert!((m.loudness().unwrap() + 6.02).abs() < 0.05);
+        assert!((m.true_peak() + 6.02).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut m = Meter::new(48000, 2);
+        m.add_interleaved(&vec![0.0; 48000 * 2]);
+        assert_eq!(m.loudness(), None);
+    }
+}
@@ -1,4 +1,6 @@
 mod api;
+mod auth;
+mod bus;
 mod convert;
 mod db;
 mod error;
@@ -6,16 +8,22 @@ mod event;
 mod fts;
 mod global;
 mod http;
+mod listener;
+mod loudness;
 mod msg;
+mod msg_serde;
+mod outbox;
 mod reverse;
+mod schedule;
 mod task;
 
 use anyhow;
 use clap::{self, Parser};
 use fts::load_fts_plugin;
 use reverse::ReverseEvent;
+use listener::{Connection, Listener, Peer};
 use std::{
-    collections::HashMap,
+    net::IpAddr,
     path::{Path, PathBuf},
     time::Duration,
 };
@@ -23,15 +31,12 @@ use task::TaskManager;
 
 use hyper::{body::Incoming, Request};
 use hyper_util::rt::TokioIo;
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::watch,
-};
+use tokio::sync::watch;
 
 use migration::{CacheDBMigrator, Migrator, MigratorTrait};
 use sea_orm::{ConnectionTrait, Database, DatabaseConnection};
 
-use api::handler::handle_request;
+use api::handler::{handle_request, start_context};
 
 #[derive(clap::Parser)]
 #[command(author, version, about, long_about = None)]
@@ -48,9 +53,64 @@ struct Args {
     #[arg(short, long)]
     port: Option<u16>,
 
+    /// Addresses to listen on, comma separated.
+    /// Loopback clients are trusted while all of them are loopback, other clients need an api key
+    #[arg(
+        short,
+        long,
+        env = "QCM_BIND",
+        value_delimiter = ',',
+        default_value = "127.0.0.1"
+    )]
+    bind: Vec<IpAddr>,
+
+    /// Certificate chain to serve tls on the addresses, pem
+    #[arg(long, env = "QCM_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key of the certificate, pkcs8 pem
+    #[arg(long, env = "QCM_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Unix socket to listen on as well, for a frontend on the same machine
+    #[cfg(unix)]
+    #[arg(long)]
+    unix: Option<PathBuf>,
+
+    /// Let loopback clients in without an api key when other addresses are bound too.
+    /// Don't use it behind a reverse proxy on the same host
+    #[arg(long, env = "QCM_TRUST_LOOPBACK")]
+    trust_loopback: bool,
+
+    /// Create an api key with this name, print its token and exit
+    #[arg(long, value_name = "NAME")]
+    create_api_key: Option<String>,
+
+    /// Keep running when the last websocket client disconnects
+    #[arg(long, env = "QCM_KEEP_ALIVE")]
+    keep_alive: bool,
+
     /// Log level (error, warn, info, debug, trace)
     #[arg(short, long, env = "RUST_LOG")]
     log_level: Option<String>,
+
+    /// User for the subsonic api at /rest, disabled if not set
+    #[arg(long, env = "QCM_SUBSONIC_USER", requires = "subsonic_secret")]
+    subsonic_user: Option<String>,
+
+    /// Password for the subsonic api.
+    /// Arguments are visible to other users, prefer the env or a file
+    #[arg(
+        long,
+        env = "QCM_SUBSONIC_PASSWORD",
+        hide_env_values = true,
+        group = "subsonic_secret"
+    )]
+    subsonic_password: Option<String>,
+
+    /// File with the password for the subsonic api, a trailing newline is ignored
+    #[arg(long, env = "QCM_SUBSONIC_PASSWORD_FILE", group = "subsonic_secret")]
+    subsonic_password_file: Option<PathBuf>,
 }
 
 fn default_log_filter() -> tracing_subscriber::filter::EnvFilter {
@@ -104,6 +164,7 @@ async fn main() -> Result<(), anyhow::Error> {
     let args = Args::parse();
     let log_level = args
         .log_level
+        .as_ref()
         .and_then(|l| EnvFilter::try_new(&l).ok())
         .unwrap_or(default_log_filter());
 
@@ -112,6 +173,17 @@ async fn main() -> Result<(), anyhow::Error> {
     }
 
     qcm_core::global::init(&args.data);
+    if let (Some(user), Some(password)) = (&args.subsonic_user, subsonic_password(&args)?) {
+        global::set_subsonic_credentials(api::subsonic::Credentials {
+            user: user.clone(),
+            password,
+        });
+    }
+    global::set_keep_alive(args.keep_alive);
+    // only this machine can connect, a reverse proxy needs a public address to be useful
+    global::set_trust_loopback(
+        args.trust_loopback || args.bind.iter().all(|addr| addr.is_loopback()),
+    );
     qcm_plugins::init();
 
     let (oper, taskmgr_handle) = {
@@ -123,6 +195,12 @@ async fn main() -> Result<(), anyhow::Error> {
     let db = prepare_db(&args.data).await?;
     let cache_db = prepare_cache_db(&args.data).await?;
 
+    if let Some(name) = &args.create_api_key {
+        let (_, token) = auth::create(&db, name).await?;
+        println!("{}", token);
+        return Ok(());
+    }
+
     // shutdown watcher
     let mut shutdown_rx = {
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
@@ -135,6 +213,7 @@ async fn main() -> Result<(), anyhow::Error> {
     };
 
     let reverse_ev = {
+        let db = db.clone();
         let cache_db = cache_db.clone();
         let cache_dir = args.cache.clone();
         let (tx, rx) = tokio::sync::mpsc::channel(512);
@@ -143,6 +222,7 @@ async fn main() -> Result<(), anyhow::Error> {
                 reverse::Dispatcher::process(
                     rx,
                     cache_db,
+                    db,
                     cache_dir.join("QcmBackend"),
                 )
                 .await;
@@ -153,7 +233,7 @@ async fn main() -> Result<(), anyhow::Error> {
     };
 
     // Use port 0 if none specified (system will assign an available port)
-    let listener = listen(args.port.unwrap_or(0)).await;
+    let listeners = listen(&args).await?;
 
     // enable logging after print port
     let _ = log_reload_handle.modify(|f| {
@@ -163,48 +243,46 @@ async fn main() -> Result<(), anyhow::Error> {
     // init other gloabl entry here for log
     qcm_core::global::load_from_db(&db).await;
 
+    let server = start_context(db, cache_db, oper.clone(), reverse_ev.clone());
+
     let accept = {
-        let oper = oper.clone();
+        let server = server.clone();
         let cnn_shutdown_rx = shutdown_rx.clone();
-        let reverse_ev = reverse_ev.clone();
-        move |stream: TcpStream| {
-            let addr = stream
-                .peer_addr()
-                .expect("connected streams should have a peer address");
-
+        move |conn: Connection, peer: Peer| {
             tokio::spawn({
                 // need double clone for multiple requests in one connection, `accept` is Fn
-                let db = db.clone();
-                let cache_db = cache_db.clone();
-                let oper = oper.clone();
-                let reverse_ev = reverse_ev.clone();
+                let server = server.clone();
                 let mut cnn_shutdown_rx = cnn_shutdown_rx.clone();
 
                 async move {
+                    let stream = match conn.establish().await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            log::warn!(target: "http", "handshake failed: {}: {}", peer, err);
+                            return;
+                        }
+                    };
                     let http = hyper::server::conn::http1::Builder::new();
                     let service = |request: Request<Incoming>| {
-                        let db = db.clone();
-                        let cache_db = cache_db.clone();
-                        let oper = oper.clone();
-                        let reverse_ev = reverse_ev.clone();
-                        async move { handle_request(request, db, cache_db, oper, reverse_ev).await }
+                        let server = server.clone();
+                        async move { handle_request(request, peer, server).await }
                     };
                     let connection = http
                         .serve_connection(TokioIo::new(stream), hyper::service::service_fn(service))
                         .with_upgrades();
 
-                    log::debug!(target: "http", "start connection: {}", addr);
+                    log::debug!(target: "http", "start connection: {}", peer);
 
                     tokio::select! {
                         res = connection => {
                             if let Err(err) = res {
                                 log::error!("Error HTTP connection: {err:?}");
                             } else {
-                                log::debug!(target: "http", "end connection: {}", addr);
+                                log::debug!(target: "http", "end connection: {}", peer);
                             }
                         }
                         _ = cnn_shutdown_rx.changed() => {
-                            log::info!("shutting down connection: {}", addr);
+                            log::info!("shutting down connection: {}", peer);
                         }
                     }
                 }
@@ -218,9 +296,11 @@ async fn main() -> Result<(), anyhow::Error> {
         }
 
         tokio::select! {
-            accept_result = listener.accept() => {
-                if let Ok((stream, _)) = accept_result {
-                    accept(stream);
+            (accept_result, _, _) = futures::future::select_all(
+                listeners.iter().map(|l| Box::pin(l.accept()))
+            ) => {
+                if let Ok((conn, peer)) = accept_result {
+                    accept(conn, peer);
                 }
             }
             _ = shutdown_rx.changed() => {
@@ -232,16 +312,38 @@ async fn main() -> Result<(), anyhow::Error> {
 
     // final
     {
+        let _ = server
+            .provider_context
+            .ev_sender
+            .send(event::Event::End)
+            .await;
+        let _ = server.backend_ev.send(event::BackendEvent::End).await;
         let _ = reverse_ev.send(ReverseEvent::Stop);
 
         log::info!("Shutting down task manager");
         oper.stop();
         let _ = taskmgr_handle.join();
         log::info!("Task manager stopped");
+
+        #[cfg(unix)]
+        if let Some(path) = &args.unix {
+            let _ = std::fs::remove_file(path);
+        }
     }
     Ok(())
 }
 
+fn subsonic_password(args: &Args) -> Result<Option<String>, anyhow::Error> {
+    match (&args.subsonic_password, &args.subsonic_password_file) {
+        (Some(password), _) => Ok(Some(password.clone())),
+        (None, Some(path)) => {
+            let password = std::fs::read_to_string(path)?;
+            Ok(Some(password.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
 async fn prepare_db(data: &Path) -> Result<DatabaseConnection, anyhow::Error> {
     let db_path = data.join("backend.2.db");
     let db_url = format!("sqlite://{}?mode=rwc", db_path.to_string_lossy());
@@ -322,21 +424,29 @@ async fn prepare_cache_db(data: &Path) -> Result<DatabaseConnection, anyhow::Err
     Ok(db)
 }
 
-async fn listen(port: u16) -> TcpListener {
-    let listener = {
-        let addr = format!("127.0.0.1:{}", port);
-
-        let try_socket = TcpListener::bind(&addr).await;
-        let listener = try_socket.expect("Failed to bind");
-
-        let local_addr = listener.local_addr().expect("Failed to get local address");
-
-        // print port json
-        println!(
-            "{}",
-            serde_json::to_string(&HashMap::from([("port", local_addr.port())])).unwrap()
-        );
-        listener
+async fn listen(args: &Args) -> Result<Vec<Listener>, anyhow::Error> {
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(listener::tls_acceptor(cert, key)?),
+        _ => None,
     };
-    listener
+    #[allow(unused_mut)]
+    let mut listeners = listener::bind_tcp(&args.bind, args.port.unwrap_or(0), tls).await?;
+    #[cfg(unix)]
+    if let Some(path) = &args.unix {
+        listeners.push(listener::bind_unix(path)?);
+    }
+    if listeners.is_empty() {
+        anyhow::bail!("No address to listen on");
+    }
+
+    // print endpoints json, port is the shared tcp port
+    let endpoints: Vec<String> = listeners.iter().map(|l| l.endpoint()).collect();
+    println!(
+        "{}",
+        serde_json::json!({
+            "port": listeners.iter().find_map(|l| l.port()),
+            "endpoints": endpoints,
+        })
+    );
+    Ok(listeners)
 }
@@ -0,0 +1,176 @@
+//! serde for the well known types in `crate::msg`, following the proto3 json mapping
+//!
+//! Timestamp is a rfc3339 string, Struct is a plain json object
+
+use prost_types::{value::Kind, ListValue, Struct, Timestamp, Value};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value as Json;
+
+pub fn timestamp_to_json(t: &Timestamp) -> Json {
+    chrono::DateTime::from_timestamp(t.seconds, t.nanos.max(0) as u32)
+        .map(|d| Json::String(d.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)))
+        .unwrap_or(Json::Null)
+}
+
+pub fn timestamp_from_json(v: &str) -> Result<Timestamp, chrono::ParseError> {
+    let d = chrono::DateTime::parse_from_rfc3339(v)?;
+    Ok(Timestamp {
+        seconds: d.timestamp(),
+        nanos: d.timestamp_subsec_nanos() as i32,
+    })
+}
+
+fn value_to_json(v: &Value) -> Json {
+    match &v.kind {
+        None | Some(Kind::NullValue(_)) => Json::Null,
+        Some(Kind::NumberValue(n)) => serde_json::Number::from_f64(*n)
+            .map(Json::Number)
+            .unwrap_or(Json::Null),
+        Some(Kind::StringValue(s)) => Json::String(s.clone()),
+        Some(Kind::BoolValue(b)) => Json::Bool(*b),
+        Some(Kind::StructValue(s)) => struct_to_json(s),
+        Some(Kind::ListValue(l)) => Json::Array(l.values.iter().map(value_to_json).collect()),
+    }
+}
+
+fn value_from_json(v: Json) -> Value {
+    let kind = match v {
+        Json::Null => Kind::NullValue(0),
+        Json::Bool(b) => Kind::BoolValue(b),
+        Json::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Json::String(s) => Kind::StringValue(s),
+        Json::Array(a) => Kind::ListValue(ListValue {
+            values: a.into_iter().map(value_from_json).collect(),
+        }),
+        Json::Object(o) => Kind::StructValue(Struct {
+            fields: o
+                .into_iter()
+                .map(|(k, v)| (k, value_from_json(v)))
+                .collect(),
+        }),
+    };
+    Value { kind: Some(kind) }
+}
+
+pub fn struct_to_json(s: &Struct) -> Json {
+    Json::Object(
+        s.fields
+            .iter()
+            .map(|(k, v)| (k.clone(), value_to_json(v)))
+            .collect(),
+    )
+}
+
+pub fn struct_from_json(v: Json) -> Option<Struct> {
+    match value_from_json(v).kind {
+        Some(Kind::StructValue(s)) => Some(s),
+        _ => None,
+    }
+}
+
+pub mod timestamp {
+    use super::*;
+
+    fn de<'de, D: Deserializer<'de>>(v: Json) -> Result<Option<Timestamp>, D::Error> {
+        match v {
+            Json::Null => Ok(None),
+            Json::String(s) => timestamp_from_json(&s).map(Some).map_err(D::Error::custom),
+            _ => Err(D::Error::custom("timestamp should be a rfc3339 string")),
+        }
+    }
+
+    pub mod opt {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &Option<Timestamp>, s: S) -> Result<S::Ok, S::Error> {
+            v.as_ref().map(timestamp_to_json).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Timestamp>, D::Error> {
+            de::<D>(Json::deserialize(d)?)
+        }
+    }
+
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &[Timestamp], s: S) -> Result<S::Ok, S::Error> {
+            s.collect_seq(v.iter().map(timestamp_to_json))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Timestamp>, D::Error> {
+            Vec::<Json>::deserialize(d)?
+                .into_iter()
+                .filter_map(|v| de::<D>(v).transpose())
+                .collect()
+        }
+    }
+}
+
+pub mod structs {
+    use super::*;
+
+    fn de<'de, D: Deserializer<'de>>(v: Json) -> Result<Option<Struct>, D::Error> {
+        match v {
+            Json::Null => Ok(None),
+            v @ Json::Object(_) => Ok(struct_from_json(v)),
+            _ => Err(D::Error::custom("struct should be an object")),
+        }
+    }
+
+    pub mod opt {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &Option<Struct>, s: S) -> Result<S::Ok, S::Error> {
+            v.as_ref().map(struct_to_json).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Struct>, D::Error> {
+            de::<D>(Json::deserialize(d)?)
+        }
+    }
+
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &[Struct], s: S) -> Result<S::Ok, S::Error> {
+            s.collect_seq(v.iter().map(struct_to_json))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Struct>, D::Error> {
+            Vec::<Json>::deserialize(d)?
+                .into_iter()
+                .filter_map(|v| de::<D>(v).transpose())
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_struct_round_trip() {
+        let json = serde_json::json!({
+            "name": "a",
+            "count": 2.0,
+            "tags": ["x", null, true],
+            "nested": { "k": 1.5 }
+        });
+        let s = struct_from_json(json.clone()).unwrap();
+        assert_eq!(struct_to_json(&s), json);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let t = timestamp_from_json("2024-01-02T03:04:05.5Z").unwrap();
+        assert_eq!(t.seconds, 1704164645);
+        assert_eq!(t.nanos, 500_000_000);
+        assert_eq!(
+            timestamp_to_json(&t),
+            Json::String("2024-01-02T03:04:05.500Z".to_string())
+        );
+    }
+}
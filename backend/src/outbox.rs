@@ -0,0 +1,312 @@
+//! Persisted queue of provider mutations, delivered in order per provider
+
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::error::ProviderError;
+use qcm_core::event::SyncState;
+use qcm_core::global;
+use qcm_core::model as sqlm;
+use qcm_core::model::type_enum::ItemType;
+use qcm_core::provider::{Context, PlaybackAction, Provider};
+use sea_orm::{
+    prelude::Expr, ColumnTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::event::BackendEvent;
+
+/// first retry delay, doubled on each failure
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+/// entries are kept as failed after this many attempts
+pub const MAX_ATTEMPTS: i32 = 10;
+/// wake up now and then even without new entries
+const IDLE_DELAY: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutboxOp {
+    Playback {
+        native_id: String,
+        action: PlaybackAction,
+        position: i64,
+        timestamp: i64,
+    },
+    Favorite {
+        native_id: String,
+        item_type: ItemType,
+        value: bool,
+    },
+    RenameMix {
+        native_id: String,
+        name: String,
+    },
+    DeleteMix {
+        native_id: String,
+    },
+    MixAddSongs {
+        native_id: String,
+        song_native_ids: Vec<String>,
+    },
+    MixRemoveSongs {
+        native_id: String,
+        song_native_ids: Vec<String>,
+    },
+}
+
+impl OutboxOp {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OutboxOp::Playback { .. } => "playback",
+            OutboxOp::Favorite { .. } => "favorite",
+            OutboxOp::RenameMix { .. } => "rename_mix",
+            OutboxOp::DeleteMix { .. } => "delete_mix",
+            OutboxOp::MixAddSongs { .. } => "mix_add_songs",
+            OutboxOp::MixRemoveSongs { .. } => "mix_remove_songs",
+        }
+    }
+
+    async fn run(&self, provider: &dyn Provider, ctx: &Context) -> Result<(), ProviderError> {
+        match self {
+            OutboxOp::Playback {
+                native_id,
+                action,
+                position,
+                timestamp,
+            } => {
+                provider
+                    .report_playback(
+                        ctx,
+                        native_id,
+                        *action,
+                        *position,
+                        Timestamp::from_millis(*timestamp),
+                    )
+                    .await
+            }
+            OutboxOp::Favorite {
+                native_id,
+                item_type,
+                value,
+            } => provider.favorite(ctx, native_id, *item_type, *value).await,
+            OutboxOp::RenameMix { native_id, name } => {
+                provider.rename_mix(ctx, native_id, name).await
+            }
+            OutboxOp::DeleteMix { native_id } => provider.delete_mix(ctx, native_id).await,
+            OutboxOp::MixAddSongs {
+                native_id,
+                song_native_ids,
+            } => {
+                provider
+                    .mix_add_songs(ctx, native_id, song_native_ids)
+                    .await
+            }
+            OutboxOp::MixRemoveSongs {
+                native_id,
+                song_native_ids,
+            } => {
+                provider
+                    .mix_remove_songs(ctx, native_id, song_native_ids)
+                    .await
+            }
+        }
+    }
+}
+
+/// only network failures are worth retrying
+fn is_transient(e: &ProviderError) -> bool {
+    SyncState::from(e) == SyncState::NetworkError
+}
+
+fn retry_at(attempts: i32) -> Timestamp {
+    let delay = RETRY_DELAY
+        .saturating_mul(1 << (attempts - 1).clamp(0, 16))
+        .min(MAX_RETRY_DELAY);
+    Timestamp::from_millis(Timestamp::now().as_millis() + delay.as_millis() as i64)
+}
+
+#[derive(Clone)]
+pub struct Outbox {
+    wake: mpsc::UnboundedSender<()>,
+}
+
+impl Outbox {
+    /// Replay runs until every handle is dropped, leftovers of the last run go first
+    pub fn start(ctx: Arc<Context>, backend_ev: mpsc::Sender<BackendEvent>) -> Self {
+        let (wake, rx) = mpsc::unbounded_channel();
+        tokio::spawn(worker(rx, ctx, backend_ev));
+        Self { wake }
+    }
+
+    /// Run `op` now, or queue it when the provider can't be reached
+    ///
+    /// Ops queued before for the same provider go first, errors other than network ones are
+    /// returned as the provider rejecting the change
+    pub async fn deliver(
+        &self,
+        ctx: &Context,
+        provider_id: i64,
+        op: OutboxOp,
+    ) -> Result<(), ProviderError> {
+        let pending = sqlm::outbox::Entity::find()
+            .filter(sqlm::outbox::Column::ProviderId.eq(provider_id))
+            .filter(sqlm::outbox::Column::Attempts.lt(MAX_ATTEMPTS))
+            .count(&ctx.db)
+            .await?;
+        if pending == 0 {
+            let provider = global::provider(provider_id).ok_or(ProviderError::NotFound)?;
+            match op.run(provider.as_ref(), ctx).await {
+                Err(e) if is_transient(&e) => {
+                    log::info!(target: "outbox", "queue {}: {}", op.kind(), e);
+                }
+                res => return res,
+            }
+        }
+        self.push(ctx, provider_id, op).await?;
+        Ok(())
+    }
+
+    pub async fn push(&self, ctx: &Context, provider_id: i64, op: OutboxOp) -> Result<(), DbErr> {
+        let model = sqlm::outbox::ActiveModel {
+            provider_id: Set(provider_id),
+            kind: Set(op.kind().to_string()),
+            payload: Set(serde_json::to_value(&op).map_err(|e| DbErr::Json(e.to_string()))?),
+            attempts: Set(0),
+            next_at: Set(Timestamp::now()),
+            error: Set(None),
+            ..Default::default()
+        };
+        sqlm::outbox::Entity::insert(model).exec(&ctx.db).await?;
+        let _ = self.wake.send(());
+        Ok(())
+    }
+}
+
+async fn worker(
+    mut rx: mpsc::UnboundedReceiver<()>,
+    ctx: Arc<Context>,
+    backend_ev: mpsc::Sender<BackendEvent>,
+) {
+    loop {
+        let wait = match replay(&ctx, &backend_ev).await {
+            Ok(Some(next_at)) => {
+                let ms = next_at.as_millis() - Timestamp::now().as_millis();
+                Duration::from_millis(ms.max(0) as u64).min(IDLE_DELAY)
+            }
+            Ok(None) => IDLE_DELAY,
+            Err(e) => {
+                log::error!(target: "outbox", "{}", e);
+                RETRY_DELAY
+            }
+        };
+        tokio::select! {
+            r = rx.recv() => {
+                if r.is_none() {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+/// Deliver due entries, returns when the next one is due
+async fn replay(
+    ctx: &Context,
+    backend_ev: &mpsc::Sender<BackendEvent>,
+) -> Result<Option<Timestamp>, DbErr> {
+    let db = &ctx.db;
+    let now = Timestamp::now();
+    let due = sqlm::outbox::Entity::find()
+        .filter(sqlm::outbox::Column::NextAt.lte(now))
+        .filter(sqlm::outbox::Column::Attempts.lt(MAX_ATTEMPTS))
+        .order_by_asc(sqlm::outbox::Column::Id)
+        .all(db)
+        .await?;
+
+    // later entries of a failed provider wait for the earlier ones
+    let mut blocked = HashSet::new();
+    let mut checked = HashSet::new();
+    // providers whose status changed
+    let mut changed = BTreeSet::new();
+    for entry in due {
+        if blocked.contains(&entry.provider_id) {
+            continue;
+        }
+        let res = match serde_json::from_value::<OutboxOp>(entry.payload.clone()) {
+            Err(e) => {
+                log::warn!(target: "outbox", "drop {}: {}", entry.id, e);
+                Ok(())
+            }
+            Ok(op) => match global::provider(entry.provider_id) {
+                None => Err(ProviderError::NotFound),
+                Some(provider) => {
+                    // after a failure, wait for the provider to be reachable again
+                    let ready = match entry.attempts > 0 && checked.insert(entry.provider_id) {
+                        true => provider.check(ctx).await,
+                        false => Ok(()),
+                    };
+                    match ready {
+                        Ok(()) => op.run(provider.as_ref(), ctx).await,
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+        };
+
+        match res {
+            Ok(()) | Err(ProviderError::NotImplemented) => {
+                sqlm::outbox::Entity::delete_by_id(entry.id)
+                    .exec(db)
+                    .await?;
+                if entry.attempts > 0 {
+                    changed.insert(entry.provider_id);
+                }
+            }
+            Err(e) => {
+                log::warn!(target: "outbox", "{} {}: {}", entry.kind, entry.id, e);
+                changed.insert(entry.provider_id);
+                let attempts = match is_transient(&e) {
+                    true => entry.attempts + 1,
+                    false => MAX_ATTEMPTS,
+                };
+                let next_at = retry_at(attempts);
+                sqlm::outbox::Entity::update_many()
+                    .col_expr(sqlm::outbox::Column::Attempts, Expr::val(attempts).into())
+                    .col_expr(sqlm::outbox::Column::NextAt, Expr::val(next_at).into())
+                    .col_expr(sqlm::outbox::Column::Error, Expr::val(e.to_string()).into())
+                    .filter(sqlm::outbox::Column::Id.eq(entry.id))
+                    .exec(db)
+                    .await?;
+                if attempts < MAX_ATTEMPTS {
+                    blocked.insert(entry.provider_id);
+                    sqlm::outbox::Entity::update_many()
+                        .col_expr(sqlm::outbox::Column::NextAt, Expr::val(next_at).into())
+                        .filter(sqlm::outbox::Column::ProviderId.eq(entry.provider_id))
+                        .filter(sqlm::outbox::Column::NextAt.lte(now))
+                        .exec(db)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    for id in changed {
+        let _ = backend_ev.send(BackendEvent::UpdateProvider { id }).await;
+    }
+
+    let next: Option<Timestamp> = sqlm::outbox::Entity::find()
+        .select_only()
+        .column_as(sqlm::outbox::Column::NextAt.min(), "next_at")
+        .filter(sqlm::outbox::Column::Attempts.lt(MAX_ATTEMPTS))
+        .into_tuple::<Option<Timestamp>>()
+        .one(db)
+        .await?
+        .flatten();
+    Ok(next)
+}
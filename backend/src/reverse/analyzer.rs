@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use qcm_core::db::values::Timestamp;
+use qcm_core::model as sqlm;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tokio::sync::mpsc;
+
+use super::block_store::{self, BlockStore};
+use crate::loudness::Meter;
+
+/// wait for the playback that filled the cache to settle
+const START_DELAY: Duration = Duration::from_secs(5);
+/// decode at most this many times faster than realtime
+const MAX_SPEED: f64 = 50.0;
+
+struct Job {
+    song_id: i64,
+    source_key: String,
+}
+
+/// Measures loudness of fully cached songs in the background, one at a time
+#[derive(Clone)]
+pub struct Analyzer {
+    tx: mpsc::UnboundedSender<Job>,
+}
+
+impl Analyzer {
+    pub fn start(
+        store: Arc<BlockStore>,
+        cache_db: DatabaseConnection,
+        db: DatabaseConnection,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(worker(rx, store, cache_db, db));
+        Self { tx }
+    }
+
+    /// Queue a song, called after its source is finished
+    pub fn request(&self, song_id: i64, source_key: &str) {
+        let _ = self.tx.send(Job {
+            song_id,
+            source_key: source_key.to_string(),
+        });
+    }
+}
+
+async fn worker(
+    mut rx: mpsc::UnboundedReceiver<Job>,
+    store: Arc<BlockStore>,
+    cache_db: DatabaseConnection,
+    db: DatabaseConnection,
+) {
+    while let Some(job) = rx.recv().await {
+        tokio::time::sleep(START_DELAY).await;
+        if let Err(e) = analyze(&store, &cache_db, &db, &job).await {
+            log::warn!(target: "analyzer", "song {}: {:?}", job.song_id, e);
+        }
+    }
+}
+
+async fn analyze(
+    store: &BlockStore,
+    cache_db: &DatabaseConnection,
+    db: &DatabaseConnection,
+    job: &Job,
+) -> Result<(), anyhow::Error> {
+    let Some(song) = sqlm::song::Entity::find_by_id(job.song_id).one(db).await? else {
+        return Ok(());
+    };
+    // the provider already knows better
+    if song.track_gain.is_some() {
+        return Ok(());
+    }
+    let done = sqlm::song_loudness::Entity::find_by_id(job.song_id)
+        .one(db)
+        .await?
+        .map(|l| l.source_key == job.source_key)
+        .unwrap_or(false);
+    if done {
+        return Ok(());
+    }
+
+    let Some(source) = sqlm::cache_source::query_by_key(cache_db, &job.source_key).await else {
+        return Ok(());
+    };
+    let blocks = sqlm::cache_block::Entity::find()
+        .filter(sqlm::cache_block::Column::SourceKey.eq(&job.source_key))
+        .order_by_asc(sqlm::cache_block::Column::BlockIndex)
+        .all(cache_db)
+        .await?;
+    let complete = blocks.len() == source.block_count as usize
+        && blocks
+            .iter()
+            .enumerate()
+            .all(|(i, b)| b.block_index == i as i32);
+    if !complete {
+        log::debug!(target: "analyzer", "{} not fully cached", job.source_key);
+        return Ok(());
+    }
+
+    // the io thread runs commands in order, a read after the renames means they are done
+    if let Some(last) = blocks.last() {
+        let _ = store.read_block(&last.key, 0, 0).await;
+    }
+    let parts: Vec<_> = blocks
+        .iter()
+        .map(|b| {
+            (
+                block_store::block_path(store.cache_dir(), &b.key),
+                b.block_size as u64,
+            )
+        })
+        .collect();
+
+    let content_type = source.content_type.clone();
+    let Some((loudness, true_peak)) =
+        tokio::task::spawn_blocking(move || measure(BlockReader::new(parts), &content_type))
+            .await??
+    else {
+        return Ok(());
+    };
+    log::info!(target: "analyzer", "song {}: {:.2} LUFS, {:.2} dBTP", job.song_id, loudness, true_peak);
+
+    let model = sqlm::song_loudness::ActiveModel {
+        id: Set(job.song_id),
+        loudness: Set(loudness),
+        true_peak: Set(true_peak),
+        source_key: Set(job.source_key.clone()),
+        update_at: Set(Timestamp::now()),
+    };
+    sqlm::song_loudness::Entity::insert(model)
+        .on_conflict(
+            sea_orm::sea_query::OnConflict::column(sqlm::song_loudness::Column::Id)
+                .update_columns([
+                    sqlm::song_loudness::Column::Loudness,
+                    sqlm::song_loudness::Column::TruePeak,
+                    sqlm::song_loudness::Column::SourceKey,
+                    sqlm::song_loudness::Column::UpdateAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Decode the whole source, paced by MAX_SPEED
+fn measure(reader: BlockReader, content_type: &str) -> Result<Option<(f64, f64)>, anyhow::Error> {
+    let mut hint = Hint::new();
+    hint.mime_type(content_type);
+    let mss = MediaSourceStream::new(Box::new(reader), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or(anyhow::anyhow!("no audio track"))?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+    let rate = params
+        .sample_rate
+        .ok_or(anyhow::anyhow!("unknown sample rate"))?;
+    let mut decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+
+    let mut meter: Option<Meter> = None;
+    let mut buf: Option<SampleBuffer<f32>> = None;
+    let mut frames: u64 = 0;
+    let begin = Instant::now();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(SymError::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            Err(SymError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            Err(SymError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let cap = decoded.capacity() as u64;
+        let b = match &mut buf {
+            Some(b) if b.capacity() as u64 >= cap * spec.channels.count() as u64 => b,
+            _ => buf.insert(SampleBuffer::new(cap, spec)),
+        };
+        b.copy_interleaved_ref(decoded);
+        meter
+            .get_or_insert_with(|| Meter::new(rate, spec.channels.count()))
+            .add_interleaved(b.samples());
+
+        frames += (b.samples().len() / spec.channels.count().max(1)) as u64;
+        let ahead = Duration::from_secs_f64(frames as f64 / rate as f64 / MAX_SPEED);
+        if let Some(wait) = ahead.checked_sub(begin.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    Ok(meter.and_then(|m| m.loudness().map(|l| (l, m.true_peak()))))
+}
+
+/// Cached blocks of a source read as one file
+struct BlockReader {
+    parts: Vec<(PathBuf, u64)>,
+    len: u64,
+    pos: u64,
+    file: Option<(usize, File)>,
+}
+
+impl BlockReader {
+    fn new(parts: Vec<(PathBuf, u64)>) -> Self {
+        let len = parts.iter().map(|(_, size)| size).sum();
+        Self {
+            parts,
+            len,
+            pos: 0,
+            file: None,
+        }
+    }
+}
+
+impl Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = block_store::block_index(self.pos) as usize;
+        let offset = self.pos - block_store::block_offset(index as u32);
+        let remain = self.parts[index].1.saturating_sub(offset);
+        if remain == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short block"));
+        }
+
+        let file = match &mut self.file {
+            Some((i, f)) if *i == index => f,
+            _ => {
+                &mut self
+                    .file
+                    .insert((index, File::open(&self.parts[index].0)?))
+                    .1
+            }
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        let len = (buf.len() as u64).min(remain) as usize;
+        let n = file.read(&mut buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for BlockReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::End(p) => self.len as i64 + p,
+            SeekFrom::Current(p) => self.pos as i64 + p,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl MediaSource for BlockReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
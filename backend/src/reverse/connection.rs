@@ -12,6 +12,8 @@ pub struct Connection {
     pub key: String,
     pub range: Option<HttpRange>,
     pub cache_type: CacheType,
+    /// song the audio belongs to, for analysis once fully cached
+    pub song_id: Option<i64>,
 }
 
 impl Connection {
@@ -20,8 +22,14 @@ impl Connection {
             key: key.to_string(),
             range,
             cache_type,
+            song_id: None,
         }
     }
+
+    pub fn with_song(mut self, song_id: i64) -> Self {
+        self.song_id = Some(song_id);
+        self
+    }
 }
 
 pub type ResponceOneshot =
@@ -6,6 +6,7 @@ use futures_util::SinkExt;
 use hyper::body::Frame;
 use sea_orm::DatabaseConnection;
 
+use super::analyzer::Analyzer;
 use super::block_store::{self, BlockState, BlockStore, SourceMeta, BLOCK_SIZE, READ_CHUNK_SIZE};
 use super::connection::{
     create_rsp, real_request, Connection, Creator, RemoteFileInfo, ResponceOneshot,
@@ -24,10 +25,11 @@ impl ConnectionHandler {
         cnn: Connection,
         store: Arc<BlockStore>,
         db: DatabaseConnection,
+        analyzer: Analyzer,
         ct: Creator,
         rsp_tx: ResponceOneshot,
     ) {
-        if let Err(e) = Self::process_inner(cnn, store, db, ct, rsp_tx).await {
+        if let Err(e) = Self::process_inner(cnn, store, db, analyzer, ct, rsp_tx).await {
             log::error!(target: "connection", "error: {:?}", e);
         }
     }
@@ -36,6 +38,7 @@ impl ConnectionHandler {
         cnn: Connection,
         store: Arc<BlockStore>,
         db: DatabaseConnection,
+        analyzer: Analyzer,
         ct: Creator,
         rsp_tx: ResponceOneshot,
     ) -> Result<(), ProcessError> {
@@ -78,13 +81,18 @@ impl ConnectionHandler {
                 let store2 = store.clone();
                 let db2 = db.clone();
                 let sk = source_key.to_string();
+                let sk2 = sk.clone();
                 let meta2 = source_meta.clone();
                 let ct = cnn.cache_type;
+                let song_id = cnn.song_id;
                 let handle = tokio::spawn(async move {
                     source_actor::source_actor(
                         store2, db2, sk, ct, meta2, response, start_block,
                     )
                     .await;
+                    if let (CacheType::Audio, Some(song_id)) = (ct, song_id) {
+                        analyzer.request(song_id, &sk2);
+                    }
                 });
                 store.set_source_actor(source_key, handle);
             }
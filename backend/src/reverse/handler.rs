@@ -73,10 +73,19 @@ pub async fn media_get_image(
     Ok(rsp?)
 }
 
+/// Song stored as a part of a file, e.g. a cue track, offsets in milliseconds
+pub struct AudioSlice<'a> {
+    pub source_id: &'a str,
+    pub start: i64,
+    pub end: Option<i64>,
+}
+
 pub async fn media_get_audio(
     ctx: &Arc<ServiceContext>,
+    song_id: i64,
     provider_id: i64,
     native_id: &str,
+    slice: Option<AudioSlice<'_>>,
     headers: http::HeaderMap,
 ) -> Result<Response<ResponseBody>, ProcessError> {
     let mut headers = headers;
@@ -115,12 +124,21 @@ pub async fn media_get_audio(
         }
     };
 
-    let key = format!("audio{}", native_id);
+    // a slice is cached by its source and range, so a changed sheet does not hit stale data
+    let key = match slice {
+        Some(s) => format!(
+            "audio{}@{}-{}",
+            s.source_id,
+            s.start,
+            s.end.map(|e| e.to_string()).unwrap_or_default()
+        ),
+        None => format!("audio{}", native_id),
+    };
     let key = crypto::digest(crypto::MessageDigest::md5(), key.as_bytes())
         .map(|data| String::from_utf8_lossy(&crypto::hex::encode_low(&data)).to_string())
         .map_err(|_| ProcessError::Internal(anyhow!("md5 error")))?;
 
-    let cnn = Connection::new(&key, range, CacheType::Audio);
+    let cnn = Connection::new(&key, range, CacheType::Audio).with_song(song_id);
 
     let (tx, rx) = tokio::sync::oneshot::channel();
     ctx.reverse_ev
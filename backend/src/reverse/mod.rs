@@ -1,5 +1,6 @@
 pub mod handler;
 pub mod connection;
+pub mod analyzer;
 pub mod block_store;
 pub mod source_actor;
 
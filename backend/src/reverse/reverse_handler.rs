@@ -4,6 +4,7 @@ use std::sync::Arc;
 use sea_orm::DatabaseConnection;
 use tokio::sync::mpsc::Receiver;
 
+use super::analyzer::Analyzer;
 use super::block_store::BlockStore;
 use super::io::IoCmd;
 use super::io_handler;
@@ -15,12 +16,14 @@ pub struct Dispatcher {
     rx: Receiver<ReverseEvent>,
     store: Arc<BlockStore>,
     db: DatabaseConnection,
+    analyzer: Analyzer,
 }
 
 impl Dispatcher {
     pub async fn process(
         rx: Receiver<ReverseEvent>,
         db: DatabaseConnection,
+        library_db: DatabaseConnection,
         cache_dir: PathBuf,
     ) {
         // Start IO thread
@@ -31,8 +34,14 @@ impl Dispatcher {
         });
 
         let store = Arc::new(BlockStore::new(io_tx, cache_dir));
+        let analyzer = Analyzer::start(store.clone(), db.clone(), library_db);
 
-        let mut dispatcher = Dispatcher { rx, store, db };
+        let mut dispatcher = Dispatcher {
+            rx,
+            store,
+            db,
+            analyzer,
+        };
         dispatcher.run().await;
 
         drop(dispatcher);
@@ -45,9 +54,10 @@ impl Dispatcher {
                 ReverseEvent::NewConnection(cnn, ct, rsp_tx) => {
                     let store = self.store.clone();
                     let db = self.db.clone();
+                    let analyzer = self.analyzer.clone();
                     tokio::spawn(async move {
                         super::connection_handler::ConnectionHandler::process(
-                            cnn, store, db, ct, rsp_tx,
+                            cnn, store, db, analyzer, ct, rsp_tx,
                         )
                         .await;
                     });
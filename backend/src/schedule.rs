@@ -0,0 +1,139 @@
+//! Automatic provider sync, driven by the schedule stored with each provider
+
+use std::collections::HashMap;
+use std::sync::Weak;
+use std::time::Duration;
+
+use chrono::Timelike;
+use qcm_core::db::values::Timestamp;
+use qcm_core::event::Event as CoreEvent;
+use qcm_core::global;
+use qcm_core::model as sqlm;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use tokio::sync::oneshot;
+
+use crate::event::ServiceContext;
+
+const TICK: Duration = Duration::from_secs(60);
+
+/// Longest interval stored, in seconds, a year
+pub const MAX_INTERVAL: i64 = 365 * 24 * 60 * 60;
+
+/// Runs until the service context is dropped, scheduled syncs run one at a time
+pub fn start(ctx: Weak<ServiceContext>) {
+    tokio::spawn(async move {
+        // failed syncs don't move last_sync_at, wait an interval before trying again
+        let mut attempts: HashMap<i64, Timestamp> = HashMap::new();
+        loop {
+            tokio::time::sleep(TICK).await;
+            let Some(ctx) = ctx.upgrade() else {
+                break;
+            };
+            if let Err(e) = tick(&ctx, &mut attempts).await {
+                log::error!(target: "schedule", "{}", e);
+            }
+        }
+    });
+}
+
+async fn tick(
+    ctx: &ServiceContext,
+    attempts: &mut HashMap<i64, Timestamp>,
+) -> Result<(), anyhow::Error> {
+    let providers = sqlm::provider::Entity::find()
+        .filter(sqlm::provider::Column::SyncInterval.gt(0))
+        .all(&ctx.provider_context.db)
+        .await?;
+
+    let now = Timestamp::now();
+    let local = chrono::Local::now();
+    let minute = (local.hour() * 60 + local.minute()) as i32;
+    for p in providers {
+        let last = [p.last_sync_at, attempts.get(&p.provider_id).copied()]
+            .into_iter()
+            .flatten()
+            .map(|t| t.as_millis())
+            .max();
+        if !is_due(&p, last, now.as_millis(), minute) {
+            continue;
+        }
+        if p.sync_idle_only && global::get_playing(p.provider_id).is_some() {
+            continue;
+        }
+        if global::provider(p.provider_id).is_none() {
+            continue;
+        }
+
+        log::info!(target: "schedule", "sync provider {}", p.provider_id);
+        attempts.insert(p.provider_id, now);
+        let (tx, rx) = oneshot::channel::<i64>();
+        ctx.provider_context
+            .ev_sender
+            .send(CoreEvent::ProviderSync {
+                id: p.provider_id,
+                oneshot: Some(tx),
+            })
+            .await?;
+        if let Ok(task_id) = rx.await {
+            ctx.oper.wait(task_id).await;
+        }
+    }
+    Ok(())
+}
+
+fn is_due(p: &sqlm::provider::Model, last: Option<i64>, now: i64, minute: i32) -> bool {
+    if p.sync_interval <= 0 {
+        return false;
+    }
+    if let (Some(start), Some(end)) = (p.sync_quiet_start, p.sync_quiet_end) {
+        if in_quiet_hours(minute, start, end) {
+            return false;
+        }
+    }
+    match last {
+        Some(last) => now - last >= p.sync_interval.saturating_mul(1000),
+        None => true,
+    }
+}
+
+/// `start..end` in minutes, wrapping past midnight when `end < start`
+fn in_quiet_hours(minute: i32, start: i32, end: i32) -> bool {
+    match start <= end {
+        true => start <= minute && minute < end,
+        false => minute >= start || minute < end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_quiet_hours() {
+        assert!(in_quiet_hours(60, 0, 360));
+        assert!(!in_quiet_hours(360, 0, 360));
+        // 23:00 - 07:00
+        assert!(in_quiet_hours(23 * 60 + 30, 23 * 60, 7 * 60));
+        assert!(in_quiet_hours(3 * 60, 23 * 60, 7 * 60));
+        assert!(!in_quiet_hours(12 * 60, 23 * 60, 7 * 60));
+        assert!(!in_quiet_hours(12 * 60, 0, 0));
+    }
+
+    #[test]
+    fn test_is_due_huge_interval() {
+        let p: sqlm::provider::Model = serde_json::from_value(serde_json::json!({
+            "provider_id": 1,
+            "name": "p",
+            "type_": "local",
+            "base_url": "",
+            "auth_method": null,
+            "cookie": "",
+            "custom": "",
+            "edit_time": "2024-01-01T00:00:00Z",
+            "sync_interval": i64::MAX,
+        }))
+        .unwrap();
+        assert!(!is_due(&p, Some(0), 1000, 0));
+        assert!(is_due(&p, None, 1000, 0));
+    }
+}
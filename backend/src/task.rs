@@ -1,27 +1,66 @@
 use crossbeam_channel::{Receiver, Sender};
+use qcm_core::db::values::Timestamp;
+use qcm_core::provider::CancelToken;
 use std::collections::HashMap;
 use std::future::Future;
 use std::sync::{atomic::AtomicI64, Arc};
 use std::thread;
-use tokio::sync::oneshot;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
 use tokio::task::JoinHandle;
 
+/// time a cancelled task gets to stop by itself before it's dropped
+const CANCEL_GRACE: Duration = Duration::from_secs(10);
+
 enum TaskOneshotEvent {
     Cancel,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    Sync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    Cancelling,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: i64,
+    pub kind: TaskKind,
+    pub provider_id: Option<i64>,
+    pub start_at: Timestamp,
+    pub current: i64,
+    /// 0 when unknown
+    pub total: i64,
+    pub state: TaskState,
+}
+
 struct Task {
     id: i64,
     handle: JoinHandle<()>,
     sender: Option<oneshot::Sender<TaskOneshotEvent>>,
     name: Option<String>,
-    waiters: Vec<oneshot::Sender<()>>, // status: TaskStatus,
+    waiters: Vec<oneshot::Sender<()>>,
+    info: TaskInfo,
+    cancel: CancelToken,
 }
 
 enum TaskManagerEvent {
     Add(Task),
-    Progress,
-    Cancel { id: i64 },
+    Progress {
+        kind: TaskKind,
+        provider_id: i64,
+        current: i64,
+        total: i64,
+    },
+    Cancel { id: i64, tx: oneshot::Sender<bool> },
+    List { tx: oneshot::Sender<Vec<TaskInfo>> },
     End { id: i64 },
     Stop,
     Wait { id: i64, tx: oneshot::Sender<()> },
@@ -35,6 +74,7 @@ pub struct TaskOper {
 #[derive(Debug)]
 struct TaskManagerInner {
     id: AtomicI64,
+    status: broadcast::Sender<TaskInfo>,
 }
 
 #[derive(Debug, Clone)]
@@ -61,32 +101,75 @@ impl TaskManagerOper {
         let _ = rx.await;
     }
 
-    pub fn canel(&self, id: i64) {
-        let _ = self.sender.send(TaskManagerEvent::Cancel { id });
+    /// Ask the task to stop, false if it's not running
+    pub async fn cancel(&self, id: i64) -> bool {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.sender.send(TaskManagerEvent::Cancel { id, tx });
+        rx.await.unwrap_or(false)
+    }
+
+    pub async fn tasks(&self) -> Vec<TaskInfo> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.sender.send(TaskManagerEvent::List { tx });
+        rx.await.unwrap_or_default()
+    }
+
+    /// Update the progress of the running task of `kind` for the provider
+    pub fn progress(&self, kind: TaskKind, provider_id: i64, current: i64, total: i64) {
+        let _ = self.sender.send(TaskManagerEvent::Progress {
+            kind,
+            provider_id,
+            current,
+            total,
+        });
+    }
+
+    /// Status of tasks as they start, progress and end
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskInfo> {
+        self.inner.status.subscribe()
     }
 
     pub fn stop(&self) {
         let _ = self.sender.send(TaskManagerEvent::Stop);
     }
 
-    pub fn spawn<Fut>(&self, future: impl FnOnce(i64) -> Fut + Send + 'static) -> i64
+    /// Spawn a task, the future gets the task id and a token set on cancel
+    ///
+    /// A cancelled task is dropped when it doesn't end within `CANCEL_GRACE`
+    pub fn spawn<Fut>(
+        &self,
+        kind: TaskKind,
+        provider_id: Option<i64>,
+        future: impl FnOnce(i64, CancelToken) -> Fut + Send + 'static,
+    ) -> i64
     where
         Fut: Future<Output = ()> + Send,
     {
         let task_id = self.gen_id();
         let (tx, rx) = oneshot::channel();
         let mgr_tx = self.sender.clone();
+        let cancel = CancelToken::default();
+        // held until the task is added, so its `End` can't arrive before the `Add`
+        let (start_tx, start_rx) = oneshot::channel::<()>();
 
-        let handle = tokio::spawn(async move {
-            tokio::select! {
-                _ = future(task_id) => {},
-                one = rx => match one {
-                    Ok(_) => {}
-                    Err(_) => {}
+        let handle = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                let _ = start_rx.await;
+                let fut = future(task_id, cancel);
+                tokio::pin!(fut);
+                tokio::select! {
+                    _ = &mut fut => {},
+                    one = rx => match one {
+                        Ok(_) => {
+                            let _ = tokio::time::timeout(CANCEL_GRACE, fut).await;
+                        }
+                        Err(_) => {}
+                    }
                 }
-            }
 
-            let _ = mgr_tx.send(TaskManagerEvent::End { id: task_id });
+                let _ = mgr_tx.send(TaskManagerEvent::End { id: task_id });
+            }
         });
 
         let task = Task {
@@ -95,8 +178,19 @@ impl TaskManagerOper {
             sender: Some(tx),
             name: None,
             waiters: Vec::new(),
+            info: TaskInfo {
+                id: task_id,
+                kind,
+                provider_id,
+                start_at: Timestamp::now(),
+                current: 0,
+                total: 0,
+                state: TaskState::Running,
+            },
+            cancel,
         };
         self.add(task);
+        let _ = start_tx.send(());
         task_id
     }
 }
@@ -112,6 +206,7 @@ impl TaskManager {
         let (tx, rx) = crossbeam_channel::unbounded();
         let inner = Arc::new(TaskManagerInner {
             id: AtomicI64::new(0),
+            status: broadcast::channel(256).0,
         });
         (
             TaskManagerOper {
@@ -136,22 +231,41 @@ impl TaskManager {
         while let Ok(event) = self.receiver.recv() {
             match event {
                 TaskManagerEvent::Add(task) => {
+                    // no receiver is fine
+                    let _ = self.inner.status.send(task.info.clone());
                     self.tasks.insert(task.id, task);
                 }
-                TaskManagerEvent::Cancel { id } => {
-                    if let Some(t) = self.tasks.get_mut(&id) {
-                        if let Some(tx) = t.sender.take() {
-                            let _ = tx.send(TaskOneshotEvent::Cancel);
+                TaskManagerEvent::Cancel { id, tx } => {
+                    let found = match self.tasks.get_mut(&id) {
+                        Some(t) => {
+                            t.cancel.cancel();
+                            if let Some(tx) = t.sender.take() {
+                                let _ = tx.send(TaskOneshotEvent::Cancel);
+                                t.info.state = TaskState::Cancelling;
+                                let _ = self.inner.status.send(t.info.clone());
+                            }
+                            true
                         }
-                    }
+                        None => false,
+                    };
+                    let _ = tx.send(found);
+                }
+                TaskManagerEvent::List { tx } => {
+                    let mut tasks: Vec<_> = self.tasks.values().map(|t| t.info.clone()).collect();
+                    tasks.sort_by_key(|t| t.id);
+                    let _ = tx.send(tasks);
                 }
                 TaskManagerEvent::End { id } => {
-                    if let Some(t) = self.tasks.get_mut(&id) {
+                    if let Some(mut t) = self.tasks.remove(&id) {
                         while let Some(w) = t.waiters.pop() {
                             let _ = w.send(());
                         }
+                        t.info.state = match t.cancel.is_cancelled() {
+                            true => TaskState::Cancelled,
+                            false => TaskState::Finished,
+                        };
+                        let _ = self.inner.status.send(t.info.clone());
                     }
-                    self.tasks.remove(&id);
                 }
                 TaskManagerEvent::Wait { id, tx } => {
                     if let Some(t) = self.tasks.get_mut(&id) {
@@ -161,7 +275,23 @@ impl TaskManager {
                         let _ = tx.send(());
                     }
                 }
-                TaskManagerEvent::Progress => {}
+                TaskManagerEvent::Progress {
+                    kind,
+                    provider_id,
+                    current,
+                    total,
+                } => {
+                    let task = self.tasks.values_mut().find(|t| {
+                        t.info.kind == kind
+                            && t.info.provider_id == Some(provider_id)
+                            && t.info.state == TaskState::Running
+                    });
+                    if let Some(t) = task {
+                        t.info.current = current;
+                        t.info.total = total;
+                        let _ = self.inner.status.send(t.info.clone());
+                    }
+                }
                 TaskManagerEvent::Stop => {
                     break;
                 }
@@ -169,3 +299,23 @@ impl TaskManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fast_task_ends() {
+        let (oper, mgr) = TaskManager::new();
+        let thread = mgr.start();
+        for _ in 0..32 {
+            let id = oper.spawn(TaskKind::Sync, Some(1), |_, _| async {});
+            tokio::time::timeout(Duration::from_secs(5), oper.wait(id))
+                .await
+                .unwrap();
+        }
+        assert!(oper.tasks().await.is_empty());
+        oper.stop();
+        thread.join().unwrap();
+    }
+}
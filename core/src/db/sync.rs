@@ -6,6 +6,7 @@ use sea_orm::sea_query::OnConflict;
 use sea_orm::{prelude::DateTimeUtc, DatabaseTransaction, EntityTrait};
 use sea_orm::{prelude::*, QuerySelect, Statement};
 use sea_orm::{sea_query, sea_query::Alias, Condition};
+use sea_orm::{NotSet, QueryTrait, Set};
 
 pub async fn sync_drop_before(
     txn: &DatabaseTransaction,
@@ -22,13 +23,19 @@ pub async fn sync_drop_before(
         .exec(txn)
         .await?;
 
-    let ids: Vec<i64> = sqlm::library::Entity::find()
+    // disabled libraries are not synced, keep what they have
+    let (ids, disabled): (Vec<_>, Vec<_>) = sqlm::library::Entity::find()
         .select_only()
         .column(sqlm::library::Column::LibraryId)
+        .column(sqlm::library::Column::Enabled)
         .filter(sqlm::library::Column::ProviderId.eq(provider_id))
-        .into_tuple()
+        .into_tuple::<(i64, bool)>()
         .all(txn)
-        .await?;
+        .await?
+        .into_iter()
+        .partition(|(_, enabled)| *enabled);
+    let ids: Vec<i64> = ids.into_iter().map(|(id, _)| id).collect();
+    let disabled: Vec<i64> = disabled.into_iter().map(|(id, _)| id).collect();
 
     {
         let cte = CommonTableExpression::new()
@@ -161,12 +168,48 @@ pub async fn sync_drop_before(
     sqlm::item::Entity::delete_many()
         .filter(sqlm::item::Column::LastSyncAt.lt(now_ts))
         .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+        .filter(
+            Condition::any()
+                .add(sqlm::item::Column::LibraryId.is_null())
+                .add(sqlm::item::Column::LibraryId.is_not_in(disabled)),
+        )
         .exec(txn)
         .await?;
 
     Ok(())
 }
 
+/// Libraries of the provider to sync, call after upserting them
+pub async fn enabled_libraries<C: ConnectionTrait>(
+    db: &C,
+    provider_id: i64,
+) -> Result<Vec<sqlm::library::Model>, DbErr> {
+    sqlm::library::Entity::find()
+        .filter(sqlm::library::Column::ProviderId.eq(provider_id))
+        .filter(sqlm::library::Column::Enabled.eq(true))
+        .all(db)
+        .await
+}
+
+/// Delete items a delta sync reported as gone upstream
+pub async fn sync_delete(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+    item_type: sqlm::type_enum::ItemType,
+    native_ids: &[String],
+) -> Result<u64, DbErr> {
+    if native_ids.is_empty() {
+        return Ok(0);
+    }
+    let res = sqlm::item::Entity::delete_many()
+        .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+        .filter(sqlm::item::Column::Type.eq(item_type))
+        .filter(sqlm::item::Column::NativeId.is_in(native_ids.iter().cloned()))
+        .exec(txn)
+        .await?;
+    Ok(res.rows_affected)
+}
+
 fn select_id_from_native_id_map(
     library_id: i64,
     ids: Vec<(String, String)>,
@@ -388,6 +431,7 @@ where
             sqlm::item::Column::CreateAt,
             sqlm::item::Column::LastSyncAt,
             sqlm::item::Column::UpdateAt,
+            sqlm::item::Column::Fingerprint,
         ])
         .to_owned();
 
@@ -419,3 +463,133 @@ where
         Ok(None)
     }
 }
+
+/// Upsert remote playlists and the cached mixes showing them, return the remote_mix ids
+///
+/// The items of the remote mixes are allocated before, their ids are the remote_mix ids
+pub async fn sync_remote_mixes(
+    txn: &DatabaseTransaction,
+    models: Vec<sqlm::remote_mix::Model>,
+    exclude: &[sqlm::remote_mix::Column],
+) -> Result<Vec<i64>, DbErr> {
+    let out = {
+        let conflict = [sqlm::remote_mix::Column::Id];
+        let mut exclude = exclude.to_vec();
+        exclude.push(sqlm::remote_mix::Column::Id);
+        let iter = models
+            .iter()
+            .cloned()
+            .map(|m| -> sqlm::remote_mix::ActiveModel { m.into() });
+        DbChunkOper::<50>::insert_return_key(txn, iter, &conflict, &exclude).await?
+    };
+
+    let now = Timestamp::now();
+    let conflict = [sqlm::mix::Column::RemoteId];
+    let exclude = [
+        sqlm::mix::Column::Id,
+        sqlm::mix::Column::SortName,
+        sqlm::mix::Column::MixType,
+        sqlm::mix::Column::CreateAt,
+        sqlm::mix::Column::ContentUpdateAt,
+    ];
+    let iter = out
+        .iter()
+        .copied()
+        .zip(models.into_iter())
+        .map(|(remote_id, m)| sqlm::mix::ActiveModel {
+            name: Set(m.name),
+            remote_id: Set(Some(remote_id)),
+            description: Set(m.description.unwrap_or_default()),
+            track_count: Set(m.track_count),
+            create_at: Set(now),
+            update_at: Set(now),
+            mix_type: Set(sqlm::type_enum::MixType::Cache),
+            id: NotSet,
+            sort_name: NotSet,
+            content_update_at: NotSet,
+        });
+    DbChunkOper::<50>::insert(txn, iter, &conflict, &exclude).await?;
+    Ok(out)
+}
+
+/// Link songs to the cached mix of a remote playlist, in order
+pub async fn sync_remote_mix_song_ids(
+    txn: &DatabaseTransaction,
+    remote_mix_id: i64,
+    song_ids: Vec<i64>,
+) -> Result<(), DbErr> {
+    let mix_id: i64 = sqlm::mix::Entity::find()
+        .select_only()
+        .column(sqlm::mix::Column::Id)
+        .filter(sqlm::mix::Column::RemoteId.eq(Some(remote_mix_id)))
+        .into_tuple()
+        .one(txn)
+        .await?
+        .ok_or(DbErr::RecordNotFound(format!(
+            "no mix of remote mix {}",
+            remote_mix_id
+        )))?;
+
+    let now = Timestamp::now();
+    sqlm::mix::Entity::update(sqlm::mix::ActiveModel {
+        id: Set(mix_id),
+        content_update_at: Set(now),
+        ..Default::default()
+    })
+    .exec(txn)
+    .await?;
+
+    let conflict = [
+        sqlm::rel_mix_song::Column::MixId,
+        sqlm::rel_mix_song::Column::SongId,
+    ];
+    let exclude = [sqlm::rel_mix_song::Column::Id];
+    let iter = song_ids
+        .into_iter()
+        .enumerate()
+        .map(|(i, song_id)| sqlm::rel_mix_song::ActiveModel {
+            mix_id: Set(mix_id),
+            song_id: Set(song_id),
+            order_idx: Set(i as i64),
+            update_at: Set(now),
+            id: NotSet,
+        });
+    DbChunkOper::<50>::insert(txn, iter, &conflict, &exclude).await?;
+    Ok(())
+}
+
+/// Unlink songs of the cached mix of a remote playlist not synced since `before`
+pub async fn sync_drop_remote_mix_songs_before(
+    txn: &DatabaseTransaction,
+    remote_mix_id: i64,
+    before: Timestamp,
+) -> Result<(), DbErr> {
+    let mix_ids = sqlm::mix::Entity::find()
+        .select_only()
+        .column(sqlm::mix::Column::Id)
+        .filter(sqlm::mix::Column::RemoteId.eq(Some(remote_mix_id)))
+        .into_query();
+    sqlm::rel_mix_song::Entity::delete_many()
+        .filter(sqlm::rel_mix_song::Column::MixId.in_subquery(mix_ids))
+        .filter(sqlm::rel_mix_song::Column::UpdateAt.lt(before))
+        .exec(txn)
+        .await?;
+    Ok(())
+}
+
+/// The remote playlist item behind a local mix, if it has one
+pub async fn remote_mix_item<DB>(db: &DB, mix_id: i64) -> Result<Option<sqlm::item::Model>, DbErr>
+where
+    DB: sea_orm::ConnectionTrait,
+{
+    let remote_id: Option<Option<i64>> = sqlm::mix::Entity::find_by_id(mix_id)
+        .select_only()
+        .column(sqlm::mix::Column::RemoteId)
+        .into_tuple()
+        .one(db)
+        .await?;
+    match remote_id.flatten() {
+        Some(id) => sqlm::item::Entity::find_by_id(id).one(db).await,
+        None => Ok(None),
+    }
+}
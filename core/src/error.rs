@@ -35,6 +35,8 @@ pub enum ProviderError {
     NotFound,
     #[error("Not Implemented")]
     NotImplemented,
+    #[error("Cancelled")]
+    Cancelled,
     #[error("Database error: {0}")]
     Db(#[from] sea_orm::DbErr),
     #[error("Infallible")]
@@ -29,6 +29,36 @@ pub enum SyncState {
     DBError = 4,
     IOError = 5,
     UknownError = 6,
+    Cancelled = 7,
+}
+
+/// What a sync is working on, also names the totals of `SyncCommit::SetTotal`
+#[derive(
+    Copy,
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    Display,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    EnumString,
+    TryFromPrimitive,
+    IntoPrimitive,
+)]
+#[strum(ascii_case_insensitive)]
+#[repr(i32)]
+pub enum SyncPhase {
+    #[default]
+    Unspecified = 0,
+    Libraries = 1,
+    Albums = 2,
+    Artists = 3,
+    Songs = 4,
+    Mixes = 5,
+    Images = 6,
 }
 
 pub enum SyncCommit {
@@ -36,12 +66,23 @@ pub enum SyncCommit {
     AddArtist(i32),
     AddSong(i32),
     SetState(SyncState),
+    SetPhase(SyncPhase),
+    /// expected count, only albums, artists and songs are counted
+    SetTotal(SyncPhase, i32),
+    /// non-fatal, the sync goes on
+    Warn(String),
 }
 pub enum Event {
+    /// from the saved cursors when the provider has them
     ProviderSync {
         id: i64,
         oneshot: Option<oneshot::Sender<i64>>,
     },
+    /// ignore saved cursors
+    ProviderFullSync {
+        id: i64,
+        oneshot: Option<oneshot::Sender<i64>>,
+    },
     SyncCommit {
         id: i64,
         commit: SyncCommit,
@@ -56,6 +97,7 @@ fn sync_state_from_provider_error(e: &crate::error::ProviderError) -> SyncState
         ProviderError::Request(_) => SyncState::NetworkError,
         ProviderError::Db(_) => SyncState::DBError,
         ProviderError::IO(_) => SyncState::IOError,
+        ProviderError::Cancelled => SyncState::Cancelled,
         ProviderError::WithContext { err, .. } => sync_state_from_provider_error(err),
         ProviderError::External(err) => {
             if let Some(p_err) = err.downcast_ref::<ProviderError>() {
@@ -74,6 +116,12 @@ fn sync_state_from_provider_error(e: &crate::error::ProviderError) -> SyncState
     }
 }
 
+impl From<&crate::error::ProviderError> for SyncState {
+    fn from(e: &crate::error::ProviderError) -> Self {
+        sync_state_from_provider_error(e)
+    }
+}
+
 impl From<crate::error::ProviderError> for SyncState {
     fn from(e: crate::error::ProviderError) -> Self {
         sync_state_from_provider_error(&e)
@@ -23,6 +23,10 @@ pub struct Model {
     #[serde(default = "Timestamp::now")]
     #[sea_orm(default_expr = "Timestamp::now_expr()")]
     pub last_sync_at: Timestamp,
+
+    /// provider defined, used to detect changes without refetching (e.g. mtime/size of a file)
+    #[serde(default)]
+    pub fingerprint: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
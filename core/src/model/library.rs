@@ -1,3 +1,4 @@
+use super::util::default_true;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +13,12 @@ pub struct Model {
     #[serde(default = "chrono::Utc::now")]
     #[sea_orm(default_expr = "Expr::current_timestamp()")]
     pub edit_time: DateTimeUtc,
+    /// opaque, kept by the provider for delta syncs
+    #[serde(default)]
+    pub sync_cursor: Option<String>,
+    /// disabled libraries are not synced and hidden unless asked for by id
+    #[serde(default = "default_true")]
+    pub enabled: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
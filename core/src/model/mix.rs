@@ -133,6 +133,7 @@ pub async fn append_songs(
             Column::TrackCount,
             Expr::val(count).add(Expr::col(Column::TrackCount)).into(),
         )
+        .filter(Column::Id.eq(mix_id))
         .exec(db)
         .await?;
 
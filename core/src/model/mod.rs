@@ -15,6 +15,7 @@ pub mod remote_mix;
 //pub mod program;
 //pub mod radio;
 pub mod song;
+pub mod song_loudness;
 
 pub mod cache;
 pub mod cache_block;
@@ -27,3 +28,5 @@ pub mod rel_mix_song;
 pub mod rel_song_artist;
 
 pub mod dynamic;
+pub mod outbox;
+pub mod api_key;
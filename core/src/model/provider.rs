@@ -1,3 +1,4 @@
+use crate::db::values::Timestamp;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -15,6 +16,21 @@ pub struct Model {
     pub custom: String,
     #[sea_orm(default_expr = "Expr::current_timestamp()")]
     pub edit_time: DateTimeUtc,
+
+    /// seconds between automatic syncs, 0 for never
+    #[serde(default)]
+    pub sync_interval: i64,
+    /// skip while a song of the provider is playing
+    #[serde(default)]
+    pub sync_idle_only: bool,
+    /// no automatic sync between these, minutes from local midnight
+    #[serde(default)]
+    pub sync_quiet_start: Option<i32>,
+    #[serde(default)]
+    pub sync_quiet_end: Option<i32>,
+    /// last successful sync
+    #[serde(default)]
+    pub last_sync_at: Option<Timestamp>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
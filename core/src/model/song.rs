@@ -28,6 +28,27 @@ pub struct Model {
     pub publish_time: Option<Timestamp>,
     #[serde(default)]
     pub added_at: Option<Timestamp>,
+
+    /// native_id of the file holding this song, when only a part of it (e.g. a CUE track)
+    #[serde(default)]
+    pub source_id: Option<String>,
+    /// milliseconds within the source
+    #[serde(default)]
+    pub start_offset: Option<i64>,
+    /// milliseconds within the source, none for until the end
+    #[serde(default)]
+    pub end_offset: Option<i64>,
+
+    /// ReplayGain in dB
+    #[serde(default)]
+    pub track_gain: Option<f64>,
+    /// linear sample peak, 1.0 for full scale
+    #[serde(default)]
+    pub track_peak: Option<f64>,
+    #[serde(default)]
+    pub album_gain: Option<f64>,
+    #[serde(default)]
+    pub album_peak: Option<f64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -40,6 +61,8 @@ pub enum Relation {
     Item,
     #[sea_orm(has_one = "super::dynamic::Entity")]
     Dynamic,
+    #[sea_orm(has_one = "super::song_loudness::Entity")]
+    Loudness,
     #[sea_orm(
         belongs_to = "super::album::Entity",
         from = "Column::AlbumId",
@@ -66,6 +89,12 @@ impl Related<super::dynamic::Entity> for Entity {
     }
 }
 
+impl Related<super::song_loudness::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Loudness.def()
+    }
+}
+
 impl Related<super::album::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Album.def()
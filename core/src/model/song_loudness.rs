@@ -0,0 +1,40 @@
+use crate::db::values::Timestamp;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// EBU R128 measurement of a song, analyzed by the backend from cached audio
+#[derive(Clone, Debug, Default, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "song_loudness")]
+pub struct Model {
+    /// song id
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: i64,
+    /// integrated loudness in LUFS
+    pub loudness: f64,
+    /// true peak in dBTP
+    pub true_peak: f64,
+    /// cache source the measurement is from
+    pub source_key: String,
+
+    #[serde(default = "Timestamp::now")]
+    #[sea_orm(default_expr = "Timestamp::now_expr()")]
+    pub update_at: Timestamp,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::song::Entity",
+        from = "Column::Id",
+        to = "super::song::Column::Id"
+    )]
+    Song,
+}
+
+impl Related<super::song::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Song.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,4 +1,5 @@
 use crate::{
+    db::values::Timestamp,
     error::ProviderError,
     event::Event,
     http,
@@ -14,6 +15,9 @@ use crate::{
 use reqwest::Response;
 use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
@@ -46,6 +50,38 @@ pub enum AuthMethod {
     Qr { key: String },
 }
 
+/// Set when the task running a provider call is cancelled
+///
+/// Long calls like `sync` should check it between steps and return `ProviderError::Cancelled`
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+tokio::task_local! {
+    static CANCEL: CancelToken;
+}
+
+impl CancelToken {
+    /// Run `fut` as the task of this token, `Context::cancel` in it returns the token
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        CANCEL.scope(self, fut).await
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<(), ProviderError> {
+        match self.is_cancelled() {
+            true => Err(ProviderError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Context {
     pub db: DatabaseConnection,
@@ -53,6 +89,25 @@ pub struct Context {
     pub ev_sender: Sender<Event>,
 }
 
+impl Context {
+    pub fn new(
+        db: DatabaseConnection,
+        cache_db: DatabaseConnection,
+        ev_sender: Sender<Event>,
+    ) -> Self {
+        Self {
+            db,
+            cache_db,
+            ev_sender,
+        }
+    }
+
+    /// The token of the task running this call, one that is never cancelled outside of a task
+    pub fn cancel(&self) -> CancelToken {
+        CANCEL.try_with(|c| c.clone()).unwrap_or_default()
+    }
+}
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct AuthInfo {
     pub server_url: String,
@@ -92,6 +147,47 @@ pub enum HomeBlockContent {
     Songs(Vec<SongModel>),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackAction {
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Prev,
+}
+
+/// Where a delta sync can start from, empty for a full sync
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncSince {
+    /// end of the last successful sync
+    pub last_sync_at: Option<Timestamp>,
+    /// opaque cursors saved by the provider, by library native id
+    pub cursors: HashMap<String, String>,
+}
+
+/// Returned by `Provider::sync`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncResult {
+    /// cursors by library native id, saved only when the sync succeeded
+    pub cursors: HashMap<String, String>,
+}
+
+/// Result of a remote search, one page per requested type
+///
+/// Models carry db ids, the provider upserts them as items without a library
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchResult {
+    pub albums: Vec<AlbumModel>,
+    pub album_total: i32,
+    pub artists: Vec<ArtistModel>,
+    pub artist_total: i32,
+    pub songs: Vec<SongModel>,
+    pub song_total: i32,
+}
+
 /// Creator for provider
 ///
 /// # Parameters
@@ -100,6 +196,24 @@ pub enum HomeBlockContent {
 ///
 pub type Creator = dyn Fn(Option<i64>, &str, &str) -> Result<Arc<dyn Provider>> + Send + Sync;
 
+/// What a provider supports beyond the required calls, for clients to hide the rest
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderCapabilities {
+    /// item types that can be favorited
+    pub favorite: Vec<ItemType>,
+    pub qr_auth: bool,
+    pub home: bool,
+    /// `get_queue_next` for radio like mixes
+    pub radio_queue: bool,
+    /// search on the remote side
+    pub search: bool,
+    /// playlists created, renamed, deleted or edited here are written back
+    pub playlist_write: bool,
+    pub scrobble: bool,
+    pub lyrics: bool,
+}
+
 #[derive(Clone)]
 pub struct ProviderMeta {
     pub type_name: String,
@@ -109,6 +223,7 @@ pub struct ProviderMeta {
     pub is_script: bool,
     pub has_server_url: bool,
     pub auth_types: Vec<i32>,
+    pub capabilities: ProviderCapabilities,
 }
 
 impl ProviderMeta {
@@ -121,8 +236,18 @@ impl ProviderMeta {
             is_script: false,
             has_server_url: true,
             auth_types: auth_types.to_vec(),
+            capabilities: ProviderCapabilities::default(),
         }
     }
+
+    pub fn with_capabilities(mut self, capabilities: ProviderCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn capabilities(&self) -> &ProviderCapabilities {
+        &self.capabilities
+    }
 }
 
 pub trait ProviderSession {
@@ -149,6 +274,19 @@ pub trait Provider: ProviderCommon + ProviderSession + Send + Sync {
     async fn check(&self, ctx: &Context) -> Result<(), ProviderError>;
     async fn auth(&self, ctx: &Context, info: &AuthInfo) -> Result<AuthResult, ProviderError>;
     async fn sync(&self, ctx: &Context) -> Result<(), ProviderError>;
+    /// Sync from where the last one ended, for providers with a change feed
+    ///
+    /// Upsert what changed and remove deleted items with `db::sync::sync_delete`, instead of
+    /// `db::sync::sync_drop_before`. Falls back to a full `sync`
+    async fn sync_since(
+        &self,
+        ctx: &Context,
+        since: &SyncSince,
+    ) -> Result<SyncResult, ProviderError> {
+        let _ = since;
+        self.sync(ctx).await?;
+        Ok(SyncResult::default())
+    }
     async fn sync_item(&self, ctx: &Context, item: ItemModel) -> Result<(), ProviderError>;
     async fn get_queue_next(
         &self,
@@ -188,6 +326,12 @@ pub trait Provider: ProviderCommon + ProviderSession + Send + Sync {
 
     async fn subtitle(&self, item_id: &str) -> Result<Subtitle, ProviderError>;
 
+    /// `subtitle` with the database at hand, for songs that need a lookup to find their source
+    async fn song_subtitle(&self, ctx: &Context, item_id: &str) -> Result<Subtitle, ProviderError> {
+        let _ = ctx;
+        self.subtitle(item_id).await
+    }
+
     async fn home_blocks(&self, ctx: &Context) -> Result<Vec<HomeBlock>, ProviderError> {
         let _ = ctx;
         Err(ProviderError::NotImplemented)
@@ -203,6 +347,75 @@ pub trait Provider: ProviderCommon + ProviderSession + Send + Sync {
         let _ = (ctx, block_id, page, page_size);
         Err(ProviderError::NotImplemented)
     }
+
+    /// Search the remote catalogue, `types` are Album, Artist or Song
+    async fn search(
+        &self,
+        ctx: &Context,
+        query: &str,
+        types: &[ItemType],
+        page: i32,
+        page_size: i32,
+    ) -> Result<SearchResult, ProviderError> {
+        let _ = (ctx, query, types, page, page_size);
+        Err(ProviderError::NotImplemented)
+    }
+
+    /// Create a remote playlist, return its native id
+    async fn create_mix(&self, ctx: &Context, name: &str) -> Result<String, ProviderError> {
+        let _ = (ctx, name);
+        Err(ProviderError::NotImplemented)
+    }
+
+    async fn rename_mix(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        name: &str,
+    ) -> Result<(), ProviderError> {
+        let _ = (ctx, native_id, name);
+        Err(ProviderError::NotImplemented)
+    }
+
+    async fn delete_mix(&self, ctx: &Context, native_id: &str) -> Result<(), ProviderError> {
+        let _ = (ctx, native_id);
+        Err(ProviderError::NotImplemented)
+    }
+
+    async fn mix_add_songs(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        song_native_ids: &[String],
+    ) -> Result<(), ProviderError> {
+        let _ = (ctx, native_id, song_native_ids);
+        Err(ProviderError::NotImplemented)
+    }
+
+    async fn mix_remove_songs(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        song_native_ids: &[String],
+    ) -> Result<(), ProviderError> {
+        let _ = (ctx, native_id, song_native_ids);
+        Err(ProviderError::NotImplemented)
+    }
+
+    /// Tell the provider a song was played, paused or skipped
+    ///
+    /// Called from a retry queue, `timestamp` is when it happened and `position` is in milliseconds
+    async fn report_playback(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        action: PlaybackAction,
+        position: i64,
+        timestamp: Timestamp,
+    ) -> Result<(), ProviderError> {
+        let _ = (ctx, native_id, action, position, timestamp);
+        Err(ProviderError::NotImplemented)
+    }
 }
 
 struct ProviderCommonDataInner {
@@ -0,0 +1,239 @@
+use encoding_rs::Encoding;
+
+use crate::tag::parse_year;
+
+#[derive(Debug, Default)]
+pub struct CueTrack {
+    pub number: i32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// milliseconds, from `INDEX 01`
+    pub start: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct CueFile {
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueFile {
+    /// (start, end) of the nth track in milliseconds, the last one has no end
+    pub fn offsets(&self, n: usize) -> (i64, Option<i64>) {
+        let start = self.tracks[n].start;
+        let end = self.tracks.get(n + 1).map(|t| t.start);
+        (start, end)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub files: Vec<CueFile>,
+}
+
+/// "MM:SS:FF", 75 frames per second
+fn parse_time(value: &str) -> Option<i64> {
+    let mut parts = value.split(':').map(|p| p.trim().parse::<i64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((m * 60 + s) * 1000 + f * 1000 / 75)
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// `FILE "name with spaces.flac" WAVE`
+fn file_name(args: &str) -> String {
+    let args = args.trim();
+    match args.strip_prefix('"') {
+        Some(rest) => rest.split('"').next().unwrap_or_default().to_string(),
+        None => args
+            .rsplit_once(' ')
+            .map(|(n, _)| n)
+            .unwrap_or(args)
+            .to_string(),
+    }
+}
+
+fn non_empty(value: String) -> Option<String> {
+    match value.is_empty() {
+        true => None,
+        false => Some(value),
+    }
+}
+
+/// Decode a cue sheet, they are often not utf8 but a legacy codepage like shift-jis or gbk,
+/// which is guessed from the content
+pub fn decode_cue(data: &[u8]) -> String {
+    if let Some((encoding, bom)) = Encoding::for_bom(data) {
+        return encoding
+            .decode_without_bom_handling(&data[bom..])
+            .0
+            .into_owned();
+    }
+    if let Ok(text) = std::str::from_utf8(data) {
+        return text.to_string();
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(data, true);
+    let encoding = detector.guess(None, false);
+    encoding.decode_without_bom_handling(data).0.into_owned()
+}
+
+pub fn parse_cue(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+
+    for line in text.lines() {
+        let line = line.trim();
+        let (cmd, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        match cmd.to_ascii_uppercase().as_str() {
+            "FILE" => sheet.files.push(CueFile {
+                name: file_name(args),
+                tracks: Vec::new(),
+            }),
+            "TRACK" => {
+                let Some(file) = sheet.files.last_mut() else {
+                    continue;
+                };
+                let mut args = args.split_whitespace();
+                let number = args.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+                // only audio tracks are songs
+                if args.next().map(|t| t.eq_ignore_ascii_case("AUDIO")) != Some(true) {
+                    continue;
+                }
+                file.tracks.push(CueTrack {
+                    number,
+                    ..Default::default()
+                });
+            }
+            "INDEX" => {
+                let mut args = args.split_whitespace();
+                if args.next().and_then(|n| n.parse::<i32>().ok()) != Some(1) {
+                    continue;
+                }
+                let track = sheet.files.last_mut().and_then(|f| f.tracks.last_mut());
+                if let (Some(track), Some(start)) = (track, args.next().and_then(parse_time)) {
+                    track.start = start;
+                }
+            }
+            "TITLE" | "PERFORMER" => {
+                let value = non_empty(unquote(args));
+                let is_title = cmd.eq_ignore_ascii_case("TITLE");
+                match sheet.files.last_mut().and_then(|f| f.tracks.last_mut()) {
+                    Some(track) => match is_title {
+                        true => track.title = value,
+                        false => track.performer = value,
+                    },
+                    None => match is_title {
+                        true => sheet.title = value,
+                        false => sheet.performer = value,
+                    },
+                }
+            }
+            "REM" => {
+                let (key, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let value = unquote(value);
+                match key.to_ascii_uppercase().as_str() {
+                    "GENRE" => sheet.genre = non_empty(value),
+                    "DATE" => sheet.year = parse_year(&value),
+                    "DISCNUMBER" => sheet.disc_number = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    sheet.files.retain(|f| !f.tracks.is_empty());
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = r#"REM GENRE "Progressive Rock"
+REM DATE 1973
+PERFORMER "Pink Floyd"
+TITLE "The Dark Side of the Moon"
+FILE "The Dark Side of the Moon.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Speak to Me"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Breathe"
+    PERFORMER "Floyd"
+    INDEX 00 01:05:10
+    INDEX 01 01:07:15
+"#;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("01:07:15"), Some(67200));
+        assert_eq!(parse_time("01:07"), None);
+    }
+
+    #[test]
+    fn test_parse_cue() {
+        let sheet = parse_cue(SHEET);
+        assert_eq!(sheet.title.as_deref(), Some("The Dark Side of the Moon"));
+        assert_eq!(sheet.performer.as_deref(), Some("Pink Floyd"));
+        assert_eq!(sheet.genre.as_deref(), Some("Progressive Rock"));
+        assert_eq!(sheet.year, Some(1973));
+        assert_eq!(sheet.files.len(), 1);
+
+        let file = &sheet.files[0];
+        assert_eq!(file.name, "The Dark Side of the Moon.flac");
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tracks[1].number, 2);
+        assert_eq!(file.tracks[1].title.as_deref(), Some("Breathe"));
+        assert_eq!(file.tracks[1].performer.as_deref(), Some("Floyd"));
+        assert_eq!(file.tracks[0].performer, None);
+        assert_eq!(file.offsets(0), (0, Some(67200)));
+        assert_eq!(file.offsets(1), (67200, None));
+
+        let legacy = [
+            (encoding_rs::SHIFT_JIS, "坂本龍一", "戦場のメリークリスマス"),
+            (encoding_rs::GBK, "王菲", "只爱陌生人"),
+            (
+                encoding_rs::WINDOWS_1252,
+                "Édith Piaf",
+                "La Vie en rose, Hymne à l'amour",
+            ),
+        ];
+        for (encoding, performer, title) in legacy {
+            let text = format!(
+                "PERFORMER \"{performer}\"\nTITLE \"{title}\"\nFILE \"a.flac\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"{title}\"\n    INDEX 01 00:00:00\n"
+            );
+            let (data, _, _) = encoding.encode(&text);
+            let sheet = parse_cue(&decode_cue(&data));
+            assert_eq!(
+                sheet.performer.as_deref(),
+                Some(performer),
+                "{}",
+                encoding.name()
+            );
+            assert_eq!(sheet.title.as_deref(), Some(title), "{}", encoding.name());
+        }
+
+        let bom = [b"\xEF\xBB\xBF", SHEET.as_bytes()].concat();
+        assert_eq!(decode_cue(&bom), SHEET);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(r#""a b.wav" WAVE"#), "a b.wav");
+        assert_eq!(file_name("a.wav WAVE"), "a.wav");
+    }
+}
@@ -0,0 +1,498 @@
+use crate::scan::{is_audio, LibraryScan, ScannedPlaylist, SongSource};
+use qcm_core::db::sync::{
+    allocate_items, sync_album_artist_ids, sync_drop_remote_mix_songs_before,
+    sync_remote_mix_song_ids, sync_remote_mixes, sync_song_album_ids, sync_song_artist_ids,
+};
+use qcm_core::db::values::Timestamp;
+use qcm_core::db::{DbChunkOper, DbOper};
+use qcm_core::error::ProviderError;
+use qcm_core::model::{self as sqlm, type_enum::ItemType};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::*;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// max (native_id, native_id) pairs per relation statement
+const REL_CHUNK: usize = 500;
+
+pub async fn upsert_library(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+    dir: &Path,
+) -> Result<i64, ProviderError> {
+    let model = sqlm::library::ActiveModel {
+        library_id: NotSet,
+        name: Set(dir
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or(dir.to_string_lossy().to_string())),
+        provider_id: Set(provider_id),
+        native_id: Set(dir.to_string_lossy().to_string()),
+        edit_time: Set(chrono::Utc::now()),
+        sync_cursor: NotSet,
+        enabled: NotSet,
+    };
+    let conflict = [
+        sqlm::library::Column::ProviderId,
+        sqlm::library::Column::NativeId,
+    ];
+    let exclude = [
+        sqlm::library::Column::LibraryId,
+        sqlm::library::Column::SyncCursor,
+        sqlm::library::Column::Enabled,
+    ];
+    match DbOper::insert_return_key(txn, [model], &conflict, &exclude).await? {
+        TryInsertResult::Inserted(ids) => ids
+            .first()
+            .copied()
+            .ok_or(ProviderError::Db(DbErr::RecordNotInserted)),
+        _ => Err(ProviderError::Db(DbErr::RecordNotInserted)),
+    }
+}
+
+fn new_items<'a, I>(
+    provider_id: i64,
+    library_id: i64,
+    item_type: ItemType,
+    native_ids: I,
+) -> impl Iterator<Item = sqlm::item::ActiveModel> + 'a
+where
+    I: Iterator<Item = &'a String> + 'a,
+{
+    let now = Timestamp::now();
+    native_ids.map(move |n| sqlm::item::ActiveModel {
+        native_id: Set(n.clone()),
+        library_id: Set(Some(library_id)),
+        provider_id: Set(provider_id),
+        r#type: Set(item_type),
+        update_at: Set(now),
+        last_sync_at: Set(now),
+        ..Default::default()
+    })
+}
+
+fn year_to_timestamp(year: Option<i32>) -> Option<Timestamp> {
+    use chrono::TimeZone;
+    year.and_then(|y| chrono::Utc.with_ymd_and_hms(y, 1, 1, 0, 0, 0).single())
+        .map(|t| Timestamp::from_millis(t.timestamp_millis()))
+}
+
+/// Write a scanned library, return (artist, album, song) count
+pub async fn write_scan(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+    library_id: i64,
+    scan: LibraryScan,
+) -> Result<(i32, i32, i32), ProviderError> {
+    let LibraryScan {
+        songs,
+        albums,
+        artists,
+        ..
+    } = scan;
+
+    // artists
+    {
+        let ids = allocate_items(
+            txn,
+            new_items(provider_id, library_id, ItemType::Artist, artists.keys()),
+        )
+        .await?;
+        let iter = ids
+            .into_iter()
+            .zip(artists.values())
+            .map(|(id, a)| sqlm::artist::ActiveModel {
+                id: Set(id),
+                name: Set(a.name.clone()),
+                sort_name: Set(None),
+                description: Set(String::new()),
+                album_count: Set(a.album_count),
+                music_count: Set(a.music_count),
+                added_at: NotSet,
+            })
+            .collect::<Vec<_>>();
+        let conflict = [sqlm::artist::Column::Id];
+        let exclude = [sqlm::artist::Column::Id, sqlm::artist::Column::AddedAt];
+        DbChunkOper::<50>::insert(txn, iter, &conflict, &exclude).await?;
+    }
+
+    // albums
+    {
+        let ids = allocate_items(
+            txn,
+            new_items(provider_id, library_id, ItemType::Album, albums.keys()),
+        )
+        .await?;
+        let iter = ids
+            .iter()
+            .copied()
+            .zip(albums.values())
+            .map(|(id, a)| sqlm::album::ActiveModel {
+                id: Set(id),
+                name: Set(a.name.clone()),
+                sort_name: Set(None),
+                track_count: Set(a.track_count),
+                disc_count: Set(a.disc_count),
+                r#type: Set(sqlm::type_enum::AlbumType::Album),
+                duration: Set(a.duration),
+                language: Set(None),
+                description: Set(None),
+                company: Set(None),
+                publish_time: Set(year_to_timestamp(a.year)),
+                added_at: Set(a.added_at),
+            })
+            .collect::<Vec<_>>();
+        // only changed songs are scanned, the stored added_at covers the others
+        let conflict = [sqlm::album::Column::Id];
+        let exclude = [sqlm::album::Column::Id, sqlm::album::Column::AddedAt];
+        DbChunkOper::<50>::insert(txn, iter, &conflict, &exclude).await?;
+
+        let now = chrono::Utc::now();
+        let (folder_images, embedded_images): (Vec<_>, Vec<_>) = ids
+            .iter()
+            .zip(albums.values())
+            .flat_map(|(id, a)| {
+                a.images
+                    .iter()
+                    .map(move |(t, native_id)| sqlm::image::ActiveModel {
+                        id: NotSet,
+                        item_id: Set(*id),
+                        image_type: Set(*t),
+                        native_id: Set(Some(native_id.clone())),
+                        db: Set(None),
+                        fresh: Set(String::new()),
+                        timestamp: Set(now),
+                    })
+            })
+            .partition(|m| match &m.native_id {
+                Set(Some(n)) => !is_audio(Path::new(n)),
+                _ => true,
+            });
+        // a folder image is meant for the album, an embedded one of a changed song
+        // only fills in a missing type
+        let conflict = [sqlm::image::Column::ItemId, sqlm::image::Column::ImageType];
+        let exclude = [sqlm::image::Column::Id];
+        DbChunkOper::<50>::insert(txn, folder_images, &conflict, &exclude).await?;
+        DbChunkOper::<50>::insert_on(
+            txn,
+            embedded_images,
+            OnConflict::columns(conflict).do_nothing().to_owned(),
+        )
+        .await?;
+    }
+
+    // songs
+    {
+        let ids = allocate_items(
+            txn,
+            new_items(
+                provider_id,
+                library_id,
+                ItemType::Song,
+                songs.iter().map(|s| &s.native_id),
+            )
+            .zip(songs.iter())
+            .map(|(mut a, s)| {
+                a.fingerprint = Set(Some(s.fingerprint.clone()));
+                a
+            })
+            .collect::<Vec<_>>(),
+        )
+        .await?;
+        let iter = ids
+            .into_iter()
+            .zip(songs.iter())
+            .map(|(id, s)| sqlm::song::ActiveModel {
+                id: Set(id),
+                name: Set(s.name.clone()),
+                sort_name: Set(None),
+                album_id: NotSet,
+                track_number: Set(s.track_number),
+                disc_number: Set(s.disc_number),
+                duration: Set(s.duration),
+                can_play: Set(true),
+                popularity: Set(0.0),
+                tags: Set(serde_json::Value::from(s.genres.clone())),
+                publish_time: Set(year_to_timestamp(s.year)),
+                added_at: Set(s.added_at),
+                source_id: Set(s.source.as_ref().map(|x| x.source_id.clone())),
+                start_offset: Set(s.source.as_ref().map(|x| x.start)),
+                end_offset: Set(s.source.as_ref().and_then(|x| x.end)),
+                track_gain: Set(s.replay_gain.track_gain),
+                track_peak: Set(s.replay_gain.track_peak),
+                album_gain: Set(s.replay_gain.album_gain),
+                album_peak: Set(s.replay_gain.album_peak),
+            })
+            .collect::<Vec<_>>();
+        let conflict = [sqlm::song::Column::Id];
+        let exclude = [sqlm::song::Column::Id, sqlm::song::Column::AlbumId];
+        DbChunkOper::<50>::insert(txn, iter, &conflict, &exclude).await?;
+    }
+
+    // relations
+    {
+        let song_album: Vec<(String, String)> = songs
+            .iter()
+            .map(|s| (s.native_id.clone(), s.album_id.clone()))
+            .collect();
+        let song_artist: Vec<(String, String)> = songs
+            .iter()
+            .flat_map(|s| {
+                s.artist_ids
+                    .iter()
+                    .map(|a| (s.native_id.clone(), a.clone()))
+            })
+            .collect();
+        let album_artist: Vec<(String, String)> = albums
+            .iter()
+            .flat_map(|(id, a)| a.artist_ids.iter().map(|ar| (id.clone(), ar.clone())))
+            .collect();
+
+        for chunk in song_album.chunks(REL_CHUNK) {
+            sync_song_album_ids(txn, library_id, chunk.to_vec()).await?;
+        }
+        for chunk in song_artist.chunks(REL_CHUNK) {
+            sync_song_artist_ids(txn, library_id, chunk.to_vec()).await?;
+        }
+        for chunk in album_artist.chunks(REL_CHUNK) {
+            sync_album_artist_ids(txn, library_id, chunk.to_vec()).await?;
+        }
+    }
+
+    Ok((
+        artists.len() as i32,
+        albums.len() as i32,
+        songs.len() as i32,
+    ))
+}
+
+/// Song ids of playlist entries, an entry is a song file or a file split by a cue sheet
+async fn playlist_song_ids(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+    entries: Vec<String>,
+) -> Result<HashMap<String, Vec<i64>>, ProviderError> {
+    let mut out: HashMap<String, Vec<i64>> = HashMap::new();
+    for chunk in entries.chunks(REL_CHUNK) {
+        let rows: Vec<(String, i64)> = sqlm::item::Entity::find()
+            .select_only()
+            .column(sqlm::item::Column::NativeId)
+            .column(sqlm::item::Column::Id)
+            .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+            .filter(sqlm::item::Column::Type.eq(ItemType::Song))
+            .filter(sqlm::item::Column::NativeId.is_in(chunk.to_vec()))
+            .into_tuple()
+            .all(txn)
+            .await?;
+        for (native_id, id) in rows {
+            out.entry(native_id).or_default().push(id);
+        }
+
+        // tracks of a cue sheet have `path#NN` ids, their file is the source
+        let rows: Vec<(String, i64)> = sqlm::song::Entity::find()
+            .inner_join(sqlm::item::Entity)
+            .select_only()
+            .column(sqlm::song::Column::SourceId)
+            .column(sqlm::song::Column::Id)
+            .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+            .filter(sqlm::song::Column::SourceId.is_in(chunk.to_vec()))
+            .order_by_asc(sqlm::song::Column::StartOffset)
+            .into_tuple()
+            .all(txn)
+            .await?;
+        for (source_id, id) in rows {
+            out.entry(source_id).or_default().push(id);
+        }
+    }
+    Ok(out)
+}
+
+/// Write playlists as remote mixes, songs are matched by native_id across libraries
+pub async fn write_playlists(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+    playlists: &[(i64, ScannedPlaylist)],
+) -> Result<(), ProviderError> {
+    let entries: Vec<String> = playlists
+        .iter()
+        .flat_map(|(_, p)| p.songs.iter().cloned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let song_ids = playlist_song_ids(txn, provider_id, entries).await?;
+
+    for (library_id, list) in playlists {
+        // a mix holds a song once
+        let mut seen = HashSet::new();
+        let songs: Vec<i64> = list
+            .songs
+            .iter()
+            .filter_map(|n| song_ids.get(n))
+            .flatten()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .collect();
+
+        let remote_id = allocate_items(
+            txn,
+            new_items(
+                provider_id,
+                *library_id,
+                ItemType::Mix,
+                std::iter::once(&list.native_id),
+            ),
+        )
+        .await?
+        .first()
+        .copied()
+        .ok_or(ProviderError::Db(DbErr::RecordNotInserted))?;
+
+        let remote_mix = sqlm::remote_mix::Model {
+            id: remote_id,
+            name: list.name.clone(),
+            description: None,
+            track_count: songs.len() as i32,
+            mix_type: "playlist".to_string(),
+        };
+        let now = Timestamp::now();
+        sync_remote_mixes(txn, vec![remote_mix], &[]).await?;
+        // the file is the truth, in its order
+        sync_remote_mix_song_ids(txn, remote_id, songs).await?;
+        sync_drop_remote_mix_songs_before(txn, remote_id, now).await?;
+    }
+    Ok(())
+}
+
+/// Keep rows of unchanged files alive, so `sync_drop_before` only drops deleted ones
+pub async fn touch_unchanged(
+    txn: &DatabaseTransaction,
+    library_id: i64,
+    song_ids: &[i64],
+    now: Timestamp,
+) -> Result<(), ProviderError> {
+    for chunk in song_ids.chunks(REL_CHUNK) {
+        sqlm::item::Entity::update_many()
+            .col_expr(sqlm::item::Column::LastSyncAt, Expr::value(now))
+            .filter(sqlm::item::Column::Id.is_in(chunk.to_vec()))
+            .exec(txn)
+            .await?;
+        sqlm::rel_song_artist::Entity::update_many()
+            .col_expr(sqlm::rel_song_artist::Column::UpdateAt, Expr::value(now))
+            .filter(sqlm::rel_song_artist::Column::SongId.is_in(chunk.to_vec()))
+            .exec(txn)
+            .await?;
+    }
+
+    let backend = txn.get_database_backend();
+    let song = ItemType::Song as i32;
+    let album = ItemType::Album as i32;
+    let artist = ItemType::Artist as i32;
+
+    // albums still having songs
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"
+        UPDATE item SET last_sync_at = ? WHERE id IN (
+            SELECT song.album_id FROM song
+            INNER JOIN item AS si ON si.id = song.id
+            WHERE si.library_id = ? AND si.type = ? AND si.last_sync_at >= ?
+        )
+        "#,
+        [now.into(), library_id.into(), song.into(), now.into()],
+    ))
+    .await?;
+
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"
+        UPDATE rel_album_artist SET update_at = ? WHERE album_id IN (
+            SELECT id FROM item WHERE library_id = ? AND type = ? AND last_sync_at >= ?
+        )
+        "#,
+        [now.into(), library_id.into(), album.into(), now.into()],
+    ))
+    .await?;
+
+    // artists still referenced
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"
+        UPDATE item SET last_sync_at = ?
+        WHERE library_id = ? AND type = ? AND id IN (
+            SELECT artist_id FROM rel_song_artist WHERE update_at >= ?
+            UNION
+            SELECT artist_id FROM rel_album_artist WHERE update_at >= ?
+        )
+        "#,
+        [
+            now.into(),
+            library_id.into(),
+            artist.into(),
+            now.into(),
+            now.into(),
+        ],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Recount album/artist aggregates, as an incremental scan only sees changed files
+pub async fn refresh_counts(
+    txn: &DatabaseTransaction,
+    provider_id: i64,
+) -> Result<(), ProviderError> {
+    let backend = txn.get_database_backend();
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"
+        UPDATE album SET
+            track_count = (SELECT COUNT(*) FROM song WHERE song.album_id = album.id),
+            disc_count = (SELECT COALESCE(MAX(disc_number), 1) FROM song WHERE song.album_id = album.id),
+            duration = (SELECT COALESCE(SUM(duration), 0) FROM song WHERE song.album_id = album.id)
+        WHERE id IN (SELECT id FROM item WHERE provider_id = ? AND type = ?)
+        "#,
+        [provider_id.into(), (ItemType::Album as i32).into()],
+    ))
+    .await?;
+
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"
+        UPDATE artist SET
+            album_count = (SELECT COUNT(*) FROM rel_album_artist r WHERE r.artist_id = artist.id),
+            music_count = (SELECT COUNT(*) FROM rel_song_artist r WHERE r.artist_id = artist.id)
+        WHERE id IN (SELECT id FROM item WHERE provider_id = ? AND type = ?)
+        "#,
+        [provider_id.into(), (ItemType::Artist as i32).into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Where the audio of a song is, when it is only a part of a file
+pub async fn song_source<C: ConnectionTrait>(
+    db: &C,
+    provider_id: i64,
+    native_id: &str,
+) -> Result<Option<SongSource>, ProviderError> {
+    let row: Option<(Option<String>, Option<i64>, Option<i64>)> = sqlm::song::Entity::find()
+        .inner_join(sqlm::item::Entity)
+        .select_only()
+        .column(sqlm::song::Column::SourceId)
+        .column(sqlm::song::Column::StartOffset)
+        .column(sqlm::song::Column::EndOffset)
+        .filter(sqlm::item::Column::ProviderId.eq(provider_id))
+        .filter(sqlm::item::Column::NativeId.eq(native_id))
+        .filter(sqlm::item::Column::Type.eq(ItemType::Song))
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(match row {
+        Some((Some(source_id), start, end)) => Some(SongSource {
+            source_id,
+            start: start.unwrap_or(0),
+            end,
+        }),
+        _ => None,
+    })
+}
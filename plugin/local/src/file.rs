@@ -0,0 +1,136 @@
+use crate::scan::{folder_images, is_audio};
+use crate::tag::read_image;
+use http_range_header::RangeUnsatisfiableError;
+use qcm_core::{error::ProviderError, http::HeaderMap, model::type_enum::ImageType};
+use reqwest::header;
+use reqwest::Response;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "m4a" | "mp4" | "alac" => "audio/mp4",
+        "aac" => "audio/aac",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "aif" | "aiff" => "audio/aiff",
+        "caf" => "audio/x-caf",
+        "mka" => "audio/x-matroska",
+        "webm" => "audio/webm",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// First range of the Range header, none when not requested
+pub fn requested_range(
+    headers: Option<&HeaderMap>,
+    full: u64,
+) -> Option<Result<(u64, u64), RangeUnsatisfiableError>> {
+    let range = headers
+        .and_then(|h| h.get(header::RANGE))
+        .and_then(|v| v.to_str().ok())?;
+    Some(
+        http_range_header::parse_range_header(range)
+            .and_then(|r| r.validate(full))
+            .and_then(|ranges| {
+                ranges
+                    .first()
+                    .map(|r| (*r.start(), *r.end()))
+                    .ok_or(RangeUnsatisfiableError::Empty)
+            }),
+    )
+}
+
+/// Serve a local file as a http response, honouring the Range header
+pub async fn file_response(
+    path: &Path,
+    headers: Option<&HeaderMap>,
+) -> Result<Response, ProviderError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let full = file.metadata().await?.len();
+
+    let builder = http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type(path))
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let rsp = match requested_range(headers, full) {
+        Some(Ok((start, end))) => {
+            let len = end - start + 1;
+            file.seek(SeekFrom::Start(start)).await?;
+            builder
+                .status(http::StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_LENGTH, len)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, full),
+                )
+                .body(reqwest::Body::wrap_stream(ReaderStream::new(
+                    file.take(len),
+                )))
+        }
+        Some(Err(_)) => builder
+            .status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_LENGTH, 0)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", full))
+            .body(reqwest::Body::from(Vec::new())),
+        None => builder
+            .status(http::StatusCode::OK)
+            .header(header::CONTENT_LENGTH, full)
+            .body(reqwest::Body::wrap_stream(ReaderStream::new(file))),
+    }
+    .map_err(|e| ProviderError::Internal(e.into()))?;
+
+    Ok(Response::from(rsp))
+}
+
+fn bytes_response(content_type: &str, data: Vec<u8>) -> Result<Response, ProviderError> {
+    let rsp = http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .body(reqwest::Body::from(data))
+        .map_err(|e| ProviderError::Internal(e.into()))?;
+    Ok(Response::from(rsp))
+}
+
+/// Serve an image file, or the embedded picture of an audio file
+///
+/// Audio files without one fall back to the folder image next to them
+pub async fn image_response(path: &Path, image_type: ImageType) -> Result<Response, ProviderError> {
+    if !is_audio(path) {
+        return file_response(path, None).await;
+    }
+
+    let image = {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || read_image(&path, image_type))
+            .await
+            .map_err(|e| ProviderError::Internal(e.into()))??
+    };
+    if let Some((media_type, data)) = image {
+        return bytes_response(&media_type, data);
+    }
+
+    let folder_image = path
+        .parent()
+        .map(folder_images)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|(t, _)| *t == image_type)
+        .map(|(_, p)| p)
+        .ok_or(ProviderError::NotFound)?;
+    file_response(&folder_image, None).await
+}
@@ -0,0 +1,10 @@
+mod cue;
+mod db;
+mod file;
+mod playlist;
+pub mod plugin;
+pub mod provider;
+mod scan;
+mod slice;
+mod tag;
+mod watch;
@@ -0,0 +1,127 @@
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Playlist {
+    pub name: Option<String>,
+    /// as written in the file, paths or urls
+    pub entries: Vec<String>,
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| matches!(e.to_ascii_lowercase().as_str(), "m3u" | "m3u8" | "pls"))
+        .unwrap_or(false)
+}
+
+/// `#EXTM3U` playlists, `#PLAYLIST:` as the name
+pub fn parse_m3u(text: &str) -> Playlist {
+    let mut list = Playlist::default();
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            list.name = Some(name.trim().to_string()).filter(|n| !n.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            list.entries.push(line.to_string());
+        }
+    }
+    list
+}
+
+/// `[playlist]` ini with `FileN=`, entries ordered by N
+pub fn parse_pls(text: &str) -> Playlist {
+    let mut files: Vec<(u32, String)> = text
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let n = key.trim().strip_prefix("File")?.parse().ok()?;
+            Some((n, value.trim().to_string()))
+        })
+        .filter(|(_, v)| !v.is_empty())
+        .collect();
+    files.sort_by_key(|(n, _)| *n);
+    Playlist {
+        name: None,
+        entries: files.into_iter().map(|(_, f)| f).collect(),
+    }
+}
+
+pub fn read_playlist(path: &Path) -> std::io::Result<Playlist> {
+    let data = std::fs::read(path)?;
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&data);
+    let text = String::from_utf8_lossy(data);
+    let is_pls = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("pls"))
+        .unwrap_or(false);
+    Ok(match is_pls {
+        true => parse_pls(&text),
+        false => parse_m3u(&text),
+    })
+}
+
+/// Resolve an entry against the playlist dir, without touching the file system
+///
+/// Remote urls are not supported, `file://` ones are percent-decoded
+pub fn resolve_entry(dir: &Path, entry: &str) -> Option<PathBuf> {
+    let entry = match entry.split_once("://") {
+        Some(("file", path)) => percent_decode_str(path).decode_utf8_lossy().to_string(),
+        Some(_) => return None,
+        None => entry.to_string(),
+    };
+    // playlists made on windows
+    let entry = entry.replace('\\', "/");
+
+    let mut out = PathBuf::new();
+    for c in dir.join(entry).components() {
+        match c {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_m3u() {
+        let list = parse_m3u("#EXTM3U\n#PLAYLIST: Road\n#EXTINF:123,A - B\na.flac\n\n../b.mp3\n");
+        assert_eq!(list.name.as_deref(), Some("Road"));
+        assert_eq!(list.entries, vec!["a.flac", "../b.mp3"]);
+    }
+
+    #[test]
+    fn test_parse_pls() {
+        let list =
+            parse_pls("[playlist]\nFile2=b.mp3\nTitle2=B\nFile1=a.flac\nNumberOfEntries=2\n");
+        assert_eq!(list.entries, vec!["a.flac", "b.mp3"]);
+    }
+
+    #[test]
+    fn test_resolve_entry() {
+        let dir = Path::new("/music/lists");
+        assert_eq!(
+            resolve_entry(dir, "../a/./b.flac"),
+            Some(PathBuf::from("/music/a/b.flac"))
+        );
+        assert_eq!(
+            resolve_entry(dir, "/abs/c.mp3"),
+            Some(PathBuf::from("/abs/c.mp3"))
+        );
+        assert_eq!(
+            resolve_entry(dir, "sub\\d.mp3"),
+            Some(PathBuf::from("/music/lists/sub/d.mp3"))
+        );
+        assert_eq!(
+            resolve_entry(dir, "file:///music/My%20Song.flac"),
+            Some(PathBuf::from("/music/My Song.flac"))
+        );
+        assert_eq!(resolve_entry(dir, "http://x/y.mp3"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use super::provider::LocalProvider;
+use qcm_core::model::type_enum::ItemType;
+use qcm_core::provider::{Creator, Provider, ProviderCapabilities, ProviderMeta};
+use qcm_core::{plugin::Plugin, Result};
+use std::sync::Arc;
+
+const SVG: &str = include_str!("../assets/local.svg");
+
+pub struct LocalPlugin {}
+
+impl LocalPlugin {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for LocalPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plugin for LocalPlugin {
+    fn id(&self) -> &str {
+        "qcm.plugin.local"
+    }
+    fn name(&self) -> &str {
+        "local"
+    }
+    fn provider_metas(&self) -> Vec<ProviderMeta> {
+        let creator: Arc<Creator> = Arc::new(|id, name, device_id| -> Result<Arc<dyn Provider>> {
+            let p: Arc<dyn Provider> = Arc::new(LocalProvider::new(id, name, device_id));
+            Ok(p)
+        });
+
+        let mut meta = ProviderMeta::new(
+            LocalProvider::type_name(),
+            &[],
+            Arc::new(SVG.to_string()),
+            creator,
+        );
+        meta.has_server_url = false;
+        let meta = meta.with_capabilities(ProviderCapabilities {
+            favorite: vec![
+                ItemType::Album,
+                ItemType::Artist,
+                ItemType::Mix,
+                ItemType::Song,
+            ],
+            lyrics: true,
+            ..Default::default()
+        });
+        vec![meta]
+    }
+}
@@ -0,0 +1,386 @@
+use crate::db;
+use crate::file::{file_response, image_response};
+use crate::scan::{parse_dirs, scan_library, KnownFile, ScannedPlaylist};
+use crate::slice::slice_response;
+use crate::watch::LibraryWatcher;
+use qcm_core::db::sync::{enabled_libraries, sync_drop_before};
+use qcm_core::db::values::Timestamp;
+use qcm_core::event::{Event as CoreEvent, SyncCommit, SyncPhase};
+use qcm_core::model::{self as sqlm, type_enum::ItemType, util::default_true};
+use qcm_core::provider::{
+    AuthInfo, AuthResult, Context, HasCommonData, Provider, ProviderCommon, ProviderCommonData,
+    ProviderSession,
+};
+use qcm_core::{
+    error::ProviderError, http::HeaderMap, model::type_enum::ImageType, subtitle::Subtitle,
+};
+use reqwest::Response;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocalConfig {
+    #[serde(default)]
+    dirs: Vec<PathBuf>,
+    /// resync on file changes
+    #[serde(default = "default_true")]
+    watch: bool,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            dirs: Vec::new(),
+            watch: true,
+        }
+    }
+}
+
+pub struct LocalProvider {
+    common: ProviderCommonData,
+    config: RwLock<LocalConfig>,
+    watcher: Mutex<Option<LibraryWatcher>>,
+}
+
+impl LocalProvider {
+    pub fn new(id: Option<i64>, name: &str, device_id: &str) -> Self {
+        Self {
+            common: ProviderCommonData::new(id, name, device_id, Self::type_name()),
+            config: RwLock::new(LocalConfig::default()),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    pub fn type_name() -> &'static str {
+        "local"
+    }
+
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        self.config.read().unwrap().dirs.clone()
+    }
+
+    /// native_id is the file path, only allow files under configured dirs
+    fn check_path(&self, native_id: &str) -> Result<PathBuf, ProviderError> {
+        let path = Path::new(native_id)
+            .canonicalize()
+            .map_err(|_| ProviderError::NotFound)?;
+        let allowed = self
+            .dirs()
+            .iter()
+            .filter_map(|d| d.canonicalize().ok())
+            .any(|d| path.starts_with(d));
+        match allowed {
+            true => Ok(path),
+            false => Err(ProviderError::NotFound),
+        }
+    }
+
+    fn commit(&self, ctx: &Context, commit: SyncCommit) {
+        if let Some(id) = self.id() {
+            let _ = ctx.ev_sender.try_send(CoreEvent::SyncCommit { id, commit });
+        }
+    }
+
+    async fn sync_library(
+        &self,
+        ctx: &Context,
+        provider_id: i64,
+        dir: &Path,
+        now: Timestamp,
+    ) -> Result<(i64, Vec<ScannedPlaylist>), ProviderError> {
+        let library_id = {
+            let txn = ctx.db.begin().await?;
+            let id = db::upsert_library(&txn, provider_id, dir).await?;
+            txn.commit().await?;
+            id
+        };
+        let enabled = enabled_libraries(&ctx.db, provider_id)
+            .await?
+            .iter()
+            .any(|l| l.library_id == library_id);
+        if !enabled {
+            log::info!("local library disabled: {}", dir.display());
+            return Ok((library_id, Vec::new()));
+        }
+
+        let known: HashMap<String, KnownFile> = sqlm::item::Entity::find()
+            .select_only()
+            .column(sqlm::item::Column::NativeId)
+            .column(sqlm::item::Column::Id)
+            .column(sqlm::item::Column::Fingerprint)
+            .column(sqlm::item::Column::LastSyncAt)
+            .filter(sqlm::item::Column::LibraryId.eq(library_id))
+            .filter(sqlm::item::Column::Type.eq(ItemType::Song))
+            .into_tuple::<(String, i64, Option<String>, Timestamp)>()
+            .all(&ctx.db)
+            .await?
+            .into_iter()
+            .map(|(native_id, id, fingerprint, last_sync_at)| {
+                (
+                    native_id,
+                    KnownFile {
+                        id,
+                        fingerprint,
+                        last_sync_at,
+                    },
+                )
+            })
+            .collect();
+
+        let mut scan = {
+            let dir = dir.to_path_buf();
+            tokio::task::spawn_blocking(move || scan_library(&dir, &known))
+                .await
+                .map_err(|e| ProviderError::Internal(e.into()))?
+        };
+        log::info!(
+            "local scan {}: {} changed, {} unchanged",
+            dir.display(),
+            scan.songs.len(),
+            scan.unchanged.len()
+        );
+
+        // the scan can take a while, don't write it when cancelled meanwhile
+        ctx.cancel().check()?;
+        let unchanged = std::mem::take(&mut scan.unchanged);
+        let playlists = std::mem::take(&mut scan.playlists);
+        let txn = ctx.db.begin().await?;
+        let counts = db::write_scan(&txn, provider_id, library_id, scan).await?;
+        db::touch_unchanged(&txn, library_id, &unchanged, now).await?;
+        txn.commit().await?;
+
+        self.commit(ctx, SyncCommit::AddArtist(counts.0));
+        self.commit(ctx, SyncCommit::AddAlbum(counts.1));
+        self.commit(ctx, SyncCommit::AddSong(counts.2 + unchanged.len() as i32));
+        Ok((library_id, playlists))
+    }
+
+    /// (re)start the watcher when enabled and not running for current dirs
+    fn ensure_watcher(&self, ctx: &Context) {
+        let Some(id) = self.id() else {
+            return;
+        };
+        let (watch, dirs) = {
+            let config = self.config.read().unwrap();
+            (config.watch, config.dirs.clone())
+        };
+        let mut watcher = self.watcher.lock().unwrap();
+        if !watch {
+            *watcher = None;
+            return;
+        }
+        if watcher.as_ref().map(|w| w.dirs() == dirs.as_slice()) == Some(true) {
+            return;
+        }
+        *watcher = match LibraryWatcher::new(id, dirs, ctx.ev_sender.clone()) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                log::error!("local watcher: {}", e);
+                None
+            }
+        };
+    }
+}
+
+impl HasCommonData for LocalProvider {
+    fn common(&self) -> &ProviderCommonData {
+        &self.common
+    }
+}
+
+// no session for local files
+impl ProviderSession for LocalProvider {
+    fn load_cookie(&self, _data: &str) {}
+    fn save_cookie(&self) -> String {
+        String::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for LocalProvider {
+    fn load(&self, data: &str) {
+        match serde_json::from_str::<LocalConfig>(data) {
+            Ok(config) => *self.config.write().unwrap() = config,
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    fn save(&self) -> String {
+        serde_json::to_string(&*self.config.read().unwrap()).unwrap_or_default()
+    }
+
+    async fn check(&self, _ctx: &Context) -> Result<(), ProviderError> {
+        for dir in self.dirs() {
+            if !dir.is_dir() {
+                log::warn!("music dir not found: {}", dir.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// `info.server_url` holds the music directories, one per line
+    async fn auth(&self, _ctx: &Context, info: &AuthInfo) -> Result<AuthResult, ProviderError> {
+        let dirs = parse_dirs(&info.server_url);
+        if dirs.is_empty() {
+            return Ok(AuthResult::Failed {
+                message: "no music directory".to_string(),
+            });
+        }
+        if let Some(dir) = dirs.iter().find(|d| !d.is_dir()) {
+            return Ok(AuthResult::Failed {
+                message: format!("not a directory: {}", dir.display()),
+            });
+        }
+
+        self.config.write().unwrap().dirs = dirs;
+        self.load_auth_info(&info.server_url, None);
+        // dirs changed, restart on next sync
+        *self.watcher.lock().unwrap() = None;
+        Ok(AuthResult::Ok)
+    }
+
+    async fn sync(&self, ctx: &Context) -> Result<(), ProviderError> {
+        let now = chrono::Utc::now();
+        let Some(provider_id) = self.id() else {
+            return Ok(());
+        };
+
+        let mut playlists = Vec::new();
+        let mut missing = false;
+        self.commit(ctx, SyncCommit::SetPhase(SyncPhase::Songs));
+        for dir in self.dirs() {
+            ctx.cancel().check()?;
+            if !dir.is_dir() {
+                log::warn!("music dir not found: {}", dir.display());
+                self.commit(
+                    ctx,
+                    SyncCommit::Warn(format!("music dir not found: {}", dir.display())),
+                );
+                missing = true;
+                continue;
+            }
+            let (library_id, lists) = self
+                .sync_library(ctx, provider_id, &dir, now.into())
+                .await?;
+            playlists.extend(lists.into_iter().map(|l| (library_id, l)));
+        }
+
+        ctx.cancel().check()?;
+        self.commit(ctx, SyncCommit::SetPhase(SyncPhase::Mixes));
+        let txn = ctx.db.begin().await?;
+        // after all libraries, entries may point to songs in other dirs
+        db::write_playlists(&txn, provider_id, &playlists).await?;
+        // an unreachable dir (e.g. an unmounted drive) is not a deleted one,
+        // keep its library and items until it is scanned again
+        if missing {
+            log::warn!("local sync: music dir missing, skip dropping stale items");
+        } else {
+            sync_drop_before(&txn, provider_id, now).await?;
+        }
+        db::refresh_counts(&txn, provider_id).await?;
+        txn.commit().await?;
+
+        self.ensure_watcher(ctx);
+        Ok(())
+    }
+
+    async fn sync_item(
+        &self,
+        _ctx: &Context,
+        _item: sqlm::item::Model,
+    ) -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    async fn favorite(
+        &self,
+        _ctx: &Context,
+        _item_id: &str,
+        _item_type: ItemType,
+        _value: bool,
+    ) -> Result<(), ProviderError> {
+        // stored only in the local dynamic table
+        Ok(())
+    }
+
+    /// `image_id` is a folder image or an audio file with embedded picture,
+    /// songs without one use their own file
+    async fn image(
+        &self,
+        _ctx: &Context,
+        item_id: &str,
+        image_id: Option<&str>,
+        image_type: ImageType,
+    ) -> Result<Response, ProviderError> {
+        let path = self.check_path(image_id.unwrap_or(item_id))?;
+        image_response(&path, image_type).await
+    }
+
+    /// a cue track is decoded and sliced from its source file
+    async fn audio(
+        &self,
+        ctx: &Context,
+        item_id: &str,
+        headers: Option<HeaderMap>,
+    ) -> Result<Response, ProviderError> {
+        let source = match self.id() {
+            Some(id) => db::song_source(&ctx.db, id, item_id).await?,
+            None => None,
+        };
+        match source {
+            Some(source) => {
+                let path = self.check_path(&source.source_id)?;
+                slice_response(&path, source.start, source.end, headers.as_ref()).await
+            }
+            None => {
+                let path = self.check_path(item_id)?;
+                file_response(&path, headers.as_ref()).await
+            }
+        }
+    }
+
+    /// sibling `.lrc` file
+    async fn subtitle(&self, item_id: &str) -> Result<Subtitle, ProviderError> {
+        let path = self.check_path(item_id)?;
+        lrc_subtitle(&path).await
+    }
+
+    /// a cue track takes the lines of its range in the source file's `.lrc`
+    async fn song_subtitle(&self, ctx: &Context, item_id: &str) -> Result<Subtitle, ProviderError> {
+        let source = match self.id() {
+            Some(id) => db::song_source(&ctx.db, id, item_id).await?,
+            None => None,
+        };
+        match source {
+            Some(source) => {
+                let path = self.check_path(&source.source_id)?;
+                let mut subtitle = lrc_subtitle(&path).await?;
+                subtitle.items = subtitle
+                    .items
+                    .into_iter()
+                    .filter(|i| {
+                        let start = i.start.unwrap_or(0);
+                        start >= source.start && source.end.is_none_or(|end| start < end)
+                    })
+                    .map(|mut i| {
+                        i.start = i.start.map(|t| t - source.start);
+                        i.end = i.end.map(|t| t - source.start);
+                        i
+                    })
+                    .collect();
+                Ok(subtitle)
+            }
+            None => self.subtitle(item_id).await,
+        }
+    }
+}
+
+async fn lrc_subtitle(path: &Path) -> Result<Subtitle, ProviderError> {
+    let lrc = tokio::fs::read_to_string(path.with_extension("lrc"))
+        .await
+        .map_err(|_| ProviderError::NotFound)?;
+    Subtitle::from_lrc(&lrc).map_err(|e| ProviderError::ParseSubtitle(format!("{:?}", e)))
+}
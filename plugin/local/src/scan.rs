@@ -0,0 +1,490 @@
+use crate::cue::{decode_cue, parse_cue, CueFile, CueSheet};
+use crate::playlist::{is_playlist, read_playlist, resolve_entry};
+use crate::tag::{read_tags, ReplayGain, TrackTags};
+use qcm_core::db::values::Timestamp;
+use qcm_core::model::type_enum::ImageType;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+pub const UNKNOWN_ARTIST: &str = "Unknown Artist";
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "m4a", "mp4", "aac", "alac", "ogg", "oga", "opus", "wav", "aif", "aiff", "caf",
+    "mka", "webm",
+];
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// file stems of folder images, in priority order
+const FOLDER_IMAGES: &[(ImageType, &[&str])] = &[
+    (ImageType::Primary, &["cover", "folder", "front", "album"]),
+    (ImageType::Backdrop, &["backdrop", "fanart", "background"]),
+    (ImageType::Banner, &["banner"]),
+    (ImageType::Thumb, &["thumb"]),
+    (ImageType::Logo, &["logo"]),
+];
+
+fn has_extension(path: &Path, exts: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| {
+            let e = e.to_ascii_lowercase();
+            exts.contains(&e.as_str())
+        })
+        .unwrap_or(false)
+}
+
+pub fn is_audio(path: &Path) -> bool {
+    has_extension(path, AUDIO_EXTENSIONS)
+}
+
+pub fn is_cue(path: &Path) -> bool {
+    has_extension(path, &["cue"])
+}
+
+/// Image files like `cover.jpg` or `folder.png` in `dir`
+pub fn folder_images(dir: &Path) -> Vec<(ImageType, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let files: HashMap<String, PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && has_extension(p, IMAGE_EXTENSIONS))
+        .filter_map(|p| {
+            let stem = p.file_stem()?.to_string_lossy().to_lowercase();
+            Some((stem, p))
+        })
+        .collect();
+
+    FOLDER_IMAGES
+        .iter()
+        .filter_map(|(t, stems)| {
+            stems
+                .iter()
+                .find_map(|s| files.get(*s))
+                .map(|p| (*t, p.clone()))
+        })
+        .collect()
+}
+
+/// Song stored as a part of a file, offsets in milliseconds
+#[derive(Clone, Debug)]
+pub struct SongSource {
+    pub source_id: String,
+    pub start: i64,
+    pub end: Option<i64>,
+}
+
+pub struct ScannedSong {
+    /// absolute path, or `path#track` for a cue track
+    pub native_id: String,
+    pub fingerprint: String,
+    pub name: String,
+    pub album_id: String,
+    pub artist_ids: Vec<String>,
+    pub track_number: i32,
+    pub disc_number: i32,
+    pub duration: i64,
+    pub year: Option<i32>,
+    pub genres: Vec<String>,
+    pub added_at: Option<Timestamp>,
+    pub source: Option<SongSource>,
+    pub replay_gain: ReplayGain,
+}
+
+pub struct ScannedAlbum {
+    pub name: String,
+    pub artist_ids: Vec<String>,
+    pub track_count: i32,
+    pub disc_count: i32,
+    pub duration: i64,
+    pub year: Option<i32>,
+    pub added_at: Option<Timestamp>,
+    /// image native_id by type, a folder image or a song file with embedded picture
+    pub images: Vec<(ImageType, String)>,
+}
+
+pub struct ScannedPlaylist {
+    /// absolute path, also used as native_id
+    pub native_id: String,
+    pub name: String,
+    /// song native_ids in order
+    pub songs: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct ScannedArtist {
+    pub name: String,
+    pub album_count: i32,
+    pub music_count: i32,
+}
+
+/// Aggregated result of one library directory
+///
+/// Only changed files are in `songs`, ids of unchanged ones are in `unchanged`
+#[derive(Default)]
+pub struct LibraryScan {
+    pub songs: Vec<ScannedSong>,
+    pub albums: BTreeMap<String, ScannedAlbum>,
+    pub artists: BTreeMap<String, ScannedArtist>,
+    pub unchanged: Vec<i64>,
+    /// always read, they are small
+    pub playlists: Vec<ScannedPlaylist>,
+}
+
+/// A song file already in db
+pub struct KnownFile {
+    pub id: i64,
+    pub fingerprint: Option<String>,
+    pub last_sync_at: Timestamp,
+}
+
+struct FileStat {
+    mtime: Timestamp,
+    size: u64,
+}
+
+impl FileStat {
+    fn new(path: &Path) -> Option<Self> {
+        let meta = path.metadata().ok()?;
+        let modified = meta.modified().ok()?;
+        let millis = modified.duration_since(UNIX_EPOCH).ok()?.as_millis();
+        Some(Self {
+            mtime: Timestamp::from_millis(millis as i64),
+            size: meta.len(),
+        })
+    }
+
+    /// a cue track depends on both the sheet and the audio file
+    fn merge(&self, other: &FileStat) -> FileStat {
+        FileStat {
+            mtime: match self.mtime.as_millis() > other.mtime.as_millis() {
+                true => self.mtime,
+                false => other.mtime,
+            },
+            size: self.size + other.size,
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("{}:{}", self.mtime.as_millis(), self.size)
+    }
+
+    /// modified after last read, or the stored fingerprint differs
+    fn changed(&self, known: &KnownFile) -> bool {
+        known.fingerprint.as_deref() != Some(self.fingerprint().as_str())
+            || self.mtime.as_millis() >= known.last_sync_at.as_millis()
+    }
+}
+
+pub fn album_native_id(album_artist: &str, album: &str) -> String {
+    format!("{}/{}", album_artist, album)
+}
+
+pub fn cue_track_native_id(path: &Path, track_number: i32) -> String {
+    format!("{}#{:02}", path.to_string_lossy(), track_number)
+}
+
+/// file named in the sheet, or a sibling with the same stem, as rips are often converted later
+fn cue_audio_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    if path.is_file() {
+        return Some(path);
+    }
+    AUDIO_EXTENSIONS
+        .iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+/// tags of the nth track, falling back to the tags of the whole file
+fn cue_track_tags(sheet: &CueSheet, file: &CueFile, n: usize, base: &TrackTags) -> TrackTags {
+    let track = &file.tracks[n];
+    let (start, end) = file.offsets(n);
+    let performer = track.performer.as_ref().or(sheet.performer.as_ref());
+    TrackTags {
+        title: track
+            .title
+            .clone()
+            .or_else(|| Some(format!("Track {:02}", track.number))),
+        album: sheet.title.clone().or(base.album.clone()),
+        artists: match performer {
+            Some(p) => vec![p.clone()],
+            None => base.artists.clone(),
+        },
+        album_artists: match &sheet.performer {
+            Some(p) => vec![p.clone()],
+            None => base.album_artists.clone(),
+        },
+        track_number: Some(track.number),
+        disc_number: sheet.disc_number.or(base.disc_number),
+        duration: end.unwrap_or(base.duration).saturating_sub(start).max(0),
+        year: sheet.year.or(base.year),
+        genres: match &sheet.genre {
+            Some(g) => vec![g.clone()],
+            None => base.genres.clone(),
+        },
+        images: base.images.clone(),
+        // the file gain covers the whole rip, only valid as album gain
+        replay_gain: ReplayGain {
+            album_gain: base.replay_gain.album_gain.or(base.replay_gain.track_gain),
+            album_peak: base.replay_gain.album_peak.or(base.replay_gain.track_peak),
+            ..Default::default()
+        },
+    }
+}
+
+fn min_ts(a: Option<Timestamp>, b: Option<Timestamp>) -> Option<Timestamp> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.as_millis() < b.as_millis() { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+impl LibraryScan {
+    fn add(
+        &mut self,
+        path: &Path,
+        native_id: String,
+        stat: &FileStat,
+        tags: TrackTags,
+        source: Option<SongSource>,
+    ) {
+        let name = tags.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        let album = tags.album.clone().unwrap_or_else(|| {
+            path.parent()
+                .and_then(|p| p.file_name())
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or(UNKNOWN_ALBUM.to_string())
+        });
+        let artists = match tags.artists.is_empty() {
+            true => vec![UNKNOWN_ARTIST.to_string()],
+            false => tags.artists.clone(),
+        };
+        let album_artists = match tags.album_artists.is_empty() {
+            true => vec![artists[0].clone()],
+            false => tags.album_artists.clone(),
+        };
+
+        let album_id = album_native_id(&album_artists[0], &album);
+        let added_at = Some(stat.mtime);
+        let disc_number = tags.disc_number.unwrap_or(1);
+
+        let is_new_album = !self.albums.contains_key(&album_id);
+        let entry = self
+            .albums
+            .entry(album_id.clone())
+            .or_insert_with(|| ScannedAlbum {
+                name: album.clone(),
+                artist_ids: album_artists.clone(),
+                track_count: 0,
+                disc_count: 0,
+                duration: 0,
+                year: None,
+                added_at: None,
+                images: path
+                    .parent()
+                    .map(folder_images)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(t, p)| (t, p.to_string_lossy().to_string()))
+                    .collect(),
+            });
+        entry.track_count += 1;
+        entry.disc_count = entry.disc_count.max(disc_number);
+        entry.duration += tags.duration;
+        entry.year = entry.year.or(tags.year);
+        entry.added_at = min_ts(entry.added_at, added_at);
+        for t in &tags.images {
+            if !entry.images.iter().any(|(et, _)| et == t) {
+                entry.images.push((*t, path.to_string_lossy().to_string()));
+            }
+        }
+
+        if is_new_album {
+            for a in &album_artists {
+                let artist = self.artist_entry(a);
+                artist.album_count += 1;
+            }
+        }
+        for a in &artists {
+            let artist = self.artist_entry(a);
+            artist.music_count += 1;
+        }
+
+        self.songs.push(ScannedSong {
+            native_id,
+            fingerprint: stat.fingerprint(),
+            name,
+            album_id,
+            artist_ids: artists,
+            track_number: tags.track_number.unwrap_or(0),
+            disc_number,
+            duration: tags.duration,
+            year: tags.year,
+            genres: tags.genres,
+            added_at,
+            source,
+            replay_gain: tags.replay_gain,
+        });
+    }
+
+    /// Add tracks of a cue sheet, return the audio files it covers
+    fn add_cue(&mut self, cue: &Path, known: &HashMap<String, KnownFile>) -> Vec<PathBuf> {
+        let (Some(cue_stat), Some(dir)) = (FileStat::new(cue), cue.parent()) else {
+            return Vec::new();
+        };
+        let sheet = match std::fs::read(cue) {
+            Ok(data) => parse_cue(&decode_cue(&data)),
+            Err(e) => {
+                log::warn!("read cue failed {}: {}", cue.display(), e);
+                return Vec::new();
+            }
+        };
+
+        let mut covered = Vec::new();
+        for file in &sheet.files {
+            let Some(path) = cue_audio_path(dir, &file.name) else {
+                log::warn!("cue file not found {}: {}", cue.display(), file.name);
+                continue;
+            };
+            let Some(stat) = FileStat::new(&path).map(|s| s.merge(&cue_stat)) else {
+                continue;
+            };
+            covered.push(path.clone());
+
+            // tracks share the files, so all of them are unchanged or none
+            let native_ids: Vec<String> = file
+                .tracks
+                .iter()
+                .map(|t| cue_track_native_id(&path, t.number))
+                .collect();
+            let unchanged: Option<Vec<i64>> = native_ids
+                .iter()
+                .map(|n| known.get(n).filter(|k| !stat.changed(k)).map(|k| k.id))
+                .collect();
+            if let Some(ids) = unchanged {
+                self.unchanged.extend(ids);
+                continue;
+            }
+
+            let base = match read_tags(&path) {
+                Ok(tags) => tags,
+                Err(e) => {
+                    log::warn!("read tags failed {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for (n, native_id) in native_ids.into_iter().enumerate() {
+                let (start, end) = file.offsets(n);
+                let tags = cue_track_tags(&sheet, file, n, &base);
+                let source = SongSource {
+                    source_id: path.to_string_lossy().to_string(),
+                    start,
+                    end,
+                };
+                self.add(&path, native_id, &stat, tags, Some(source));
+            }
+        }
+        covered
+    }
+
+    fn artist_entry(&mut self, name: &str) -> &mut ScannedArtist {
+        self.artists
+            .entry(name.to_string())
+            .or_insert_with(|| ScannedArtist {
+                name: name.to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+/// Walk `root` and read tags of new or changed audio files, blocking
+///
+/// Audio files covered by a cue sheet are split into its tracks,
+/// playlists are read as is
+pub fn scan_library(root: &Path, known: &HashMap<String, KnownFile>) -> LibraryScan {
+    let mut scan = LibraryScan::default();
+    let mut audios = Vec::new();
+    let mut cues = Vec::new();
+    let mut playlists = Vec::new();
+    for entry in WalkDir::new(root).follow_links(true) {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                log::warn!("{}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if is_audio(entry.path()) {
+            audios.push(entry.into_path());
+        } else if is_cue(entry.path()) {
+            cues.push(entry.into_path());
+        } else if is_playlist(entry.path()) {
+            playlists.push(entry.into_path());
+        }
+    }
+
+    let mut covered = HashSet::new();
+    for cue in &cues {
+        covered.extend(scan.add_cue(cue, known));
+    }
+
+    for path in audios.iter().filter(|p| !covered.contains(*p)) {
+        let Some(stat) = FileStat::new(path) else {
+            continue;
+        };
+        let native_id = path.to_string_lossy().to_string();
+        if let Some(k) = known.get(&native_id) {
+            if !stat.changed(k) {
+                scan.unchanged.push(k.id);
+                continue;
+            }
+        }
+        match read_tags(path) {
+            Ok(tags) => scan.add(path, native_id, &stat, tags, None),
+            Err(e) => log::warn!("read tags failed {}: {}", path.display(), e),
+        }
+    }
+
+    for path in &playlists {
+        let (Ok(list), Some(dir)) = (read_playlist(path), path.parent()) else {
+            log::warn!("read playlist failed: {}", path.display());
+            continue;
+        };
+        scan.playlists.push(ScannedPlaylist {
+            native_id: path.to_string_lossy().to_string(),
+            name: list.name.unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            }),
+            songs: list
+                .entries
+                .iter()
+                .filter_map(|e| resolve_entry(dir, e))
+                .map(|p| p.to_string_lossy().to_string())
+                .collect(),
+        });
+    }
+    scan
+}
+
+/// Split `base_url`-like input into directories, one per line or path separator
+pub fn parse_dirs(input: &str) -> Vec<PathBuf> {
+    input
+        .lines()
+        .flat_map(|l| std::env::split_paths(l.trim()).collect::<Vec<_>>())
+        .filter(|p| !p.as_os_str().is_empty())
+        .collect()
+}
@@ -0,0 +1,313 @@
+use crate::file::requested_range;
+use crate::tag::probe;
+use qcm_core::{error::ProviderError, http::HeaderMap};
+use reqwest::header;
+use reqwest::Response;
+use std::io;
+use std::path::Path;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymError;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::units::{Time, TimeBase};
+use tokio::sync::mpsc;
+
+const WAV_HEADER_LEN: u64 = 44;
+
+/// Decoded pcm of a time range in a file, served as wav
+struct WavSlice {
+    channels: u16,
+    rate: u32,
+    /// 16 or 32
+    bits: u16,
+    /// first frame in the source
+    start: u64,
+    frames: u64,
+}
+
+impl WavSlice {
+    fn block_align(&self) -> u64 {
+        self.channels as u64 * self.bits as u64 / 8
+    }
+
+    fn data_len(&self) -> u64 {
+        self.frames * self.block_align()
+    }
+
+    fn len(&self) -> u64 {
+        WAV_HEADER_LEN + self.data_len()
+    }
+
+    /// sizes past 4 GiB are clamped to u32::MAX, players then read to the end
+    fn header(&self) -> Vec<u8> {
+        let data_len = self.data_len().min(u32::MAX as u64) as u32;
+        let riff_len = (36 + data_len as u64).min(u32::MAX as u64) as u32;
+        let block_align = self.block_align() as u16;
+        let mut h = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        h.extend_from_slice(b"RIFF");
+        h.extend_from_slice(&riff_len.to_le_bytes());
+        h.extend_from_slice(b"WAVEfmt ");
+        h.extend_from_slice(&16u32.to_le_bytes());
+        h.extend_from_slice(&1u16.to_le_bytes());
+        h.extend_from_slice(&self.channels.to_le_bytes());
+        h.extend_from_slice(&self.rate.to_le_bytes());
+        h.extend_from_slice(&(self.rate * block_align as u32).to_le_bytes());
+        h.extend_from_slice(&block_align.to_le_bytes());
+        h.extend_from_slice(&self.bits.to_le_bytes());
+        h.extend_from_slice(b"data");
+        h.extend_from_slice(&data_len.to_le_bytes());
+        h
+    }
+}
+
+struct SliceReader {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    slice: WavSlice,
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn open_slice(path: &Path, start: i64, end: Option<i64>) -> io::Result<SliceReader> {
+    let probed = probe(path)?;
+    let format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| invalid("no audio track"))?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+
+    let rate = params
+        .sample_rate
+        .ok_or_else(|| invalid("unknown sample rate"))?;
+    let channels = params
+        .channels
+        .map(|c| c.count() as u16)
+        .ok_or_else(|| invalid("unknown channels"))?;
+    let bits = match params.bits_per_sample {
+        Some(b) if b > 16 => 32,
+        _ => 16,
+    };
+
+    let to_frame = |ms: i64| ms.max(0) as u64 * rate as u64 / 1000;
+    let start = to_frame(start);
+    let end = match (end, params.n_frames) {
+        (Some(end), _) => to_frame(end),
+        (None, Some(n)) => n,
+        (None, None) => return Err(invalid("unknown duration")),
+    };
+
+    let decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(invalid)?;
+
+    Ok(SliceReader {
+        track_id,
+        time_base: params.time_base,
+        format,
+        decoder,
+        slice: WavSlice {
+            channels,
+            rate,
+            bits,
+            start,
+            frames: end.saturating_sub(start),
+        },
+    })
+}
+
+impl SliceReader {
+    fn ts_to_frame(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(tb) => ts * tb.numer as u64 * self.slice.rate as u64 / tb.denom as u64,
+            None => ts,
+        }
+    }
+
+    /// Send bytes `[first, last]` of the wav file, blocking
+    fn run(mut self, first: u64, last: u64, tx: mpsc::Sender<io::Result<Vec<u8>>>) {
+        let header = self.slice.header();
+        if first < WAV_HEADER_LEN {
+            let end = (last + 1).min(WAV_HEADER_LEN) as usize;
+            if tx
+                .blocking_send(Ok(header[first as usize..end].to_vec()))
+                .is_err()
+            {
+                return;
+            }
+        }
+        if last < WAV_HEADER_LEN {
+            return;
+        }
+
+        let block_align = self.slice.block_align();
+        let data_first = first.max(WAV_HEADER_LEN) - WAV_HEADER_LEN;
+        let mut remain = last - WAV_HEADER_LEN - data_first + 1;
+        let mut skip = data_first % block_align;
+        // absolute frame of the next needed sample
+        let mut cursor = self.slice.start + data_first / block_align;
+        let end = self.slice.start + self.slice.frames;
+
+        let rate = self.slice.rate as f64;
+        let seek = SeekTo::Time {
+            time: Time::from(cursor as f64 / rate),
+            track_id: Some(self.track_id),
+        };
+        if let Err(e) = self.format.seek(SeekMode::Accurate, seek) {
+            // from the beginning, skipping until cursor
+            log::warn!("seek failed: {}", e);
+        }
+        self.decoder.reset();
+
+        let mut b16: Option<SampleBuffer<i16>> = None;
+        let mut b32: Option<SampleBuffer<i32>> = None;
+        while remain > 0 && cursor < end {
+            let packet = match self.format.next_packet() {
+                Ok(p) => p,
+                Err(SymError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(_) => break,
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let packet_frame = self.ts_to_frame(packet.ts());
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(d) => d,
+                Err(SymError::DecodeError(e)) => {
+                    log::warn!("decode error: {}", e);
+                    continue;
+                }
+                Err(_) => break,
+            };
+            let frames = decoded.frames() as u64;
+            if frames == 0 || packet_frame + frames <= cursor {
+                continue;
+            }
+
+            if packet_frame >= end {
+                break;
+            }
+
+            let spec: SignalSpec = *decoded.spec();
+            let cap = decoded.capacity() as u64;
+            let from = cursor.saturating_sub(packet_frame);
+            let to = frames.min(end - packet_frame);
+            let channels = self.slice.channels as usize;
+            let range = (from as usize * channels)..(to as usize * channels);
+            let mut bytes: Vec<u8> = match self.slice.bits {
+                16 => {
+                    let b = b16.get_or_insert_with(|| SampleBuffer::new(cap, spec));
+                    b.copy_interleaved_ref(decoded);
+                    b.samples()[range]
+                        .iter()
+                        .flat_map(|s| s.to_le_bytes())
+                        .collect()
+                }
+                _ => {
+                    let b = b32.get_or_insert_with(|| SampleBuffer::new(cap, spec));
+                    b.copy_interleaved_ref(decoded);
+                    b.samples()[range]
+                        .iter()
+                        .flat_map(|s| s.to_le_bytes())
+                        .collect()
+                }
+            };
+            cursor = packet_frame + to;
+
+            if skip > 0 {
+                bytes.drain(..(skip as usize).min(bytes.len()));
+                skip = 0;
+            }
+            bytes.truncate(remain.min(bytes.len() as u64) as usize);
+            remain -= bytes.len() as u64;
+            if tx.blocking_send(Ok(bytes)).is_err() {
+                return;
+            }
+        }
+
+        // keep the promised length when the source ends early
+        if remain > 0 {
+            let _ = tx.blocking_send(Ok(vec![0; remain as usize]));
+        }
+    }
+}
+
+/// Serve `[start, end)` milliseconds of an audio file as wav, honouring the Range header
+pub async fn slice_response(
+    path: &Path,
+    start: i64,
+    end: Option<i64>,
+    headers: Option<&HeaderMap>,
+) -> Result<Response, ProviderError> {
+    let reader = {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || open_slice(&path, start, end))
+            .await
+            .map_err(|e| ProviderError::Internal(e.into()))??
+    };
+    let full = reader.slice.len();
+
+    let builder = http::Response::builder()
+        .header(header::CONTENT_TYPE, "audio/wav")
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let (builder, first, last) = match requested_range(headers, full) {
+        Some(Ok((first, last))) => (
+            builder.status(http::StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, full),
+            ),
+            first,
+            last,
+        ),
+        Some(Err(_)) => {
+            let rsp = builder
+                .status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_LENGTH, 0)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", full))
+                .body(reqwest::Body::from(Vec::new()))
+                .map_err(|e| ProviderError::Internal(e.into()))?;
+            return Ok(Response::from(rsp));
+        }
+        None => (builder.status(http::StatusCode::OK), 0, full - 1),
+    };
+
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || reader.run(first, last, tx));
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    let rsp = builder
+        .header(header::CONTENT_LENGTH, last - first + 1)
+        .body(reqwest::Body::wrap_stream(stream))
+        .map_err(|e| ProviderError::Internal(e.into()))?;
+    Ok(Response::from(rsp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_clamps_long_slice() {
+        let slice = WavSlice {
+            channels: 2,
+            rate: 192000,
+            bits: 32,
+            start: 0,
+            // about 2 hours
+            frames: 192000 * 7200,
+        };
+        let h = slice.header();
+        assert_eq!(h.len() as u64, WAV_HEADER_LEN);
+        assert_eq!(&h[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&h[40..44], &u32::MAX.to_le_bytes());
+    }
+}
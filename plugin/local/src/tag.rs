@@ -0,0 +1,297 @@
+use qcm_core::model::type_enum::ImageType;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+/// gains in dB, peaks in linear amplitude
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// from a tag key, both `REPLAYGAIN_*` and opus `R128_*` ones
+    fn apply(&mut self, key: &str, value: &str) {
+        let key = key.to_ascii_lowercase();
+        // e.g. "com.apple.iTunes:replaygain_track_gain" in mp4
+        let key = key.rsplit(':').next().unwrap_or_default();
+        let (field, value) = match key {
+            "replaygain_track_gain" => (&mut self.track_gain, parse_gain(value)),
+            "replaygain_track_peak" => (&mut self.track_peak, parse_gain(value)),
+            "replaygain_album_gain" => (&mut self.album_gain, parse_gain(value)),
+            "replaygain_album_peak" => (&mut self.album_peak, parse_gain(value)),
+            "r128_track_gain" => (&mut self.track_gain, parse_r128(value)),
+            "r128_album_gain" => (&mut self.album_gain, parse_r128(value)),
+            _ => return,
+        };
+        if field.is_none() {
+            *field = value;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    /// milliseconds
+    pub duration: i64,
+    pub year: Option<i32>,
+    pub genres: Vec<String>,
+    /// types of embedded pictures
+    pub images: Vec<ImageType>,
+    pub replay_gain: ReplayGain,
+}
+
+impl TrackTags {
+    /// fill missing fields from a metadata revision, earlier revisions win
+    fn apply(&mut self, rev: &MetadataRevision) {
+        let mut artists = Vec::new();
+        let mut album_artists = Vec::new();
+        let mut genres = Vec::new();
+
+        for tag in rev.tags() {
+            let value = tag.value.to_string();
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let Some(key) = tag.std_key else {
+                self.replay_gain.apply(&tag.key, value);
+                continue;
+            };
+            match key {
+                StandardTagKey::TrackTitle => {
+                    self.title.get_or_insert_with(|| value.to_string());
+                }
+                StandardTagKey::Album => {
+                    self.album.get_or_insert_with(|| value.to_string());
+                }
+                StandardTagKey::Artist => artists.extend(split_multi(value)),
+                StandardTagKey::AlbumArtist => album_artists.extend(split_multi(value)),
+                StandardTagKey::Genre => genres.extend(split_multi(value)),
+                StandardTagKey::TrackNumber if self.track_number.is_none() => {
+                    self.track_number = parse_index(value);
+                }
+                StandardTagKey::DiscNumber if self.disc_number.is_none() => {
+                    self.disc_number = parse_index(value);
+                }
+                StandardTagKey::ReplayGainTrackGain => {
+                    self.replay_gain.apply("replaygain_track_gain", value)
+                }
+                StandardTagKey::ReplayGainTrackPeak => {
+                    self.replay_gain.apply("replaygain_track_peak", value)
+                }
+                StandardTagKey::ReplayGainAlbumGain => {
+                    self.replay_gain.apply("replaygain_album_gain", value)
+                }
+                StandardTagKey::ReplayGainAlbumPeak => {
+                    self.replay_gain.apply("replaygain_album_peak", value)
+                }
+                StandardTagKey::Date
+                | StandardTagKey::ReleaseDate
+                | StandardTagKey::OriginalDate
+                    if self.year.is_none() =>
+                {
+                    self.year = parse_year(value);
+                }
+                _ => {}
+            }
+        }
+
+        if self.artists.is_empty() {
+            self.artists = artists;
+        }
+        if self.album_artists.is_empty() {
+            self.album_artists = album_artists;
+        }
+        if self.genres.is_empty() {
+            self.genres = genres;
+        }
+
+        for t in rev
+            .visuals()
+            .iter()
+            .filter_map(|v| visual_image_type(v.usage))
+        {
+            if !self.images.contains(&t) {
+                self.images.push(t);
+            }
+        }
+    }
+}
+
+/// untyped pictures are usually the front cover
+fn visual_image_type(usage: Option<StandardVisualKey>) -> Option<ImageType> {
+    match usage {
+        None | Some(StandardVisualKey::FrontCover) => Some(ImageType::Primary),
+        Some(StandardVisualKey::Illustration) => Some(ImageType::Backdrop),
+        Some(StandardVisualKey::FileIcon) | Some(StandardVisualKey::OtherIcon) => {
+            Some(ImageType::Thumb)
+        }
+        Some(StandardVisualKey::BandArtistLogo) | Some(StandardVisualKey::PublisherStudioLogo) => {
+            Some(ImageType::Logo)
+        }
+        _ => None,
+    }
+}
+
+/// multi-value tags are often joined into one string
+fn split_multi(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split([';', '\0'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+}
+
+/// "3" or "3/12"
+fn parse_index(value: &str) -> Option<i32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// "-7.35 dB" or "0.988"
+fn parse_gain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok().filter(|v: &f64| v.is_finite())
+}
+
+/// Q7.8 integer relative to -23 LUFS, replaygain references -18 LUFS
+fn parse_r128(value: &str) -> Option<f64> {
+    let q: i32 = value.trim().parse().ok()?;
+    Some(q as f64 / 256.0 + 5.0)
+}
+
+/// "2001", "2001-05-04" or "2001-05-04T00:00:00"
+pub fn parse_year(value: &str) -> Option<i32> {
+    let year: String = value.chars().take_while(|c| c.is_ascii_digit()).collect();
+    match year.len() {
+        4 => year.parse().ok(),
+        _ => None,
+    }
+}
+
+pub fn probe(path: &Path) -> io::Result<ProbeResult> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// container metadata first, then the ones found while probing (e.g. ID3v2 in front of flac)
+fn for_each_revision(probed: &mut ProbeResult, mut f: impl FnMut(&MetadataRevision)) {
+    if let Some(rev) = probed.format.metadata().current() {
+        f(rev);
+    }
+    if let Some(meta) = probed.metadata.get() {
+        if let Some(rev) = meta.current() {
+            f(rev);
+        }
+    }
+}
+
+pub fn read_tags(path: &Path) -> io::Result<TrackTags> {
+    let mut probed = probe(path)?;
+
+    let mut tags = TrackTags::default();
+    for_each_revision(&mut probed, |rev| tags.apply(rev));
+
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        tags.duration = match (params.n_frames, params.time_base, params.sample_rate) {
+            (Some(n), Some(tb), _) => {
+                let t = tb.calc_time(n);
+                (t.seconds * 1000) as i64 + (t.frac * 1000.0) as i64
+            }
+            (Some(n), None, Some(rate)) if rate > 0 => (n * 1000 / rate as u64) as i64,
+            _ => 0,
+        };
+    }
+
+    Ok(tags)
+}
+
+/// First embedded picture of `image_type`, as (media type, data)
+pub fn read_image(path: &Path, image_type: ImageType) -> io::Result<Option<(String, Vec<u8>)>> {
+    let mut probed = probe(path)?;
+
+    let mut image = None;
+    for_each_revision(&mut probed, |rev| {
+        if image.is_some() {
+            return;
+        }
+        image = rev
+            .visuals()
+            .iter()
+            .find(|v| visual_image_type(v.usage) == Some(image_type))
+            .map(|v| (v.media_type.clone(), v.data.to_vec()));
+    });
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_index() {
+        assert_eq!(parse_index("3"), Some(3));
+        assert_eq!(parse_index("3/12"), Some(3));
+        assert_eq!(parse_index(" 07 / 12"), Some(7));
+        assert_eq!(parse_index("a"), None);
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2001"), Some(2001));
+        assert_eq!(parse_year("2001-05-04"), Some(2001));
+        assert_eq!(parse_year("01"), None);
+    }
+
+    #[test]
+    fn test_replay_gain() {
+        let mut rg = ReplayGain::default();
+        rg.apply("REPLAYGAIN_TRACK_GAIN", "-7.35 dB");
+        rg.apply("com.apple.iTunes:replaygain_track_peak", "0.988");
+        rg.apply("R128_ALBUM_GAIN", "-512");
+        // earlier wins
+        rg.apply("replaygain_track_gain", "1.0 dB");
+        assert_eq!(rg.track_gain, Some(-7.35));
+        assert_eq!(rg.track_peak, Some(0.988));
+        assert_eq!(rg.album_gain, Some(3.0));
+        assert_eq!(rg.album_peak, None);
+    }
+
+    #[test]
+    fn test_split_multi() {
+        let v: Vec<_> = split_multi("a; b;;c\0d").collect();
+        assert_eq!(v, vec!["a", "b", "c", "d"]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::playlist::is_playlist;
+use crate::scan::{is_audio, is_cue};
+use notify::event::{ModifyKind, RemoveKind};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use qcm_core::event::Event as CoreEvent;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Sender};
+
+/// wait for the file system to calm down before resync
+const DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Watch library dirs and emit `ProviderSync` on changes
+pub struct LibraryWatcher {
+    dirs: Vec<PathBuf>,
+    _watcher: RecommendedWatcher,
+    task: tokio::task::JoinHandle<()>,
+}
+
+fn is_media(path: &Path) -> bool {
+    is_audio(path) || is_cue(path) || is_playlist(path)
+}
+
+/// Media files, and dirs as their files are not reported one by one when moved.
+/// Other files like editor swaps or `.part` downloads don't matter
+fn is_relevant(ev: &notify::Event) -> bool {
+    match ev.kind {
+        EventKind::Remove(RemoveKind::Folder) => true,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+            ev.paths.iter().any(|p| is_media(p) || p.is_dir())
+        }
+        EventKind::Modify(_) => ev.paths.iter().any(|p| is_media(p)),
+        _ => false,
+    }
+}
+
+impl LibraryWatcher {
+    pub fn new(
+        provider_id: i64,
+        dirs: Vec<PathBuf>,
+        ev_sender: Sender<CoreEvent>,
+    ) -> notify::Result<Self> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(ev) if is_relevant(&ev) => {
+                    let _ = tx.send(());
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("local watcher: {}", e),
+            })?;
+        for dir in &dirs {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+
+        let task = tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                loop {
+                    match tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                        Ok(Some(_)) => continue,
+                        Ok(None) => return,
+                        Err(_) => break,
+                    }
+                }
+                log::info!("local files changed, sync provider {}", provider_id);
+                let _ = ev_sender
+                    .send(CoreEvent::ProviderSync {
+                        id: provider_id,
+                        oneshot: None,
+                    })
+                    .await;
+            }
+        });
+
+        Ok(Self {
+            dirs,
+            _watcher: watcher,
+            task,
+        })
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
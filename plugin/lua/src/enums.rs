@@ -1,5 +1,5 @@
 use mlua::prelude::*;
-use qcm_core::event::SyncState;
+use qcm_core::event::{SyncPhase, SyncState};
 use qcm_core::model::type_enum::{AlbumType, ItemType};
 use qcm_core::IntoEnumIterator;
 use std::fmt::Display;
@@ -21,6 +21,7 @@ pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
     let t = lua.create_table()?;
     t.set("ItemType", register_enum::<ItemType>(lua)?)?;
     t.set("SyncState", register_enum::<SyncState>(lua)?)?;
+    t.set("SyncPhase", register_enum::<SyncPhase>(lua)?)?;
     t.set("AlbumType", register_enum::<AlbumType>(lua)?)?;
     Ok(t)
 }
@@ -1,5 +1,5 @@
 use super::provider::LuaProvider;
-use qcm_core::provider::{Creator, Provider, ProviderMeta};
+use qcm_core::provider::{Creator, Provider, ProviderCapabilities, ProviderMeta};
 use qcm_core::{plugin::Plugin, Result};
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -14,6 +14,8 @@ struct PluginJson {
     svg_path: PathBuf,
     has_server_url: Option<bool>,
     auth_types: Vec<i32>,
+    /// inferred from the script when missing
+    capabilities: Option<ProviderCapabilities>,
 }
 
 pub struct LuaPlugin {
@@ -57,6 +59,13 @@ impl LuaPlugin {
         svg_content: String,
     ) -> ProviderMeta {
         let script_path = path.join(plugin_json.script_path);
+        let capabilities = match plugin_json.capabilities {
+            Some(c) => c,
+            None => LuaProvider::infer_capabilities(&script_path).unwrap_or_else(|e| {
+                log::warn!("load {} failed: {:?}", plugin_json.name, e);
+                ProviderCapabilities::default()
+            }),
+        };
         let type_name = plugin_json.name.clone();
         let creator: Arc<Creator> =
             Arc::new(move |id, name, device_id| -> Result<Arc<dyn Provider>> {
@@ -73,7 +82,7 @@ impl LuaPlugin {
             creator,
         );
         meta.has_server_url = plugin_json.has_server_url.unwrap_or(true);
-        return meta;
+        return meta.with_capabilities(capabilities);
     }
 }
 
@@ -2,14 +2,14 @@ use crate::enums;
 use crate::error::{create_lua_error_func, FromLuaError};
 use crate::util::to_lua;
 use mlua::prelude::*;
-use qcm_core::db::sync::sync_song_album_ids;
+use qcm_core::db::sync::{sync_remote_mix_song_ids, sync_remote_mixes, sync_song_album_ids};
 use qcm_core::db::values::Timestamp;
 use qcm_core::db::{self, DbChunkOper};
-use qcm_core::event::{SyncCommit, SyncState};
+use qcm_core::event::{SyncCommit, SyncPhase, SyncState};
 use qcm_core::model as sqlm;
 use qcm_core::provider::{
-    AuthResult, HasCommonData, HomeBlock, HomeBlockContent, ProviderCommon, ProviderCommonData,
-    QrInfo,
+    AuthResult, HasCommonData, HomeBlock, HomeBlockContent, PlaybackAction, ProviderCapabilities,
+    ProviderCommon, ProviderCommonData, QrInfo, SearchResult, SyncResult, SyncSince,
 };
 use qcm_core::{
     anyhow,
@@ -17,7 +17,7 @@ use qcm_core::{
     error::ProviderError,
     event::Event as CoreEvent,
     http::{CookieStoreRwLock, HasCookieJar, HeaderMap, HttpClient},
-    model::type_enum::ImageType,
+    model::type_enum::{ImageType, ItemType},
     provider::{AuthInfo, Context, Provider},
     subtitle::Subtitle,
     AnyError, Result,
@@ -61,14 +61,35 @@ struct LuaImpl {
     login: LuaFunction,
     sync: LuaFunction,
     sync_item: LuaFunction,
-    get_queue_next: LuaFunction,
-    favorite: LuaFunction,
+    get_queue_next: Option<LuaFunction>,
+    favorite: Option<LuaFunction>,
     qr: Option<LuaFunction>,
     image: LuaFunction,
     audio: LuaFunction,
-    subtitle: LuaFunction,
+    subtitle: Option<LuaFunction>,
     home_blocks: Option<LuaFunction>,
     home_block_items: Option<LuaFunction>,
+    search: Option<LuaFunction>,
+    create_mix: Option<LuaFunction>,
+    rename_mix: Option<LuaFunction>,
+    delete_mix: Option<LuaFunction>,
+    mix_add_songs: Option<LuaFunction>,
+    mix_remove_songs: Option<LuaFunction>,
+    report_playback: Option<LuaFunction>,
+}
+
+/// `sync` returns a `SyncState` or this table
+#[derive(Debug, Serialize, Deserialize)]
+struct LuaSyncResult {
+    /// false after a delta sync, deletions were reported with `ctx:sync_deleted`
+    #[serde(default = "default_true")]
+    full: bool,
+    #[serde(default)]
+    cursors: std::collections::HashMap<String, String>,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -191,12 +212,8 @@ impl LuaProvider {
                 sync_item: provider_table
                     .get::<LuaFunction>("sync_item")
                     .map_err(|_| anyhow!("sync_item func not found"))?,
-                get_queue_next: provider_table
-                    .get::<LuaFunction>("get_queue_next")
-                    .map_err(|_| anyhow!("get_queue_next func not found"))?,
-                favorite: provider_table
-                    .get::<LuaFunction>("favorite")
-                    .map_err(|_| anyhow!("favorite func not found"))?,
+                get_queue_next: provider_table.get::<LuaFunction>("get_queue_next").ok(),
+                favorite: provider_table.get::<LuaFunction>("favorite").ok(),
                 qr: provider_table.get::<LuaFunction>("qr").ok(),
                 image: provider_table
                     .get::<LuaFunction>("image")
@@ -204,11 +221,16 @@ impl LuaProvider {
                 audio: provider_table
                     .get::<LuaFunction>("audio")
                     .map_err(|_| anyhow!("audio func not found"))?,
-                subtitle: provider_table
-                    .get::<LuaFunction>("subtitle")
-                    .map_err(|_| anyhow!("subtitle func not found"))?,
+                subtitle: provider_table.get::<LuaFunction>("subtitle").ok(),
                 home_blocks: provider_table.get::<LuaFunction>("home_blocks").ok(),
                 home_block_items: provider_table.get::<LuaFunction>("home_block_items").ok(),
+                search: provider_table.get::<LuaFunction>("search").ok(),
+                create_mix: provider_table.get::<LuaFunction>("create_mix").ok(),
+                rename_mix: provider_table.get::<LuaFunction>("rename_mix").ok(),
+                delete_mix: provider_table.get::<LuaFunction>("delete_mix").ok(),
+                mix_add_songs: provider_table.get::<LuaFunction>("mix_add_songs").ok(),
+                mix_remove_songs: provider_table.get::<LuaFunction>("mix_remove_songs").ok(),
+                report_playback: provider_table.get::<LuaFunction>("report_playback").ok(),
             },
             lua,
         };
@@ -222,6 +244,64 @@ impl LuaProvider {
     pub fn client(&self) -> HttpClient {
         return self.inner.client.clone();
     }
+
+    /// Inferred from the optional functions the script provides
+    ///
+    /// The script runs in a bare state, `qcm` is a stub that accepts any call
+    pub fn infer_capabilities(script_path: &Path) -> Result<ProviderCapabilities> {
+        let lua = Lua::new();
+        let package = lua.globals().get::<LuaTable>("package")?;
+        package.set(
+            "path",
+            script_path
+                .parent()
+                .and_then(|p| p.to_str())
+                .map(|p| format!("{}/?.lua", p)),
+        )?;
+        lua.load(
+            r#"
+            local stub = {}
+            setmetatable(stub, {
+                __index = function(t) return t end,
+                __call = function(t) return t end,
+            })
+            qcm = stub
+            "#,
+        )
+        .exec()?;
+
+        let provider_table = lua.load(script_path).into_function()?.call::<LuaTable>(())?;
+        let has = |name: &str| provider_table.get::<LuaFunction>(name).is_ok();
+        let favorite = if has("favorite") {
+            vec![
+                ItemType::Album,
+                ItemType::Artist,
+                ItemType::Mix,
+                ItemType::Song,
+            ]
+        } else {
+            Vec::new()
+        };
+        Ok(ProviderCapabilities {
+            favorite,
+            qr_auth: has("qr"),
+            home: has("home_blocks") && has("home_block_items"),
+            radio_queue: has("get_queue_next"),
+            search: has("search"),
+            // clients offer every playlist action once it is set
+            playlist_write: [
+                "create_mix",
+                "rename_mix",
+                "delete_mix",
+                "mix_add_songs",
+                "mix_remove_songs",
+            ]
+            .iter()
+            .all(|name| has(name)),
+            scrobble: has("report_playback"),
+            lyrics: has("subtitle"),
+        })
+    }
 }
 
 impl HasCookieJar for LuaProvider {
@@ -270,29 +350,51 @@ impl Provider for LuaProvider {
     }
 
     async fn sync(&self, ctx: &Context) -> Result<(), ProviderError> {
+        self.sync_since(ctx, &SyncSince::default()).await?;
+        Ok(())
+    }
+    async fn sync_since(
+        &self,
+        ctx: &Context,
+        since: &SyncSince,
+    ) -> Result<SyncResult, ProviderError> {
         let now = chrono::Utc::now();
 
         let res = self
             .funcs
             .sync
-            .call_async::<LuaValue>(LuaContext(ctx.clone(), self.id()))
-            .await
-            .map_err(ProviderError::from_err)?;
-
+            .call_async::<LuaValue>((
+                LuaContext(ctx.clone(), self.id()),
+                to_lua(&self.lua, since),
+            ))
+            .await;
+        // keep what was written, but don't drop the items not reached yet
+        ctx.cancel().check()?;
+        let res = res.map_err(ProviderError::from_err)?;
+
+        let mut out = LuaSyncResult {
+            full: true,
+            cursors: Default::default(),
+        };
         if res.is_integer() {
             let val = res.as_i32().and_then(|v| SyncState::try_from(v).ok());
             match val {
                 Some(SyncState::NotAuth) => return Err(ProviderError::NotAuth),
+                Some(SyncState::Cancelled) => return Err(ProviderError::Cancelled),
                 _ => {}
             }
+        } else if res.is_table() {
+            out = self.lua.from_value(res).map_err(ProviderError::from_err)?;
         }
 
-        if let Some(id) = self.id() {
+        if let (true, Some(id)) = (out.full, self.id()) {
             let txn = ctx.db.begin().await?;
             db::sync::sync_drop_before(&txn, id, now).await?;
             txn.commit().await?;
         }
-        Ok(())
+        Ok(SyncResult {
+            cursors: out.cursors,
+        })
     }
     async fn sync_item(&self, ctx: &Context, item: sqlm::item::Model) -> Result<(), ProviderError> {
         self.funcs
@@ -309,9 +411,12 @@ impl Provider for LuaProvider {
         native_id: &str,
         current_native_ids: &[String],
     ) -> Result<Vec<sqlm::song::Model>, ProviderError> {
-        let res: LuaValue = self
+        let func = self
             .funcs
             .get_queue_next
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        let res: LuaValue = func
             .call_async((
                 LuaContext(ctx.clone(), self.id()),
                 native_id.to_string(),
@@ -330,17 +435,19 @@ impl Provider for LuaProvider {
         item_type: sqlm::type_enum::ItemType,
         value: bool,
     ) -> Result<(), ProviderError> {
-        let res = self
+        let func = self
             .funcs
             .favorite
-            .call_async::<LuaValue>((
-                LuaContext(ctx.clone(), self.id()),
-                item_id,
-                item_type as i32,
-                value,
-            ))
-            .await
-            .map_err(ProviderError::from_err)?;
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<LuaValue>((
+            LuaContext(ctx.clone(), self.id()),
+            item_id,
+            item_type as i32,
+            value,
+        ))
+        .await
+        .map_err(ProviderError::from_err)?;
         Ok(())
     }
 
@@ -415,9 +522,12 @@ impl Provider for LuaProvider {
         }
     }
     async fn subtitle(&self, item_id: &str) -> Result<Subtitle, ProviderError> {
-        let val = self
+        let func = self
             .funcs
             .subtitle
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        let val = func
             .call_async::<LuaValue>(item_id)
             .await
             .map_err(|e| anyhow!(e))?;
@@ -469,6 +579,140 @@ impl Provider for LuaProvider {
             self.lua.from_value(val).map_err(ProviderError::from_err)?;
         Ok((out.content, out.total))
     }
+
+    async fn search(
+        &self,
+        ctx: &Context,
+        query: &str,
+        types: &[ItemType],
+        page: i32,
+        page_size: i32,
+    ) -> Result<SearchResult, ProviderError> {
+        let func = self
+            .funcs
+            .search
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        let types: Vec<i32> = types.iter().map(|t| *t as i32).collect();
+        let val = func
+            .call_async::<LuaValue>((
+                LuaContext(ctx.clone(), self.id()),
+                query.to_string(),
+                types,
+                page,
+                page_size,
+            ))
+            .await
+            .map_err(ProviderError::from_err)?;
+        self.lua.from_value(val).map_err(ProviderError::from_err)
+    }
+
+    async fn create_mix(&self, ctx: &Context, name: &str) -> Result<String, ProviderError> {
+        let func = self
+            .funcs
+            .create_mix
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<String>((LuaContext(ctx.clone(), self.id()), name.to_string()))
+            .await
+            .map_err(ProviderError::from_err)
+    }
+
+    async fn rename_mix(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        name: &str,
+    ) -> Result<(), ProviderError> {
+        let func = self
+            .funcs
+            .rename_mix
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<()>((
+            LuaContext(ctx.clone(), self.id()),
+            native_id.to_string(),
+            name.to_string(),
+        ))
+        .await
+        .map_err(ProviderError::from_err)
+    }
+
+    async fn delete_mix(&self, ctx: &Context, native_id: &str) -> Result<(), ProviderError> {
+        let func = self
+            .funcs
+            .delete_mix
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<()>((LuaContext(ctx.clone(), self.id()), native_id.to_string()))
+            .await
+            .map_err(ProviderError::from_err)
+    }
+
+    async fn mix_add_songs(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        song_native_ids: &[String],
+    ) -> Result<(), ProviderError> {
+        let func = self
+            .funcs
+            .mix_add_songs
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<()>((
+            LuaContext(ctx.clone(), self.id()),
+            native_id.to_string(),
+            song_native_ids.to_vec(),
+        ))
+        .await
+        .map_err(ProviderError::from_err)
+    }
+
+    async fn mix_remove_songs(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        song_native_ids: &[String],
+    ) -> Result<(), ProviderError> {
+        let func = self
+            .funcs
+            .mix_remove_songs
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        func.call_async::<()>((
+            LuaContext(ctx.clone(), self.id()),
+            native_id.to_string(),
+            song_native_ids.to_vec(),
+        ))
+        .await
+        .map_err(ProviderError::from_err)
+    }
+
+    async fn report_playback(
+        &self,
+        ctx: &Context,
+        native_id: &str,
+        action: PlaybackAction,
+        position: i64,
+        timestamp: Timestamp,
+    ) -> Result<(), ProviderError> {
+        let func = self
+            .funcs
+            .report_playback
+            .as_ref()
+            .ok_or(ProviderError::NotImplemented)?;
+        let action = self.lua.to_value(&action).map_err(ProviderError::from_err)?;
+        func.call_async::<()>((
+            LuaContext(ctx.clone(), self.id()),
+            native_id.to_string(),
+            action,
+            position,
+            timestamp.as_millis(),
+        ))
+        .await
+        .map_err(ProviderError::from_err)
+    }
 }
 
 #[derive(Deserialize)]
@@ -506,6 +750,11 @@ struct RadioQueueInput {
 
 struct LuaContext(Context, /* provider_id */ Option<i64>);
 
+/// Raise in the coroutine, so a cancelled sync stops before its next write
+fn check_cancel(ctx: &Context) -> mlua::Result<()> {
+    ctx.cancel().check().map_err(mlua::Error::external)
+}
+
 impl LuaUserData for LuaContext {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("commit_album", |_, this, count: i32| {
@@ -536,6 +785,63 @@ impl LuaUserData for LuaContext {
             Ok(())
         });
 
+        methods.add_method("set_phase", |_, this, phase: i32| {
+            let phase = SyncPhase::try_from(phase).map_err(mlua::Error::external)?;
+            if let Some(id) = this.1 {
+                let _ = this.0.ev_sender.try_send(CoreEvent::SyncCommit {
+                    id,
+                    commit: SyncCommit::SetPhase(phase),
+                });
+            }
+            Ok(())
+        });
+        methods.add_method("set_total", |_, this, (kind, count): (i32, i32)| {
+            let kind = SyncPhase::try_from(kind).map_err(mlua::Error::external)?;
+            if let Some(id) = this.1 {
+                let _ = this.0.ev_sender.try_send(CoreEvent::SyncCommit {
+                    id,
+                    commit: SyncCommit::SetTotal(kind, count),
+                });
+            }
+            Ok(())
+        });
+        methods.add_method("warn", |_, this, message: String| {
+            if let Some(id) = this.1 {
+                let _ = this.0.ev_sender.try_send(CoreEvent::SyncCommit {
+                    id,
+                    commit: SyncCommit::Warn(message),
+                });
+            }
+            Ok(())
+        });
+        // call after sync_libraries, disabled ones should be skipped
+        methods.add_async_method("enabled_libraries", |lua, this, ()| async move {
+            let provider_id = this.1.ok_or_else(|| mlua::Error::runtime("no provider id"))?;
+            let libraries = db::sync::enabled_libraries(&this.0.db, provider_id)
+                .await
+                .map_err(mlua::Error::external)?;
+            to_lua(&lua, &libraries)
+        });
+        methods.add_async_method(
+            "sync_deleted",
+            |lua, this, (item_type, native_ids): (i32, LuaValue)| async move {
+                let item_type = ItemType::try_from(item_type).map_err(mlua::Error::external)?;
+                let native_ids: Vec<String> = lua.from_value(native_ids)?;
+                let provider_id = this.1.ok_or_else(|| mlua::Error::runtime("no provider id"))?;
+
+                check_cancel(&this.0)?;
+                let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
+                let n = db::sync::sync_delete(&txn, provider_id, item_type, &native_ids)
+                    .await
+                    .map_err(mlua::Error::external)?;
+                txn.commit().await.map_err(mlua::Error::external)?;
+                Ok(n)
+            },
+        );
+        methods.add_method("is_cancelled", |_, this, ()| {
+            Ok(this.0.cancel().is_cancelled())
+        });
+
         methods.add_method("get_playing", |lua, this, ()| {
             match this.1.and_then(qcm_core::global::get_playing) {
                 Some(info) => {
@@ -551,13 +857,19 @@ impl LuaUserData for LuaContext {
         methods.add_async_method("sync_libraries", |lua, this, models: LuaValue| async move {
             let models: Vec<sqlm::library::Model> = lua.from_value(models)?;
 
+            check_cancel(&this.0)?;
+
             let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
             let conflict = [
                 sqlm::library::Column::ProviderId,
                 sqlm::library::Column::NativeId,
             ];
 
-            let exclude = [sqlm::library::Column::LibraryId];
+            let exclude = [
+                sqlm::library::Column::LibraryId,
+                sqlm::library::Column::SyncCursor,
+                sqlm::library::Column::Enabled,
+            ];
 
             let iter = models.into_iter().map(|i| -> sqlm::library::ActiveModel {
                 let id = i.library_id;
@@ -584,6 +896,8 @@ impl LuaUserData for LuaContext {
                 let models: Vec<sqlm::album::Model> = lua.from_value(models)?;
                 let opts: Option<LuaSyncOption> = lua.from_value(lua_syncopt)?;
 
+                check_cancel(&this.0)?;
+
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
                 let conflict = [sqlm::album::Column::Id];
                 let mut exclude = vec![sqlm::album::Column::Id];
@@ -606,6 +920,8 @@ impl LuaUserData for LuaContext {
         methods.add_async_method("sync_artists", |lua, this, models: LuaValue| async move {
             let models: Vec<sqlm::artist::Model> = lua.from_value(models)?;
 
+            check_cancel(&this.0)?;
+
             let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
             let conflict = [sqlm::artist::Column::Id];
             let exclude = [sqlm::artist::Column::Id];
@@ -624,6 +940,8 @@ impl LuaUserData for LuaContext {
         methods.add_async_method("sync_songs", |lua, this, models: LuaValue| async move {
             let models: Vec<sqlm::song::Model> = lua.from_value(models)?;
 
+            check_cancel(&this.0)?;
+
             let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
             let conflict = [sqlm::song::Column::Id];
             let exclude = [sqlm::song::Column::Id];
@@ -645,60 +963,15 @@ impl LuaUserData for LuaContext {
                 let models: Vec<sqlm::remote_mix::Model> = lua.from_value(models)?;
                 let opts: Option<LuaSyncOption> = lua.from_value(lua_syncopt)?;
 
-                let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
-
-                let mut out = Vec::new();
-                {
-                    let conflict = [sqlm::remote_mix::Column::Id];
-                    let mut exclude = vec![sqlm::remote_mix::Column::Id];
-                    if let Some(opts) = opts {
-                        exclude.extend(opts.generate_exclude::<sqlm::remote_mix::Column>());
-                    }
-
-                    let iter = models.clone().into_iter().map(|i| {
-                        let a: sqlm::remote_mix::ActiveModel = i.into();
-                        a
-                    });
+                check_cancel(&this.0)?;
 
-                    out = DbChunkOper::<50>::insert_return_key(&txn, iter, &conflict, &exclude)
-                        .await
-                        .map_err(mlua::Error::external)?;
-                }
-
-                {
-                    let now = Timestamp::now();
-                    let conflict = [sqlm::mix::Column::RemoteId];
-                    let exclude = [
-                        sqlm::mix::Column::Id,
-                        sqlm::mix::Column::SortName,
-                        sqlm::mix::Column::MixType,
-                        sqlm::mix::Column::CreateAt,
-                        sqlm::mix::Column::ContentUpdateAt,
-                    ];
-                    let iter =
-                        out.clone()
-                            .into_iter()
-                            .zip(models.into_iter())
-                            .map(|(remote_id, m)| {
-                                let a = sqlm::mix::ActiveModel {
-                                    name: Set(m.name.clone()),
-                                    remote_id: Set(Some(remote_id)),
-                                    description: Set(m.description.unwrap_or_default().clone()),
-                                    track_count: Set(m.track_count),
-                                    create_at: Set(now),
-                                    update_at: Set(now),
-                                    mix_type: Set(sqlm::type_enum::MixType::Cache),
-                                    id: NotSet,
-                                    sort_name: NotSet,
-                                    content_update_at: NotSet,
-                                };
-                                a
-                            });
-
-                    DbChunkOper::<50>::insert(&txn, iter, &conflict, &exclude)
-                        .await
-                        .map_err(mlua::Error::external)?;
-                }
+                let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
+                let exclude = opts
+                    .map(|o| o.generate_exclude::<sqlm::remote_mix::Column>())
+                    .unwrap_or_default();
+                let out = sync_remote_mixes(&txn, models, &exclude)
+                    .await
+                    .map_err(mlua::Error::external)?;
 
                 txn.commit().await.map_err(mlua::Error::external)?;
                 Ok(out)
@@ -710,6 +983,8 @@ impl LuaUserData for LuaContext {
                 let models: Vec<RadioQueueInput> = lua.from_value(models)?;
                 let provider_id = this.1.ok_or_else(|| mlua::Error::runtime("no provider id"))?;
 
+                check_cancel(&this.0)?;
+
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
 
                 let item_models = models.clone().into_iter().map(|m| sqlm::item::ActiveModel {
@@ -747,6 +1022,8 @@ impl LuaUserData for LuaContext {
         methods.add_async_method("sync_images", |lua, this, models: LuaValue| async move {
             let models: Vec<sqlm::image::Model> = lua.from_value(models)?;
 
+            check_cancel(&this.0)?;
+
             let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
             let conflict = [sqlm::image::Column::ItemId, sqlm::image::Column::ImageType];
             let exclude = [sqlm::image::Column::Id];
@@ -769,6 +1046,8 @@ impl LuaUserData for LuaContext {
                 let models: Vec<sqlm::dynamic::Model> = lua.from_value(models)?;
                 let opts: Option<LuaSyncOption> = lua.from_value(lua_syncopt)?;
 
+                check_cancel(&this.0)?;
+
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
                 let conflict = [sqlm::dynamic::Column::Id];
                 let mut exclude = vec![sqlm::dynamic::Column::Id];
@@ -795,6 +1074,8 @@ impl LuaUserData for LuaContext {
             |lua, this, (library_id, models): (i64, LuaValue)| async move {
                 let models: Vec<(String, String)> = lua.from_value(models)?;
 
+                check_cancel(&this.0)?;
+
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
                 sync_song_album_ids(&txn, library_id, models)
                     .await
@@ -807,47 +1088,10 @@ impl LuaUserData for LuaContext {
         methods.add_async_method(
             "sync_remote_mix_song_ids",
             |_lua, this, (remote_mix_id, song_ids): (i64, Vec<i64>)| async move {
-                let mix_id: i64 = sqlm::mix::Entity::find()
-                    .select_only()
-                    .column(sqlm::mix::Column::Id)
-                    .filter(sqlm::mix::Column::RemoteId.eq(Some(remote_mix_id)))
-                    .into_tuple()
-                    .one(&this.0.db)
-                    .await
-                    .map_err(mlua::Error::external)?
-                    .ok_or(mlua::Error::runtime("no mix entry"))?;
+                check_cancel(&this.0)?;
 
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
-
-                let conflict = [
-                    sqlm::rel_mix_song::Column::MixId,
-                    sqlm::rel_mix_song::Column::SongId,
-                ];
-                let exclude = [sqlm::rel_mix_song::Column::Id];
-
-                let now = Timestamp::now();
-
-                sqlm::mix::Entity::update(sqlm::mix::ActiveModel {
-                    id: Set(mix_id),
-                    content_update_at: Set(now),
-                    ..Default::default()
-                })
-                .exec(&txn)
-                .await
-                .map_err(mlua::Error::external)?;
-
-                let iter = song_ids.into_iter().enumerate().map(|(i, song_id)| {
-                    let a: sqlm::rel_mix_song::ActiveModel = sqlm::rel_mix_song::ActiveModel {
-                        mix_id: Set(mix_id),
-                        song_id: Set(song_id),
-                        order_idx: Set(i as i64),
-                        update_at: Set(now),
-                        id: NotSet,
-                    };
-                    a
-                });
-
-                DbChunkOper::<50>::insert(&txn, iter, &conflict, &exclude)
+                sync_remote_mix_song_ids(&txn, remote_mix_id, song_ids)
                     .await
                     .map_err(mlua::Error::external)?;
 
@@ -859,6 +1103,7 @@ impl LuaUserData for LuaContext {
             "sync_album_artist_ids",
             |lua, this, (models,): (LuaValue,)| async move {
                 let models: Vec<sqlm::rel_album_artist::Model> = lua.from_value(models)?;
+                check_cancel(&this.0)?;
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
 
                 let conflict = [
@@ -884,6 +1129,7 @@ impl LuaUserData for LuaContext {
             "sync_song_artist_ids",
             |lua, this, (models,): (LuaValue,)| async move {
                 let models: Vec<sqlm::rel_song_artist::Model> = lua.from_value(models)?;
+                check_cancel(&this.0)?;
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
 
                 let conflict = [
@@ -921,6 +1167,7 @@ impl LuaUserData for LuaContext {
                 }
 
                 let models: Vec<sqlm::item::Model> = lua.from_value(models)?;
+                check_cancel(&this.0)?;
                 let txn = this.0.db.begin().await.map_err(mlua::Error::external)?;
 
                 let iter = models.into_iter().map(|i| {
@@ -8,6 +8,8 @@ pub fn init() {
         qg::add_plugin(Box::new(JellyfinPlugin::new()));
         use qcm_plugin_lua::plugin::LuaPlugin;
         qg::add_plugin(Box::new(LuaPlugin::new()));
+        use qcm_plugin_local::plugin::LocalPlugin;
+        qg::add_plugin(Box::new(LocalPlugin::new()));
     }
 
     let metas = qg::with_plugins(|plugins| {